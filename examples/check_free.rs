@@ -1,10 +1,10 @@
 use anyhow::Error;
 use dotenv::dotenv;
+use pfc_reservation::chain::parse_mint_result;
 use pfc_reservation::requests::{AssignOwner, ReservationTxResultRequest};
 use reqwest::Client;
 use secp256k1::{All, Secp256k1};
 use std::env;
-use terra_rust_api::client::tx_types::TXResultBlock;
 use terra_rust_api::{PrivateKey, Terra};
 
 async fn assign_owner(
@@ -22,13 +22,21 @@ async fn assign_owner(
         let message = serde_json::to_string(&assign_owner)?;
         let url = format!("{}/mint/assign-owner", server_url);
         log::info!("{}", url);
-        let signature = signing_key.sign(secp, &message)?;
+        let issued_at = chrono::Utc::now().timestamp().to_string();
+        let nonce = chrono::Utc::now().timestamp_nanos().to_string();
+        let payload = format!(
+            "POST /mint/assign-owner:{}:{}:{}",
+            issued_at, nonce, message
+        );
+        let signature = signing_key.sign(secp, &payload)?;
         log::info!("{}\t{}", signature.signature, signature.pub_key.value);
         let response = client
             .post(&url)
             .body(message)
             .header("content-type", "application/json")
             .header("X-Reservation-Signature", signature.signature)
+            .header("X-Reservation-Timestamp", issued_at)
+            .header("X-Reservation-Nonce", nonce)
             .send()
             .await?;
         log::info!("{:?} {}", response.status(), response.text().await?);
@@ -36,72 +44,6 @@ async fn assign_owner(
     Ok(())
 }
 
-fn parse_result(nft_contract: &str, tx: &TXResultBlock, name: &str) -> ReservationTxResultRequest {
-    if let Some(code) = tx.code {
-        ReservationTxResultRequest {
-            wallet_address: None,
-            assigned_on: None,
-            tx: tx.txhash.clone(),
-            token_id: None,
-            success: false,
-            error: Some(format!(
-                "{}/{}-{}",
-                code,
-                tx.codespace.as_ref().unwrap_or(&"".to_string()),
-                tx.raw_log
-            )),
-        }
-    } else if let Some(tx_block) = &tx.tx {
-        for msg in &tx_block.value.msg {
-            let contract = &msg.value.contract;
-            if contract == nft_contract {
-                let exec_msg = &msg.value.execute_msg;
-                if let Some(obj) = exec_msg.as_object() {
-                    if let Some(mint) = obj.get("mint") {
-                        if let Some(mint_obj) = mint.as_object() {
-                            let token_id = mint_obj
-                                .get("token_id")
-                                .map(|f| f.as_str())
-                                .flatten()
-                                .unwrap_or_default();
-                            if token_id == name {
-                                if let Some(owner) =
-                                    mint_obj.get("owner").map(|f| f.as_str()).flatten()
-                                {
-                                    return ReservationTxResultRequest {
-                                        wallet_address: Some(String::from(owner)),
-                                        assigned_on: None,
-                                        tx: tx.txhash.clone(),
-                                        token_id: Some(String::from(token_id)),
-                                        success: true,
-                                        error: None,
-                                    };
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        ReservationTxResultRequest {
-            wallet_address: None,
-            assigned_on: None,
-            tx: tx.txhash.clone(),
-            token_id: None,
-            success: false,
-            error: Some(String::from("Unable to find event")),
-        }
-    } else {
-        ReservationTxResultRequest {
-            wallet_address: None,
-            assigned_on: None,
-            tx: tx.txhash.clone(),
-            token_id: None,
-            success: false,
-            error: Some(format!("Unable to find tx block")),
-        }
-    }
-}
 #[rocket::main]
 async fn main() {
     dotenv().ok();
@@ -133,7 +75,8 @@ async fn main() {
                     for hash in hashes {
                         match terra.tx().get(&hash.0).await {
                             Ok(tx) => {
-                                let reservation_result = parse_result(&nft_contract, &tx, &hash.1);
+                                let reservation_result =
+                                    parse_mint_result(&nft_contract, &tx, &hash.1);
 
                                 log::info!("{:?}", reservation_result);
                                 if reservation_result.success {
@@ -141,7 +84,7 @@ async fn main() {
                                         &c,
                                         &secp,
                                         &signing_key,
-                                        &server_url,
+                                        server_url,
                                         reservation_result,
                                     )
                                     .await
@@ -163,12 +106,10 @@ async fn main() {
                 }
                 Err(e) => {
                     eprintln!("Error: {}", e);
-                    return;
                 }
             },
             Err(e) => {
                 eprintln!("Error: {}", e);
-                return;
             }
         }
     }
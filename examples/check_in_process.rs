@@ -1,10 +1,10 @@
 use anyhow::Error;
 use dotenv::dotenv;
+use pfc_reservation::chain::parse_result;
 use pfc_reservation::requests::ReservationTxResultRequest;
 use reqwest::Client;
 use secp256k1::{All, Secp256k1};
 use std::env;
-use terra_rust_api::client::tx_types::TXResultBlock;
 use terra_rust_api::{PrivateKey, Terra};
 
 async fn update_tx(
@@ -17,73 +17,24 @@ async fn update_tx(
     let message = serde_json::to_string(&status)?;
     let url = format!("{}/mint/tx_result", server_url);
     log::info!("{}", url);
-    let signature = signing_key.sign(secp, &message)?;
+    let issued_at = chrono::Utc::now().timestamp().to_string();
+    let nonce = chrono::Utc::now().timestamp_nanos().to_string();
+    let payload = format!("POST /mint/tx_result:{}:{}:{}", issued_at, nonce, message);
+    let signature = signing_key.sign(secp, &payload)?;
     log::info!("{}\t{}", signature.signature, signature.pub_key.value);
     let response = client
         .post(&url)
         .body(message)
         .header("content-type", "application/json")
         .header("X-Reservation-Signature", signature.signature)
+        .header("X-Reservation-Timestamp", issued_at)
+        .header("X-Reservation-Nonce", nonce)
         .send()
         .await?;
     log::info!("{:?} {}", response.status(), response.text().await?);
     Ok(())
 }
 
-fn parse_result(nft_contract: &str, tx: &TXResultBlock) -> ReservationTxResultRequest {
-    if let Some(code) = tx.code {
-        ReservationTxResultRequest {
-            wallet_address: None,
-            assigned_on: None,
-            tx: tx.txhash.clone(),
-            token_id: None,
-            success: false,
-            error: Some(format!(
-                "{}/{}-{}",
-                code,
-                tx.codespace.as_ref().unwrap_or(&"".to_string()),
-                tx.raw_log
-            )),
-        }
-    } else {
-        let wasm_events = tx.get_events("wasm");
-
-        if let Some(wasm) = wasm_events.first() {
-            let contract = wasm.get_first_value("contract_address").unwrap_or_default();
-            if contract == nft_contract {
-                let owner = wasm.get_first_value("minter").unwrap_or_default();
-                let token_id = wasm.get_first_value("token_id").unwrap_or_default();
-
-                ReservationTxResultRequest {
-                    wallet_address: Some(owner),
-                    assigned_on: Some(tx.timestamp),
-                    tx: tx.txhash.clone(),
-                    token_id: Some(token_id),
-                    success: true,
-                    error: None,
-                }
-            } else {
-                ReservationTxResultRequest {
-                    wallet_address: None,
-                    assigned_on: None,
-                    tx: tx.txhash.clone(),
-                    token_id: None,
-                    success: false,
-                    error: Some(String::from("contract mismatch")),
-                }
-            }
-        } else {
-            ReservationTxResultRequest {
-                wallet_address: None,
-                assigned_on: None,
-                tx: tx.txhash.clone(),
-                token_id: None,
-                success: false,
-                error: Some(String::from("Unable to find event")),
-            }
-        }
-    }
-}
 #[rocket::main]
 async fn main() {
     dotenv().ok();
@@ -133,12 +84,10 @@ async fn main() {
                 }
                 Err(e) => {
                     eprintln!("Error: {}", e);
-                    return;
                 }
             },
             Err(e) => {
                 eprintln!("Error: {}", e);
-                return;
             }
         }
     }
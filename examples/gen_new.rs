@@ -2,7 +2,6 @@ use pfc_reservation::requests::NewNFTRequest;
 use secp256k1::{All, Secp256k1};
 use std::env;
 use terra_rust_api::PrivateKey;
-use terra_rust_wallet::Wallet;
 
 fn main() {
     dotenv::dotenv().ok();
@@ -25,15 +24,19 @@ fn main() {
             animation_url: None,
             youtube_url: None,
         };
-        //   let json = serde_json::to_string(&new_nft).unwrap();
+        let json = serde_json::to_string(&new_nft).unwrap();
         let secp: Secp256k1<All> = Secp256k1::new();
         let signing_key_phrase = env::var("DEBUG_RESERVATION_AUTH")
             .expect("Environment Variable 'DEBUG_RESERVATION_AUTH' Not present");
         let signing_key = PrivateKey::from_words(&secp, &signing_key_phrase).unwrap();
-        let json = r#"random/{"token_uri":"https://www.merriam-webster.com/dictionary/petrify","image":null,"image_data":null,"external_url":null,"description":null,"name":null,"attributes":[{"display_type":null,"trait_type":"gender","value":"male"},{"display_type":null,"trait_type":"name","value":"Jim Morrisson"}],"background_color":null,"animation_url":null,"youtube_url":null}"#;
-        let json = r#"random/{"token_uri":"https://www.merriam-webster.com/dictionary/petrify","image":null,"image_data":null,"external_url":null,"description":null,"name":null,"attributes":[{"display_type":null,"trait_type":"gender","value":"male"},{"display_type":null,"trait_type":"name","value":"Jim Morrisson"}],"background_color":null,"animation_url":null,"youtube_url":null,"current_status":null}"#;
-        let sig = signing_key.sign(&secp, &json).unwrap();
+        let issued_at = chrono::Utc::now().timestamp().to_string();
+        let nonce = chrono::Utc::now().timestamp_nanos().to_string();
+        // see auth::signed_payload
+        let payload = format!("POST /nft/new:{}:{}:{}", issued_at, nonce, json);
+        let sig = signing_key.sign(&secp, &payload).unwrap();
         println!("Message:\n{}", json);
+        println!("X-Reservation-Timestamp:\n{}", issued_at);
+        println!("X-Reservation-Nonce:\n{}", nonce);
         println!("Signature:\n{}", sig.signature);
         println!("Public Key:\n{}", sig.pub_key.value);
     }
@@ -33,7 +33,6 @@ fn main() {
         let reservation_request = NewReservationRequest {
             wallet_address,
             reserved_until: until,
-            signed_tx: None,
         };
         let json =
             serde_json::to_string(&reservation_request).expect("Unable to serialize request");
@@ -42,9 +41,15 @@ fn main() {
             .expect("Environment Variable 'RESERVATION_AUTH' Not present");
 
         let signing_key = PrivateKey::from_words(&secp, &signing_key_phrase).unwrap();
-        let sig = signing_key.sign(&secp, &json).unwrap();
+        let issued_at = Utc::now().timestamp().to_string();
+        let nonce = Utc::now().timestamp_nanos().to_string();
+        // see auth::signed_payload
+        let payload = format!("POST /reservation/new:{}:{}:{}", issued_at, nonce, json);
+        let sig = signing_key.sign(&secp, &payload).unwrap();
 
         println!("Message:\n{}", &json);
+        println!("X-Reservation-Timestamp:\n{}", issued_at);
+        println!("X-Reservation-Nonce:\n{}", nonce);
         println!("Signature:\n{}", sig.signature);
         println!("Public Key:\n{}", sig.pub_key.value);
     }
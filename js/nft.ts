@@ -78,9 +78,19 @@ export interface NFTChangeDynamics {
   price_ceiling: number;
 }
 
+export type NftState =
+  | "available"
+  | "reserved"
+  | "pending_verification"
+  | "submitted"
+  | "errored"
+  | "dead_letter"
+  | "minted";
+
 export interface Reservation {
   wallet_address: string;
   nft_id: string;
+  status: NftState;
   reserved: boolean;
   reserved_until: string | undefined;
   assigned: boolean;
@@ -442,17 +452,14 @@ export async function doReservation(
   });
   if (reserved_nft) {
     const message = JSON.stringify({ nft: reserved_nft });
-    return gen_header(message).then((signature) => {
+    const path = "/mint/" + walletAddress + "/" + reserved_nft;
+    return gen_header("GET", path, message).then((signed_headers) => {
       return fetch(
-        process.env.NEXT_PUBLIC_RESERVATION_SERVER +
-          "/mint/" +
-          walletAddress +
-          "/" +
-          reserved_nft,
+        process.env.NEXT_PUBLIC_RESERVATION_SERVER + path,
         {
           method: "GET",
           headers: {
-            "X-Reservation-Signature": signature,
+            ...signed_headers,
           },
         }
       )
@@ -465,14 +472,14 @@ export async function doReservation(
         });
     });
   } else {
-    return gen_header(message).then((signature) => {
+    return gen_header("POST", "/reservation/new", message).then((signed_headers) => {
       return fetch(
         process.env.NEXT_PUBLIC_RESERVATION_SERVER + "/reservation/new",
         {
           method: "POST",
           headers: {
             "Content-Type": "application/json",
-            "X-Reservation-Signature": signature,
+            ...signed_headers,
           },
           body: message,
         }
@@ -519,12 +526,12 @@ export function nft_set_mint_txhash(
     nft_id: nft_id,
     tx_hash: tx_hash,
   });
-  return gen_header(message).then((signature) => {
+  return gen_header("POST", "/mint/hash", message).then((signed_headers) => {
     return fetch(process.env.NEXT_PUBLIC_RESERVATION_SERVER + "/mint/hash", {
       method: "POST",
       headers: {
         "Content-Type": "application/json",
-        "X-Reservation-Signature": signature,
+        ...signed_headers,
       },
       body: message,
     })
@@ -538,7 +545,12 @@ export function nft_set_mint_txhash(
   });
 }
 
-async function gen_header(message: string): Promise<string> {
+// the signature covers "<METHOD> <path>:<timestamp>:<nonce>:<message>", so it can only be used once, on that route
+async function gen_header(
+  method: string,
+  path: string,
+  message: string
+): Promise<Record<string, string>> {
   const mnemonic = "";
   //  const test_key_sign_phrase = (await getStaticProps()).mnemonic;
   console.log("gen header:" + message + ":");
@@ -551,7 +563,15 @@ async function gen_header(message: string): Promise<string> {
   }
 
   //console.log(Buffer.from(mk.publicKey, "base64"));
-  return mk.sign(Buffer.from(message, "utf-8")).then((buf) => {
-    return buf.toString("base64");
+  const timestamp = Math.floor(Date.now() / 1000).toString();
+  const nonce = Date.now().toString() + Math.random().toString().substring(2);
+  const payload =
+    method + " " + path + ":" + timestamp + ":" + nonce + ":" + message;
+  return mk.sign(Buffer.from(payload, "utf-8")).then((buf) => {
+    return {
+      "X-Reservation-Signature": buf.toString("base64"),
+      "X-Reservation-Timestamp": timestamp,
+      "X-Reservation-Nonce": nonce,
+    };
   });
 }
@@ -1,4 +1,5 @@
 -- This file should undo anything in `up.sql`
+drop table NFT_Reservation;
 drop table wallet_whitelist;
 drop table stage_whitelist;
 
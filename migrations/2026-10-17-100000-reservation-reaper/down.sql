@@ -0,0 +1,5 @@
+drop index nft_reservation_nft;
+
+alter table NFT_Reservation
+    drop column reserved_until,
+    drop column stage;
@@ -0,0 +1,6 @@
+-- record which stage a reservation was drawn from, so the reaper can release the whitelist allocation
+alter table NFT_Reservation
+    add column stage          uuid references stage_whitelist (id) null,
+    add column reserved_until timestamp with time zone null;
+
+create index nft_reservation_nft on NFT_Reservation (nft_reserved);
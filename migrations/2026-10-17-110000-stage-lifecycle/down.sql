@@ -0,0 +1,4 @@
+drop table stage_transition;
+
+alter table stage_whitelist
+    drop column stage_status;
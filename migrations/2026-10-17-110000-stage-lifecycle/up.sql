@@ -0,0 +1,14 @@
+-- the last state the stage lifecycle task saw the stage in
+alter table stage_whitelist
+    add column stage_status varchar(10) not null default 'pending'
+        check (stage_status in ('pending', 'open', 'closed'));
+
+create table stage_transition
+(
+    id              uuid primary key                  DEFAULT gen_random_uuid(),
+    stage           uuid references stage_whitelist (id) not null,
+    from_status     varchar(10)                       not null,
+    to_status       varchar(10)                       not null,
+    revoked_count   int                               default 0,
+    transitioned_on timestamp with time zone          default now()
+);
@@ -0,0 +1,2 @@
+drop index stage_whitelist_code;
+drop index wallet_whitelist_wallet_stage;
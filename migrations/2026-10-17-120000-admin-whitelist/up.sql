@@ -0,0 +1,3 @@
+-- a wallet has a single allocation per stage, so bulk uploads can upsert
+create unique index wallet_whitelist_wallet_stage on wallet_whitelist (wallet_address, stage);
+create unique index stage_whitelist_code on stage_whitelist (code);
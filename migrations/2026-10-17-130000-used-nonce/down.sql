@@ -0,0 +1 @@
+drop table used_nonce;
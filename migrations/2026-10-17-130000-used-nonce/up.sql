@@ -0,0 +1,8 @@
+-- nonces from signed requests. a nonce can only be used once while its timestamp is within the allowed skew,
+-- after which the timestamp check rejects it anyway and the row is pruned
+create table used_nonce (
+    nonce varchar(128) not null primary key,
+    issued_at timestamp with time zone not null,
+    used_at timestamp with time zone not null default now()
+);
+create index used_nonce_issued_at on used_nonce (issued_at);
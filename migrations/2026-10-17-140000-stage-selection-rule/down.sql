@@ -0,0 +1,12 @@
+alter table stage_whitelist
+    add column attribute_type  varchar(25)  null,
+    add column attribute_value varchar(100) null;
+
+-- only single trait rules can be expressed with the old columns
+update stage_whitelist
+set attribute_type  = selection_rule -> 'trait' ->> 'trait_type',
+    attribute_value = selection_rule -> 'trait' ->> 'value'
+where selection_rule ? 'trait';
+
+alter table stage_whitelist
+    drop column selection_rule;
@@ -0,0 +1,18 @@
+-- which NFTs a stage draws from, see SelectionRule. null means every NFT
+alter table stage_whitelist
+    add column selection_rule jsonb null;
+
+update stage_whitelist
+set selection_rule = jsonb_build_object('trait',
+                                        jsonb_build_object('trait_type', attribute_type, 'value', attribute_value))
+where attribute_type is not null
+  and attribute_value is not null;
+
+-- this was special cased in code
+update stage_whitelist
+set selection_rule = '{"names": ["Evan Bagelmeister"]}'
+where trim(code) = 'bagel';
+
+alter table stage_whitelist
+    drop column attribute_type,
+    drop column attribute_value;
@@ -0,0 +1,4 @@
+drop index nft_stage;
+
+alter table NFT
+    drop column stage;
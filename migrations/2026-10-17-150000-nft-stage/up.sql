@@ -0,0 +1,13 @@
+-- the stage an NFT was drawn from, kept once it is minted so whitelist counts can be recomputed
+alter table NFT
+    add column stage uuid references stage_whitelist (id) null;
+
+update NFT n
+set stage = r.stage
+from (select distinct on (nft_reserved) nft_reserved, stage
+      from NFT_Reservation
+      where stage is not null
+      order by nft_reserved, reserved_until desc nulls last) r
+where r.nft_reserved = n.id;
+
+create index nft_stage on NFT (stage);
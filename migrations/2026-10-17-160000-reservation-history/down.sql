@@ -0,0 +1,12 @@
+drop index nft_reservation_wallet;
+
+delete from NFT_Reservation where nft_reserved is null;
+
+alter table NFT_Reservation
+    drop column created_on,
+    drop column submitted_on,
+    drop column txhash,
+    drop column errored_on,
+    drop column tx_error,
+    drop column expired_on,
+    drop column completed_on;
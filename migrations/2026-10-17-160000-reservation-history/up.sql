@@ -0,0 +1,12 @@
+-- NFT_Reservation is the history of a wallet's reservations. each step is timestamped,
+-- and attempts which didn't get an NFT are kept too (nft_reserved is null, tx_error has the reason)
+alter table NFT_Reservation
+    add column created_on   timestamp with time zone not null default now(),
+    add column submitted_on timestamp with time zone null,
+    add column txhash       char(64)                 null,
+    add column errored_on   timestamp with time zone null,
+    add column tx_error     varchar(2000)            null,
+    add column expired_on   timestamp with time zone null,
+    add column completed_on timestamp with time zone null;
+
+create index nft_reservation_wallet on NFT_Reservation (wallet_address, created_on);
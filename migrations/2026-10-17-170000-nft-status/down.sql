@@ -0,0 +1,16 @@
+alter table NFT
+    add column assigned         boolean default false,
+    add column reserved         boolean default false,
+    add column has_submit_error boolean default false,
+    add column in_process       boolean default false;
+
+update NFT
+set assigned         = status = 'minted',
+    reserved         = status in ('reserved', 'submitted', 'errored'),
+    has_submit_error = status = 'errored',
+    in_process       = status in ('submitted', 'errored');
+
+drop index nft_status;
+
+alter table NFT
+    drop column status;
@@ -0,0 +1,22 @@
+-- one lifecycle state per NFT, replacing the assigned/reserved/in_process/has_submit_error flags.
+-- a 'reserved' NFT whose reserved_until has passed is available again, until the reaper clears it
+alter table NFT
+    add column status varchar(10) not null default 'available'
+        check (status in ('available', 'reserved', 'submitted', 'errored', 'minted'));
+
+update NFT
+set status = case
+                 when assigned then 'minted'
+                 when has_submit_error then 'errored'
+                 when in_process then 'submitted'
+                 when reserved then 'reserved'
+                 else 'available'
+    end;
+
+create index nft_status on NFT (status);
+
+alter table NFT
+    drop column assigned,
+    drop column reserved,
+    drop column in_process,
+    drop column has_submit_error;
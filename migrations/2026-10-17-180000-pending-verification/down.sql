@@ -0,0 +1,10 @@
+update NFT
+set status = 'reserved', txhash = null
+where status = 'pending_verification';
+
+alter table NFT
+    drop column verify_by,
+    drop constraint nft_status_check,
+    alter column status type varchar(10),
+    add constraint nft_status_check
+        check (status in ('available', 'reserved', 'submitted', 'errored', 'minted'));
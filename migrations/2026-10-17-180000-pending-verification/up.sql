@@ -0,0 +1,8 @@
+-- a tx hash sent to /mint/hash can wait in 'pending_verification' until it is found on chain,
+-- minting the reserved NFT from the reserved wallet. if it isn't by verify_by, the NFT goes back to 'reserved'
+alter table NFT
+    drop constraint nft_status_check,
+    alter column status type varchar(20),
+    add constraint nft_status_check
+        check (status in ('available', 'reserved', 'pending_verification', 'submitted', 'errored', 'minted')),
+    add column verify_by timestamp with time zone null;
@@ -0,0 +1,11 @@
+drop table NFT_Tx_Error;
+
+update NFT
+set status = 'errored'
+where status = 'dead_letter';
+
+alter table NFT
+    drop column mint_failures,
+    drop constraint nft_status_check,
+    add constraint nft_status_check
+        check (status in ('available', 'reserved', 'pending_verification', 'submitted', 'errored', 'minted'));
@@ -0,0 +1,21 @@
+-- a failed mint is retried, released back to the pool, or set aside in 'dead_letter' for an admin to
+-- requeue or release. mint_failures counts the failures since the NFT was last reserved
+alter table NFT
+    drop constraint nft_status_check,
+    add constraint nft_status_check
+        check (status in ('available', 'reserved', 'pending_verification', 'submitted', 'errored', 'dead_letter',
+                          'minted')),
+    add column mint_failures int not null default 0;
+
+-- every failed mint, and what the retry policy made of it
+create table NFT_Tx_Error
+(
+    id             uuid primary key         DEFAULT gen_random_uuid(),
+    nft_id         uuid references NFT (id) not null,
+    wallet_address char(44)                 null,
+    txhash         char(64)                 null,
+    tx_error       varchar(2000)            null,
+    outcome        varchar(12)              not null,
+    created_on     timestamp with time zone not null default now()
+);
+create index nft_tx_error_nft on NFT_Tx_Error (nft_id, created_on);
@@ -0,0 +1,4 @@
+drop table NFT_Audit;
+
+alter table NFT
+    drop column withdrawn;
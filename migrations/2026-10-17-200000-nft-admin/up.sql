@@ -0,0 +1,18 @@
+-- a withdrawn NFT is pulled from sale, and no stage hands it out until it is restored
+alter table NFT
+    add column withdrawn boolean not null default false;
+
+-- every admin change to an NFT: the fields before and after, and the signature it was made with.
+-- not tied to NFT, so the trail outlives a deleted NFT
+create table NFT_Audit
+(
+    id         uuid primary key         DEFAULT gen_random_uuid(),
+    nft_id     uuid                     not null,
+    action     varchar(10)              not null check (action in ('update', 'withdraw', 'restore', 'delete')),
+    before     json                     null,
+    after      json                     null,
+    signature  varchar(200)             not null,
+    nonce      varchar(128)             null,
+    created_on timestamp with time zone not null default now()
+);
+create index nft_audit_nft on NFT_Audit (nft_id, created_on);
@@ -0,0 +1,3 @@
+drop trigger nft_trait_refresh on NFT;
+drop function nft_trait_refresh();
+drop table NFT_Trait;
@@ -0,0 +1,40 @@
+-- each NFT's attributes, kept up to date as NFTs are added or their metadata changes, so trait counts
+-- don't have to pick apart every NFT's metadata
+create table NFT_Trait
+(
+    nft_id     uuid references NFT (id) on delete cascade not null,
+    trait_type text                                       not null,
+    value      text                                       not null
+);
+create index nft_trait_nft on NFT_Trait (nft_id);
+create index nft_trait_type_value on NFT_Trait (trait_type, value);
+
+create function nft_trait_refresh() returns trigger as
+$$
+begin
+    delete from NFT_Trait where nft_id = new.id;
+    if json_typeof(new.meta_data -> 'attributes') = 'array' then
+        insert into NFT_Trait (nft_id, trait_type, value)
+        select distinct new.id, att ->> 'trait_type', coalesce(att ->> 'value', '')
+        from json_array_elements(new.meta_data -> 'attributes') att
+        where json_typeof(att) = 'object'
+          and att ->> 'trait_type' is not null;
+    end if;
+    return null;
+end;
+$$ language plpgsql;
+
+create trigger nft_trait_refresh
+    after insert or update of meta_data
+    on NFT
+    for each row
+execute procedure nft_trait_refresh();
+
+insert into NFT_Trait (nft_id, trait_type, value)
+select distinct n.id, att ->> 'trait_type', coalesce(att ->> 'value', '')
+from (select id, meta_data -> 'attributes' as attributes
+      from NFT
+      where json_typeof(meta_data -> 'attributes') = 'array') n,
+     json_array_elements(n.attributes) att
+where json_typeof(att) = 'object'
+  and att ->> 'trait_type' is not null;
@@ -0,0 +1,5 @@
+alter table NFT_Reservation
+    drop column selection_seed,
+    drop column candidates_hash,
+    drop column candidate_count,
+    drop column selection_index;
@@ -0,0 +1,6 @@
+-- how each reservation's NFT was picked, so the proof is of the pool at the time rather than the pool now
+alter table NFT_Reservation
+    add column selection_seed  char(64) null,
+    add column candidates_hash char(64) null,
+    add column candidate_count int      null,
+    add column selection_index int      null;
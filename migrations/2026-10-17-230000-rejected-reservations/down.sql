@@ -0,0 +1,4 @@
+drop index nft_reservation_rejected;
+
+alter table NFT_Reservation
+    drop column rejected_count;
@@ -0,0 +1,24 @@
+-- attempts which didn't get an NFT are kept once per wallet and reason, counting the repeats, rather than a row each
+alter table NFT_Reservation
+    add column rejected_count int not null default 1;
+
+update NFT_Reservation r
+set rejected_count = d.attempts,
+    errored_on     = d.last_on
+from (select wallet_address, tx_error, count(*) as attempts, max(errored_on) as last_on
+      from NFT_Reservation
+      where nft_reserved is null
+      group by wallet_address, tx_error) d
+where r.nft_reserved is null
+  and r.wallet_address = d.wallet_address
+  and r.tx_error is not distinct from d.tx_error;
+
+delete
+from NFT_Reservation r using NFT_Reservation newer
+where r.nft_reserved is null
+  and newer.nft_reserved is null
+  and newer.wallet_address = r.wallet_address
+  and newer.tx_error is not distinct from r.tx_error
+  and (newer.created_on, newer.id) > (r.created_on, r.id);
+
+create unique index nft_reservation_rejected on NFT_Reservation (wallet_address, tx_error) where nft_reserved is null;
@@ -1,19 +1,33 @@
+use crate::db::use_nonce;
 use crate::requests::ErrorResponse;
+use crate::{NFTDatabase, ReservationState};
+use chrono::{TimeZone, Utc};
 use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome};
 use rocket::serde::json::Json;
 use rocket::Request;
 use secp256k1::{All, Message, PublicKey, Secp256k1, Signature};
 use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::env;
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
 use terra_rust_api::PrivateKey;
 use thread_local::ThreadLocal;
 
+/// the longest nonce accepted, matching the `used_nonce` column
+const MAX_NONCE_LENGTH: usize = 128;
+
 #[derive(Debug, Clone)]
 pub enum SignatureError {
     MissingHeader,
 }
 
+/// why a request guard refused the request, left in the request's local cache for the 403 catcher
+#[derive(Debug, Default)]
+pub struct Refusal(pub Option<String>);
+
 #[derive(Debug)]
 pub struct SignatureB64 {
     pub signature: String,
@@ -58,6 +72,324 @@ pub fn verify_signature(
     Ok(result?)
 }
 
+/// who a signing key belongs to. each route accepts signatures from a fixed set of roles
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyRole {
+    /// the app/web site, making reservations on behalf of a user
+    User,
+    /// the operator, managing NFTs, stages and whitelists
+    Admin,
+    /// the process watching the chain and reporting mint results
+    Oracle,
+}
+impl KeyRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyRole::User => "user",
+            KeyRole::Admin => "admin",
+            KeyRole::Oracle => "oracle",
+        }
+    }
+}
+impl fmt::Display for KeyRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// public keys (base64) allowed to sign for each role
+pub type VerificationKeys = HashMap<KeyRole, Vec<String>>;
+
+/// the roles a route accepts signatures from
+pub trait KeyScope: Send + Sync + 'static {
+    const ROLES: &'static [KeyRole];
+}
+pub struct UserScope;
+impl KeyScope for UserScope {
+    const ROLES: &'static [KeyRole] = &[KeyRole::User];
+}
+pub struct AdminScope;
+impl KeyScope for AdminScope {
+    const ROLES: &'static [KeyRole] = &[KeyRole::Admin];
+}
+/// chain results can be reported by the oracle, or by hand by the admin
+pub struct OracleScope;
+impl KeyScope for OracleScope {
+    const ROLES: &'static [KeyRole] = &[KeyRole::Oracle, KeyRole::Admin];
+}
+
+/// what is actually signed. the route (`<METHOD> <path>`), timestamp and nonce headers are bound to the body
+/// (or synthesized JSON) so a captured signature can't be replayed later, with a different nonce, or on another route
+pub fn signed_payload(route: &str, issued_at: &str, nonce: &str, message: &str) -> String {
+    format!("{}:{}:{}:{}", route, issued_at, nonce, message)
+}
+
+/// request guard for a `X-Reservation-Signature` which must come from one of the scope's roles.
+/// the request's method and path, `X-Reservation-Timestamp` (unix seconds) and `X-Reservation-Nonce` are signed
+/// along with the message.
+/// the signature is checked against the message with `verify`, once the body is available
+#[derive(Debug)]
+pub struct Signed<S: KeyScope> {
+    pub signature: SignatureB64,
+    pub issued_at: Option<String>,
+    pub nonce: Option<String>,
+    /// `<METHOD> <path>` of the request, without the query string
+    pub route: String,
+    /// `X-Reservation-Public-Key`, when the wallet signed the request itself
+    pub public_key: Option<String>,
+    scope: PhantomData<S>,
+}
+
+impl<S: KeyScope> Signed<S> {
+    /// the roles this signature is accepted from, for error messages
+    pub fn roles() -> String {
+        S::ROLES
+            .iter()
+            .map(|r| r.as_str())
+            .collect::<Vec<&str>>()
+            .join(" or ")
+    }
+
+    /// checks the signature, that the timestamp is within `signature_max_skew` of now,
+    /// and that the nonce hasn't been seen before. the nonce is used up if all is well
+    pub async fn verify(
+        &self,
+        message: &str,
+        state: &ReservationState,
+        conn: &NFTDatabase,
+    ) -> Result<(), (Status, Json<ErrorResponse>)> {
+        let result = self
+            .check(
+                message,
+                &Self::role_keys(state),
+                &Self::roles(),
+                state,
+                conn,
+            )
+            .await;
+        Self::outcome(result, message, state)
+    }
+
+    fn role_keys(state: &ReservationState) -> Vec<String> {
+        S::ROLES
+            .iter()
+            .flat_map(|role| {
+                state
+                    .verification_keys
+                    .get(role)
+                    .cloned()
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<String>>()
+    }
+
+    fn outcome(
+        result: Result<(), (Status, String)>,
+        message: &str,
+        state: &ReservationState,
+    ) -> Result<(), (Status, Json<ErrorResponse>)> {
+        match result {
+            Ok(()) => Ok(()),
+            Err((status, e)) => {
+                if state.debug_mode {
+                    log::warn!("IGNORING SIGNATURES");
+                    Ok(())
+                } else {
+                    log::warn!("Signature Failed ({}) {}", e, message);
+                    Err((
+                        status,
+                        Json(ErrorResponse {
+                            code: status.code,
+                            message: e,
+                            errors: None,
+                        }),
+                    ))
+                }
+            }
+        }
+    }
+
+    async fn check(
+        &self,
+        message: &str,
+        public_keys: &[String],
+        signer: &str,
+        state: &ReservationState,
+        conn: &NFTDatabase,
+    ) -> Result<(), (Status, String)> {
+        let issued_at_header = self.issued_at.as_ref().ok_or((
+            Status::new(403),
+            String::from("Missing X-Reservation-Timestamp"),
+        ))?;
+        let nonce = self.nonce.clone().ok_or((
+            Status::new(403),
+            String::from("Missing X-Reservation-Nonce"),
+        ))?;
+        if nonce.is_empty() || nonce.len() > MAX_NONCE_LENGTH {
+            return Err((
+                Status::new(403),
+                format!("Nonce must be 1-{} characters", MAX_NONCE_LENGTH),
+            ));
+        }
+        let payload = signed_payload(&self.route, issued_at_header, &nonce, message);
+        verify_signature(&payload, &self.signature, public_keys).map_err(|e| {
+            (
+                Status::new(403),
+                format!("{} signature required: {}", signer, e),
+            )
+        })?;
+
+        let issued_at = issued_at_header
+            .parse::<i64>()
+            .ok()
+            .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+            .ok_or((
+                Status::new(403),
+                String::from("X-Reservation-Timestamp should be unix seconds"),
+            ))?;
+        if (Utc::now() - issued_at).num_seconds().abs() > state.signature_max_skew.num_seconds() {
+            return Err((
+                Status::new(403),
+                String::from("Signature timestamp is too old, or in the future"),
+            ));
+        }
+        match conn.run(move |c| use_nonce(c, &nonce, issued_at)).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err((
+                Status::new(409),
+                String::from("Nonce has already been used"),
+            )),
+            Err(e) => Err((Status::new(500), e.to_string())),
+        }
+    }
+}
+
+/// how user routes may be signed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserAuthMode {
+    /// only the app's shared `RESERVATION_AUTH_PUBLIC_KEY`
+    Shared,
+    /// only the wallet's own key, sent in `X-Reservation-Public-Key`
+    Wallet,
+    /// the wallet's key if `X-Reservation-Public-Key` is sent, otherwise the shared key
+    Either,
+}
+
+impl FromStr for UserAuthMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "shared" => Ok(UserAuthMode::Shared),
+            "wallet" => Ok(UserAuthMode::Wallet),
+            "either" => Ok(UserAuthMode::Either),
+            _ => Err(anyhow::anyhow!(
+                "Unknown user auth mode '{}'. expecting shared, wallet or either",
+                s
+            )),
+        }
+    }
+}
+
+/// the terra address a (base64) secp256k1 public key belongs to
+pub fn wallet_address_of(public_key: &str) -> anyhow::Result<String> {
+    let key_bytes = base64::decode(public_key)?;
+    // make sure it is actually a key before deriving an address from it
+    PublicKey::from_slice(&key_bytes)?;
+    terra_rust_api::PublicKey::from_public_key(&key_bytes).account()
+}
+
+impl Signed<UserScope> {
+    /// as `verify`, but the request may also be signed by the wallet it acts for.
+    /// the key in `X-Reservation-Public-Key` has to derive `wallet_address`
+    pub async fn verify_wallet(
+        &self,
+        message: &str,
+        wallet_address: &str,
+        state: &ReservationState,
+        conn: &NFTDatabase,
+    ) -> Result<(), (Status, Json<ErrorResponse>)> {
+        let result = match (&self.public_key, state.user_auth_mode) {
+            (None, UserAuthMode::Wallet) => Err((
+                Status::new(403),
+                String::from("Missing X-Reservation-Public-Key"),
+            )),
+            (Some(_), UserAuthMode::Shared) => Err((
+                Status::new(403),
+                String::from("Wallet signatures are not accepted"),
+            )),
+            (Some(public_key), _) => match wallet_address_of(public_key) {
+                Ok(derived) if derived == wallet_address => {
+                    self.check(
+                        message,
+                        std::slice::from_ref(public_key),
+                        "wallet",
+                        state,
+                        conn,
+                    )
+                    .await
+                }
+                Ok(derived) => Err((
+                    Status::new(403),
+                    format!("Public key belongs to {}, not {}", derived, wallet_address),
+                )),
+                Err(e) => Err((Status::new(403), format!("Invalid public key: {}", e))),
+            },
+            (None, _) => {
+                self.check(
+                    message,
+                    &Self::role_keys(state),
+                    &Self::roles(),
+                    state,
+                    conn,
+                )
+                .await
+            }
+        };
+        Self::outcome(result, message, state)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, S: KeyScope> FromRequest<'r> for Signed<S> {
+    type Error = SignatureError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let issued_at = request
+            .headers()
+            .get_one("X-Reservation-Timestamp")
+            .map(String::from);
+        let nonce = request
+            .headers()
+            .get_one("X-Reservation-Nonce")
+            .map(String::from);
+        let public_key = request
+            .headers()
+            .get_one("X-Reservation-Public-Key")
+            .map(String::from);
+        let route = format!("{} {}", request.method(), request.uri().path());
+        SignatureB64::from_request(request)
+            .await
+            .map(|signature| Signed {
+                signature,
+                issued_at,
+                nonce,
+                route,
+                public_key,
+                scope: PhantomData,
+            })
+            .map_failure(|(status, e)| {
+                request.local_cache(|| {
+                    Refusal(Some(format!(
+                        "Missing X-Reservation-Signature. {} signature required",
+                        Self::roles()
+                    )))
+                });
+                (status, e)
+            })
+    }
+}
+
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for SignatureB64 {
     type Error = SignatureError;
@@ -85,6 +417,7 @@ pub fn is_valid_address(wallet_address: &str) -> Result<(), Json<ErrorResponse>>
         Err(Json(ErrorResponse {
             code: 404,
             message: "Expecting wallet address 'terra....'".into(),
+            errors: None,
         }))
     } else {
         Ok(())
@@ -107,6 +440,7 @@ pub fn generate_signature(
         Err(e) => Err(Json(ErrorResponse {
             code: 500,
             message: e.to_string(),
+            errors: None,
         })),
     }
 }
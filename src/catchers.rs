@@ -1,12 +1,26 @@
+use crate::auth::Refusal;
 use pfc_reservation::requests::ErrorResponse;
 use rocket::serde::json::Json;
-use rocket::Catcher;
+use rocket::{Catcher, Request};
 
 #[catch(500)]
 fn internal_server_error() -> Json<ErrorResponse> {
     Json(ErrorResponse {
         message: "Internal server error".to_string(),
         code: 500,
+        errors: None,
+    })
+}
+#[catch(403)]
+fn forbidden(request: &Request) -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        message: request
+            .local_cache(Refusal::default)
+            .0
+            .clone()
+            .unwrap_or_else(|| "Forbidden".to_string()),
+        code: 403,
+        errors: None,
     })
 }
 #[catch(404)]
@@ -14,6 +28,7 @@ fn not_found() -> Json<ErrorResponse> {
     Json(ErrorResponse {
         message: "Not Found".to_string(),
         code: 404,
+        errors: None,
     })
 }
 #[catch(422)]
@@ -21,9 +36,10 @@ fn malformed() -> Json<ErrorResponse> {
     Json(ErrorResponse {
         message: "Malformed Request".to_string(),
         code: 422,
+        errors: None,
     })
 }
 
 pub fn get_catchers() -> Vec<Catcher> {
-    catchers![internal_server_error, not_found, malformed]
+    catchers![internal_server_error, forbidden, not_found, malformed]
 }
@@ -0,0 +1,324 @@
+//! Reading mint results back from the chain, and sending wallets' signed mint transactions to it.
+//!
+//! The parsing matches what `examples/check_in_process.rs` and `examples/check_free.rs` post to `/mint/tx_result`
+//! and `/mint/assign-owner`, so the in-process watcher and the examples agree on what a transaction means.
+use crate::requests::ReservationTxResultRequest;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use terra_rust_api::client::tx_types::TXResultBlock;
+use terra_rust_api::Terra;
+
+/// fetch a transaction from the LCD. an error usually means it isn't in a block yet
+pub async fn get_tx(lcd: &str, chain: &str, hash: &str) -> anyhow::Result<TXResultBlock> {
+    let terra = Terra::lcd_client_no_tx(lcd, chain).await?;
+    Ok(terra.tx().get(hash).await?)
+}
+
+/// the LCD's answer to a sync broadcast. a `code` means it was turned away before reaching a block
+#[derive(Deserialize, Debug)]
+pub struct BroadcastResult {
+    pub txhash: String,
+    pub code: Option<usize>,
+    #[serde(default)]
+    pub codespace: Option<String>,
+    #[serde(default)]
+    pub raw_log: String,
+}
+
+impl BroadcastResult {
+    /// why the LCD turned the transaction away, recorded like a failure on chain so the retry policy can read it
+    pub fn error(&self) -> Option<String> {
+        self.code
+            .map(|code| code_error(code, self.codespace.as_deref(), &self.raw_log))
+    }
+}
+
+/// broadcast a signed transaction. a `code` in the result means the LCD turned it away
+pub async fn broadcast_tx(lcd: &str, chain: &str, tx: &Value) -> anyhow::Result<BroadcastResult> {
+    let terra = Terra::lcd_client_no_tx(lcd, chain).await?;
+    Ok(terra
+        .post_cmd("/txs", &json!({"tx": tx, "mode": "sync"}))
+        .await?)
+}
+
+/// the `mint` message a wallet sends the contract, carrying the metadata and signature from `/mint/<wallet>/<nft>`
+pub fn signed_mint_msg(attributes: &str, signature: &str) -> Value {
+    json!({"mint": {"attributes": attributes, "signature": signature}})
+}
+
+/// the transaction in a signed packet. wallets hand over either a `core/StdTx`, or just its value
+pub fn decode_signed_tx(packet: &Value) -> Result<Value, String> {
+    let tx = match packet.get("type").and_then(|t| t.as_str()) {
+        Some("core/StdTx") => packet.get("value").cloned().unwrap_or_default(),
+        Some(other) => return Err(format!("unexpected transaction type {}", other)),
+        None => packet.clone(),
+    };
+    if tx
+        .get("signatures")
+        .and_then(|s| s.as_array())
+        .map(|s| s.is_empty())
+        .unwrap_or(true)
+    {
+        return Err(String::from("transaction is not signed"));
+    }
+    Ok(tx)
+}
+
+/// check a signed transaction does nothing but send `wallet`'s `expected` mint message to the NFT contract
+pub fn check_signed_mint(
+    tx: &Value,
+    nft_contract: &str,
+    wallet: &str,
+    expected: &Value,
+) -> Result<(), String> {
+    let msgs = tx
+        .get("msg")
+        .and_then(|m| m.as_array())
+        .cloned()
+        .unwrap_or_default();
+    if msgs.len() != 1 {
+        return Err(format!("expected 1 message, found {}", msgs.len()));
+    }
+    let msg = &msgs[0];
+    if msg.get("type").and_then(|t| t.as_str()) != Some("wasm/MsgExecuteContract") {
+        return Err(String::from("message is not a contract execution"));
+    }
+    let value = msg.get("value").cloned().unwrap_or_default();
+    if value.get("contract").and_then(|c| c.as_str()) != Some(nft_contract) {
+        return Err(String::from("contract mismatch"));
+    }
+    if value.get("sender").and_then(|s| s.as_str()) != Some(wallet) {
+        return Err(String::from("sender is not the reserved wallet"));
+    }
+    if value.get("execute_msg") != Some(expected) {
+        return Err(String::from("mint message does not match the reservation"));
+    }
+    Ok(())
+}
+
+/// a transaction hash is 64 hex digits
+pub fn is_tx_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// check a transaction on chain was sent by `wallet`, and only executes the NFT contract.
+/// used to vouch for a hash handed to `/mint/hash` before the NFT counts as submitted
+pub fn check_tx_sender(nft_contract: &str, wallet: &str, tx: &TXResultBlock) -> Result<(), String> {
+    let msgs = match &tx.tx {
+        Some(tx_block) => &tx_block.value.msg,
+        None => return Err(String::from("Unable to find tx block")),
+    };
+    if msgs.is_empty() {
+        return Err(String::from("transaction has no messages"));
+    }
+    for msg in msgs {
+        if msg.value.contract != nft_contract {
+            return Err(String::from("contract mismatch"));
+        }
+        if msg.value.sender != wallet {
+            return Err(String::from("sender is not the reserved wallet"));
+        }
+    }
+    Ok(())
+}
+
+fn failed(tx: &TXResultBlock, error: String) -> ReservationTxResultRequest {
+    ReservationTxResultRequest {
+        wallet_address: None,
+        assigned_on: None,
+        tx: tx.txhash.clone(),
+        token_id: None,
+        success: false,
+        error: Some(error),
+    }
+}
+
+/// a failed transaction's error, as `<code>/<codespace>-<raw_log>`
+pub fn code_error(code: usize, codespace: Option<&str>, raw_log: &str) -> String {
+    format!("{}/{}-{}", code, codespace.unwrap_or_default(), raw_log)
+}
+
+/// the codespace of an error recorded as `<code>/<codespace>-<raw_log>`
+pub fn error_codespace(error: &str) -> Option<&str> {
+    let (code, rest) = error.split_once('/')?;
+    code.parse::<usize>().ok()?;
+    match rest.split_once('-') {
+        Some((codespace, _)) if !codespace.is_empty() => Some(codespace),
+        _ => None,
+    }
+}
+
+/// what becomes of an NFT whose mint failed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailureOutcome {
+    /// left errored, for the wallet to resubmit
+    Retry,
+    /// set aside in `dead_letter` for an admin to requeue or release
+    DeadLetter,
+    /// released back to the pool
+    Release,
+}
+impl FailureOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureOutcome::Retry => "retry",
+            FailureOutcome::DeadLetter => "dead_letter",
+            FailureOutcome::Release => "release",
+        }
+    }
+}
+
+/// decides what happens to an NFT when its mint transaction fails
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// failures the wallet may resubmit after. the next one dead letters the NFT
+    pub max_retries: i32,
+    /// errors from these codespaces can be retried, others are dead lettered straight away. empty retries any error
+    pub retryable_codespaces: Vec<String>,
+    /// release the NFT back to the pool once it has failed this many times. 0 never does
+    pub release_after: i32,
+    /// minutes an errored NFT is held for the wallet to resubmit, before the reaper releases it
+    pub errored_hold: i32,
+}
+impl RetryPolicy {
+    pub fn is_retryable(&self, error: &str) -> bool {
+        self.retryable_codespaces.is_empty()
+            || error_codespace(error)
+                .map(|codespace| self.retryable_codespaces.iter().any(|c| c == codespace))
+                .unwrap_or(false)
+    }
+
+    /// the outcome of a failure with `error`, the NFT's `failures`th
+    pub fn outcome(&self, failures: i32, error: &str) -> FailureOutcome {
+        if !self.is_retryable(error) {
+            FailureOutcome::DeadLetter
+        } else if self.release_after > 0 && failures >= self.release_after {
+            FailureOutcome::Release
+        } else if failures > self.max_retries {
+            FailureOutcome::DeadLetter
+        } else {
+            FailureOutcome::Retry
+        }
+    }
+}
+
+/// the outcome of a mint sent by the wallet, from the contract's `wasm` event
+pub fn parse_result(nft_contract: &str, tx: &TXResultBlock) -> ReservationTxResultRequest {
+    if let Some(code) = tx.code {
+        return failed(tx, code_error(code, tx.codespace.as_deref(), &tx.raw_log));
+    }
+    match tx.get_events("wasm").first() {
+        Some(wasm) => {
+            let contract = wasm.get_first_value("contract_address").unwrap_or_default();
+            if contract == nft_contract {
+                ReservationTxResultRequest {
+                    wallet_address: Some(wasm.get_first_value("minter").unwrap_or_default()),
+                    assigned_on: Some(tx.timestamp),
+                    tx: tx.txhash.clone(),
+                    token_id: Some(wasm.get_first_value("token_id").unwrap_or_default()),
+                    success: true,
+                    error: None,
+                }
+            } else {
+                failed(tx, String::from("contract mismatch"))
+            }
+        }
+        None => failed(tx, String::from("Unable to find event")),
+    }
+}
+
+/// the outcome of minting the NFT called `name` in a mint run, from the contract's `mint` message.
+/// a mint run transaction can carry several mints
+pub fn parse_mint_result(
+    nft_contract: &str,
+    tx: &TXResultBlock,
+    name: &str,
+) -> ReservationTxResultRequest {
+    if let Some(code) = tx.code {
+        return failed(tx, code_error(code, tx.codespace.as_deref(), &tx.raw_log));
+    }
+    let tx_block = match &tx.tx {
+        Some(tx_block) => tx_block,
+        None => return failed(tx, String::from("Unable to find tx block")),
+    };
+    for msg in tx_block
+        .value
+        .msg
+        .iter()
+        .filter(|msg| msg.value.contract == nft_contract)
+    {
+        if let Some(mint) = msg.value.execute_msg.get("mint") {
+            let token_id = mint
+                .get("token_id")
+                .and_then(|f| f.as_str())
+                .unwrap_or_default();
+            if token_id == name {
+                if let Some(owner) = mint.get("owner").and_then(|f| f.as_str()) {
+                    return ReservationTxResultRequest {
+                        wallet_address: Some(String::from(owner)),
+                        assigned_on: Some(tx.timestamp),
+                        tx: tx.txhash.clone(),
+                        token_id: Some(String::from(token_id)),
+                        success: true,
+                        error: None,
+                    };
+                }
+            }
+        }
+    }
+    failed(tx, String::from("Unable to find event"))
+}
+
+/// when to look for a transaction again. a hash is checked straight away, then after
+/// `base`, 2×`base`, 4×`base`.. up to `max` each time it isn't found
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    /// failed attempts, and when the next one is due
+    waiting: HashMap<String, (u32, Instant)>,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Backoff {
+            base,
+            max,
+            waiting: HashMap::new(),
+        }
+    }
+
+    pub fn is_due(&self, hash: &str, now: Instant) -> bool {
+        match self.waiting.get(hash) {
+            Some((_, next)) => *next <= now,
+            None => true,
+        }
+    }
+
+    /// note a failed look up, returning how long until the next
+    pub fn failed(&mut self, hash: &str, now: Instant) -> Duration {
+        let attempts = self.waiting.get(hash).map(|w| w.0).unwrap_or(0);
+        let delay = self
+            .base
+            .checked_mul(1 << attempts.min(16))
+            .unwrap_or(self.max)
+            .min(self.max);
+        self.waiting
+            .insert(String::from(hash), (attempts + 1, now + delay));
+        delay
+    }
+
+    pub fn succeeded(&mut self, hash: &str) {
+        self.waiting.remove(hash);
+    }
+
+    /// forget hashes which are no longer being watched
+    pub fn retain(&mut self, hashes: &[String]) {
+        self.waiting.retain(|hash, _| hashes.contains(hash));
+    }
+
+    pub fn attempts(&self, hash: &str) -> u32 {
+        self.waiting.get(hash).map(|w| w.0).unwrap_or(0)
+    }
+}
@@ -1,24 +1,68 @@
+use crate::chain::{FailureOutcome, RetryPolicy};
 use crate::requests::{ErrorResponse, NFTTallyStat, Reservation};
 use chrono::{DateTime, Utc};
-use postgres::{Client, Error, Row, Statement};
+use postgres::types::ToSql;
+use postgres::{Client, Error, GenericClient, Row, Statement, Transaction};
 use rocket::http::Status;
 use rocket::serde::json::Json;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use std::collections::HashMap;
 use std::ops::Add;
+use std::str::FromStr;
 
-use crate::models::{NftFull, Stage, NFT};
+use crate::models::{
+    AdminSignature, NftFull, NftPage, SignedTxToBroadcast, Stage, StageTransition,
+    TxPendingVerification, WalletStageAllocation, WhitelistCorrection, NFT,
+};
 use crate::requests::Metadata;
-use crate::requests::{MintReservation, OpenStageWallet};
+use crate::requests::{
+    ErroredNft, FieldError, MintReservation, NFTTallyResponse, NewNFTRequest, NewStageRequest,
+    NftAuditEntry, NftImportResponse, NftImportRow, NftListQuery, NftState, OpenStageWallet,
+    ReservationHistory, ReservationStatus, ReservationTxResultRequest, SelectionProof,
+    SelectionRule, StageEligibility, StageState, TraitStatsResponse, TraitTypeStat, TraitValueStat,
+    TxErrorRecord, UpdateNFTRequest, UpdateStageRequest, WalletAllocationRequest,
+    WalletEligibility, WhitelistTallyStat,
+};
+use crate::selection::{salt_hash, to_hex, Pick, SelectionOrder};
 use uuid::Uuid;
 
+/// the most history entries returned for a wallet
+const RESERVATION_HISTORY_LIMIT: i64 = 200;
+
+/// the NFT's state as it stands now. see `NftState::at`
+const NFT_STATE: &str =
+    "(case when status = 'reserved' and reserved_until < now() then 'available' else status end)";
+
+/// SQL condition that the NFT, as it stands now, may move to `to`
+fn can_become(to: NftState) -> String {
+    format!("{} in ({})", NFT_STATE, state_list(NftState::sources(to)))
+}
+
+fn state_list(states: &[NftState]) -> String {
+    states
+        .iter()
+        .map(|s| format!("'{}'", s))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn nft_state(status: &str, reserved_until: Option<DateTime<Utc>>) -> NftState {
+    NftState::from_str(status)
+        .unwrap_or(NftState::Errored)
+        .at(reserved_until, Utc::now())
+}
+
 // examine available NFTs and 'reserve' one
-pub fn get_reservation_count(
-    conn: &mut Client,
+pub fn get_reservation_count<C: GenericClient>(
+    conn: &mut C,
     wallet_address: &str,
 ) -> Result<usize, (Status, Json<ErrorResponse>)> {
     match conn.query(
-        "Select count(*) from NFT where (reserved_to_wallet_address=$1 and reserved=true and (in_process or reserved_until > now())) or assigned_to_wallet_address=$2",
+        format!(
+            "Select count(*) from NFT where (reserved_to_wallet_address=$1 and {} in ({})) or assigned_to_wallet_address=$2",
+            NFT_STATE,
+            state_list(NftState::HELD)
+        )
+        .as_str(),
         &[&String::from(wallet_address),&String::from(wallet_address)],
     ) {
         Ok(reservation_count) => {
@@ -32,6 +76,7 @@ pub fn get_reservation_count(
                     Json(ErrorResponse {
                         code: 444,
                         message: "No NFTs available for reservation at this time".into(),
+                        errors: None,
                     }),
                 ))
             }
@@ -43,6 +88,7 @@ pub fn get_reservation_count(
             Json(ErrorResponse {
                 code: 500,
                 message: db_err.to_string(),
+                errors: None,
             }),
         ))},
     }
@@ -54,41 +100,43 @@ pub fn get_reservations_for_wallet(
     wallet_address: &str,
 ) -> (Status, Result<Json<Vec<Reservation>>, Json<ErrorResponse>>) {
     match conn.query(
-        r#"
-        Select  reserved_to_wallet_address, id, reserved_until, reserved,  assigned,  assigned_on, has_submit_error, in_process, txhash, tx_error, tx_retry_count,token_id
+        format!(r#"
+        Select  reserved_to_wallet_address, id, reserved_until, status, assigned_on, txhash, tx_error, tx_retry_count,token_id
         from NFT
-        where (reserved_to_wallet_address=$1 and ((reserved=true and reserved_until > now()) or in_process=true) ) or assigned_to_wallet_address=$2"#,
+        where (reserved_to_wallet_address=$1 and {} in ({})) or assigned_to_wallet_address=$2"#, NFT_STATE, state_list(NftState::HELD)).as_str(),
         &[&String::from(wallet_address),&String::from(wallet_address)],
     ) {
         Ok(reservation_rows) => {
             let reservations = reservation_rows.iter().map(|r| {
-                let mut reserved:bool = r.get(3);
+                let status = nft_state(r.get(3), r.get(2));
+                let mut reserved = NftState::HELD.contains(&status);
                 let mut reserved_until :Option<DateTime<chrono::offset::Utc>>= r.get(2);
-                let wallet_return:String = r.get(0);
-                if wallet_address != wallet_return {
+                let wallet_return:Option<String> = r.get(0);
+                if Some(wallet_address) != wallet_return.as_deref() {
                     reserved=false;
                     reserved_until= None
                 }
-                let txhash:Option<String> = r.get(8);
-                let tx_error:Option<String> = r.get(9);
-                let assigned:bool = r.get(4);
+                let txhash:Option<String> = r.get(5);
+                let tx_error:Option<String> = r.get(6);
+                let assigned = status == NftState::Minted;
                 let token_id:Option<String> = if assigned {
-                    r.get(11)
+                    r.get(8)
                 } else {
                     None
                 };
                 Reservation {
                     wallet_address: wallet_address.to_string(),
                     nft_id: r.get(1),
+                    status,
                     reserved,
                     reserved_until,
                     assigned,
-                    assigned_on: r.get(5),
-                    has_submit_error: r.get(6),
-                    in_process: r.get(7),
+                    assigned_on: r.get(4),
+                    has_submit_error: matches!(status, NftState::Errored | NftState::DeadLetter),
+                    in_process: matches!(status, NftState::PendingVerification | NftState::Submitted | NftState::Errored | NftState::DeadLetter),
                     tx_hash: txhash,
                     tx_error,
-                    tx_retry_count: r.get(10),
+                    tx_retry_count: r.get(7),
                     token_id
                 }
             }).collect::<Vec<Reservation>>();
@@ -103,21 +151,77 @@ pub fn get_reservations_for_wallet(
             Err(Json(ErrorResponse {
                 code: 500,
                 message: db_err.to_string(),
+                errors: None,
             }),
         ))},
     }
 }
-/// do a reservation for a NFT, picking NFT in seemingly random order
+/// do a reservation for a NFT, picking NFT deterministically based on the wallet and the drop salt
+///
+/// the limit check, the NFT selection and the whitelist update all happen in a single transaction.
+/// concurrent requests for the same wallet are serialized by an advisory lock on the wallet address,
+/// and candidate NFTs locked by another transaction are skipped.
+///
+/// reservations which are turned down are kept in the wallet's history, once for each reason with a count of the
+/// repeats. database errors aren't, they say nothing about the wallet
 pub fn do_reservation(
-    mut c: &mut Client,
+    c: &mut Client,
     wallet_address: &str,
     reserved_until: &DateTime<Utc>,
     max_reservations: usize,
+    salt: &str,
 ) -> (
     Status,
     Result<(Uuid, serde_json::Value), Json<ErrorResponse>>,
 ) {
-    let res_count_r = get_reservation_count(c, wallet_address);
+    let result = reserve_in_transaction(c, wallet_address, reserved_until, max_reservations, salt);
+    let rejection = result.1.as_ref().err().filter(|e| e.code != 500);
+    if let Some(e) = rejection {
+        if let Err(db_err) = record_rejected_reservation(c, wallet_address, &e.message) {
+            log::error!("do_reservation/history: {}", db_err.to_string());
+        }
+    }
+    result
+}
+fn reserve_in_transaction(
+    c: &mut Client,
+    wallet_address: &str,
+    reserved_until: &DateTime<Utc>,
+    max_reservations: usize,
+    salt: &str,
+) -> (
+    Status,
+    Result<(Uuid, serde_json::Value), Json<ErrorResponse>>,
+) {
+    let mut tx = match c.transaction() {
+        Ok(tx) => tx,
+        Err(db_err) => {
+            log::error!("do_reservation: {}", db_err.to_string());
+            return (
+                Status::new(500),
+                Err(Json(ErrorResponse {
+                    code: 500,
+                    message: db_err.to_string(),
+                    errors: None,
+                })),
+            );
+        }
+    };
+    if let Err(db_err) = tx.execute(
+        "select pg_advisory_xact_lock(hashtext($1))",
+        &[&String::from(wallet_address)],
+    ) {
+        log::error!("do_reservation/lock: {}", db_err.to_string());
+        return (
+            Status::new(500),
+            Err(Json(ErrorResponse {
+                code: 500,
+                message: db_err.to_string(),
+                errors: None,
+            })),
+        );
+    }
+    let res_count_r = get_reservation_count(&mut tx, wallet_address);
     match res_count_r {
         Ok(count) => {
             if count >= max_reservations {
@@ -126,13 +230,25 @@ pub fn do_reservation(
                     Err(Json(ErrorResponse {
                         code: 403,
                         message: "Reservation limit exceeded".to_string(),
+                        errors: None,
                     })),
                 )
             } else {
                 let nft_id_r =
-                    get_and_reserve_available_nft(&mut c, wallet_address, reserved_until);
+                    get_and_reserve_available_nft(&mut tx, wallet_address, reserved_until, salt);
                 match nft_id_r {
                     Ok(nft_reservation) => {
+                        if let Err(db_err) = tx.commit() {
+                            log::error!("do_reservation/commit: {}", db_err.to_string());
+                            return (
+                                Status::new(500),
+                                Err(Json(ErrorResponse {
+                                    code: 500,
+                                    message: db_err.to_string(),
+                                    errors: None,
+                                })),
+                            );
+                        }
                         let nft_id = nft_reservation.0;
                         let meta = nft_reservation.1;
                         (Status::new(201), Ok((nft_id, meta)))
@@ -146,29 +262,184 @@ pub fn do_reservation(
     }
 }
 
-/// clear reservations from expired reservations
-//fn clear_reservations(_conn: &mut Client) -> Result<i64, (Status, Json<ErrorResponse>)> {
-//    todo!()
-//}
-/// examine available NFTs and 'reserve' one
-pub fn get_and_reserve_available_nft(
+/// clear reservations from expired reservations, returning the number of NFTs put back in the pool
+pub fn clear_reservations(conn: &mut Client) -> Result<u64, Error> {
+    let mut tx = conn.transaction()?;
+    let rows = tx.query(
+        r#"update NFT set status='available', reserved_to_wallet_address=null, reserved_until=null, in_mint_run=false
+            where id in (
+                select id
+                from NFT
+                where status='reserved'
+                 and reserved_until < now()
+                for update skip locked
+            ) returning id"#,
+        &[],
+    )?;
+    let nft_ids = rows.iter().map(|r| r.get(0)).collect::<Vec<Uuid>>();
+    if !nft_ids.is_empty() {
+        expire_reservation_records(&mut tx, &nft_ids)?;
+    }
+    tx.commit()?;
+    Ok(nft_ids.len() as u64)
+}
+/// release errored NFTs the wallet didn't resubmit before their hold ran out, returning how many went back to the pool
+pub fn release_expired_errored(conn: &mut Client) -> Result<u64, Error> {
+    let mut tx = conn.transaction()?;
+    let nft_ids = tx
+        .query(
+            "select id from NFT where status='errored' and reserved_until < now() for update skip locked",
+            &[],
+        )?
+        .iter()
+        .map(|r| r.get(0))
+        .collect::<Vec<Uuid>>();
+    let released = if nft_ids.is_empty() {
+        0
+    } else {
+        release_failed_nfts(&mut tx, &nft_ids)?
+    };
+    tx.commit()?;
+    Ok(released)
+}
+/// mark the open NFT_Reservation rows for these NFTs as expired, and hand the whitelist allocation back
+fn expire_reservation_records<C: GenericClient>(
+    conn: &mut C,
+    nft_ids: &[Uuid],
+) -> Result<u64, Error> {
+    let rows = conn.query(
+        r#"update NFT_Reservation set has_expired=true, expired_on=now()
+            where nft_reserved = any($1) and completed=false and has_expired=false
+            returning wallet_address, stage"#,
+        &[&nft_ids],
+    )?;
+    for row in &rows {
+        adjust_whitelist_counts(conn, row.get(0), row.get(1), -1, 0)?;
+    }
+    Ok(rows.len() as u64)
+}
+/// move a wallet's whitelist counters for the stage an NFT was drawn from.
+/// reserved_count covers NFTs the wallet holds (errored and dead lettered ones too, until they are released),
+/// assigned_count those minted to the wallet
+fn adjust_whitelist_counts<C: GenericClient>(
+    conn: &mut C,
+    wallet_address: String,
+    stage: Option<Uuid>,
+    reserved: i32,
+    assigned: i32,
+) -> Result<u64, Error> {
+    match stage {
+        Some(stage_id) => conn.execute(
+            r#"update wallet_whitelist set
+                reserved_count = greatest(reserved_count + $3, 0),
+                assigned_count = greatest(assigned_count + $4, 0)
+            where wallet_address=$1 and stage=$2"#,
+            &[&wallet_address, &stage_id, &reserved, &assigned],
+        ),
+        None => Ok(0),
+    }
+}
+/// record a reservation against the stage it was drawn from, and how its NFT was picked.
+/// any previous (lapsed) reservation of the NFT is expired first.
+pub fn record_reservation<C: GenericClient>(
+    conn: &mut C,
+    pick: &Pick,
+    wallet_address: &str,
+    stage_id: Uuid,
+    reserved_until: &DateTime<Utc>,
+) -> Result<u64, Error> {
+    expire_reservation_records(conn, &[pick.nft_id])?;
+    conn.execute(
+        r#"insert into NFT_Reservation (wallet_address, nft_reserved, stage, reserved_until,
+                selection_seed, candidates_hash, candidate_count, selection_index)
+            values ($1,$2,$3,$4,$5,$6,$7,$8)"#,
+        &[
+            &String::from(wallet_address),
+            &pick.nft_id,
+            &stage_id,
+            reserved_until,
+            &to_hex(&pick.seed),
+            &pick.candidates_hash,
+            &(pick.candidate_count as i32),
+            &(pick.index as i32),
+        ],
+    )
+}
+/// keep a reservation attempt which didn't get an NFT. repeats of the same reason are counted on the one entry
+fn record_rejected_reservation(
+    conn: &mut Client,
+    wallet_address: &str,
+    reason: &str,
+) -> Result<u64, Error> {
+    conn.execute(
+        r#"insert into NFT_Reservation (wallet_address, has_error, errored_on, tx_error) values ($1, true, now(), $2)
+            on conflict (wallet_address, tx_error) where nft_reserved is null
+            do update set errored_on = now(), rejected_count = NFT_Reservation.rejected_count + 1"#,
+        &[&String::from(wallet_address), &String::from(reason)],
+    )
+}
+/// the wallet's reservations and attempts, newest first. a rejection is placed by its latest repeat
+pub fn get_reservation_history(
     conn: &mut Client,
     wallet_address: &str,
+) -> Result<Vec<ReservationHistory>, Error> {
+    let rows = conn.query(
+        r#"select r.id, r.nft_reserved, n.name, s.code, r.created_on, r.reserved_until,
+                r.submitted_on, r.txhash, r.errored_on, r.tx_error, r.expired_on, r.completed_on,
+                r.completed, r.has_error, r.has_expired, r.rejected_count
+            from NFT_Reservation r
+            left join NFT n on n.id = r.nft_reserved
+            left join stage_whitelist s on s.id = r.stage
+            where r.wallet_address = $1
+            order by case when r.nft_reserved is null then r.errored_on else r.created_on end desc
+            limit $2"#,
+        &[&String::from(wallet_address), &RESERVATION_HISTORY_LIMIT],
+    )?;
+    Ok(rows
+        .iter()
+        .map(|r| {
+            let nft_id: Option<Uuid> = r.get(1);
+            let submitted_on: Option<DateTime<Utc>> = r.get(6);
+            let status = if r.get(12) {
+                ReservationStatus::Completed
+            } else if r.get(14) {
+                ReservationStatus::Expired
+            } else if nft_id.is_none() {
+                ReservationStatus::Rejected
+            } else if r.get(13) {
+                ReservationStatus::Errored
+            } else if submitted_on.is_some() {
+                ReservationStatus::Submitted
+            } else {
+                ReservationStatus::Reserved
+            };
+            let stage_code: Option<String> = r.get(3);
+            ReservationHistory {
+                id: r.get(0),
+                nft_id,
+                nft_name: r.get(2),
+                stage_code: stage_code.map(|c| c.trim().to_string()),
+                status,
+                created_on: r.get(4),
+                reserved_until: r.get(5),
+                submitted_on,
+                tx_hash: r.get(7),
+                errored_on: r.get(8),
+                tx_error: r.get(9),
+                expired_on: r.get(10),
+                completed_on: r.get(11),
+                rejected_count: nft_id.is_none().then(|| r.get(15)),
+            }
+        })
+        .collect())
+}
+/// examine available NFTs and 'reserve' one
+pub fn get_and_reserve_available_nft<C: GenericClient>(
+    conn: &mut C,
+    wallet_address: &str,
     reserved_until: &DateTime<Utc>,
+    salt: &str,
 ) -> Result<(Uuid, serde_json::Value), (Status, Json<ErrorResponse>)> {
-    //  let pg_ts: &DateTime<chrono::offset::Utc> = reserved_until;
-    let mut hasher = DefaultHasher::new();
-    wallet_address.hash(&mut hasher);
-    let hash = hasher.finish();
-    let hash_i32: i32 = (((hash % i32::MAX as u64) as i32) - (i32::MAX / 2)) as i32;
-    let hash_f64: f64 = f64::from(hash_i32);
-
-    let seed: f64 = if hash_f64 == 0.0 {
-        -1.0
-    } else {
-        hash_f64 / f64::from(i32::MAX)
-    };
-    log::info!("Seed for {} is {} {}", wallet_address, hash, seed);
     let available_stages = get_open_stages_for_wallet(conn, wallet_address);
     match available_stages {
         Ok(stages) => {
@@ -178,18 +449,18 @@ pub fn get_and_reserve_available_nft(
                     Json(ErrorResponse {
                         code: 444,
                         message: "No stages are open for your wallet at this time".into(),
+                        errors: None,
                     }),
                 ))
             } else {
                 // go through the available stages and try to allocate a NFT from each stage
                 // get_open_stages should return the 'open' stage if it is open as a last resort
-                let _r = conn.execute("select setseed($1)", &[&seed]);
-                log::info!("_r = {:?}", _r);
                 for stage in stages {
                     let query = do_reservation_in_stage(
                         conn,
                         &stage,
                         wallet_address,
+                        salt,
                         1,
                         false,
                         reserved_until,
@@ -198,15 +469,23 @@ pub fn get_and_reserve_available_nft(
                         Ok(rows) => {
                             log::info!("get_and_reserve_available_nft/rows={}", rows.len());
                             if let Some(row) = rows.first() {
+                                let id_returned: Uuid = row.get(0);
                                 let r =
                                     increase_stage_reservation(conn, stage.id, wallet_address, 1);
                                 if let Err(db_err) = r {
                                     log::error!(
-                                        "Wallet Whitelist has error {}. We still reserved",
+                                        "get_and_reserve_available_nft/whitelist: {}",
                                         db_err.to_string()
-                                    )
+                                    );
+                                    return Err((
+                                        Status::new(500),
+                                        Json(ErrorResponse {
+                                            code: 500,
+                                            message: db_err.to_string(),
+                                            errors: None,
+                                        }),
+                                    ));
                                 }
-                                let id_returned: Uuid = row.get(0);
                                 let meta_data: serde_json::Value = row.get(1);
                                 return Ok((id_returned, meta_data));
                             } else {
@@ -230,6 +509,7 @@ pub fn get_and_reserve_available_nft(
                     Json(ErrorResponse {
                         code: 444,
                         message: "No NFTs available for reservation at this time".into(),
+                        errors: None,
                     }),
                 ))
             }
@@ -243,105 +523,239 @@ pub fn get_stage(
     code: &str,
 ) -> Result<Option<Stage>, (Status, Json<ErrorResponse>)> {
     match conn.query(
-        "Select id,code,name,attribute_type,attribute_value,is_default,stage_free,stage_open,stage_close from stage_whitelist where code=$1",
+        format!(
+            "Select {} from stage_whitelist where code=$1",
+            STAGE_COLUMNS
+        )
+        .as_str(),
         &[&String::from(code)],
     ) {
-        Ok(rows) => {
-
-            let stage = rows.first().map (|r|{
-                let code:String = r.get(1);
-                Stage{
-                    id: r.get(0),
-                    code: code.trim().to_string(),
-                    name: r.get(2),
-                    attribute_type: r.get(3),
-                    attribute_value: r.get(4),
-                    is_default: r.get(5),
-                    stage_free: r.get(6),
-                    stage_open: r.get(7),
-                    stage_close: r.get(8)
-                }});
-            Ok(stage)
-        }
+        Ok(rows) => Ok(rows.first().map(stage_from_row)),
         Err(db_err) => {
             log::error!("get_stage: {}", db_err.to_string());
             Err((
-            Status::new(500),
-            Json(ErrorResponse {
-                code: 500,
-                message: db_err.to_string(),
-            }),
-        ))},
+                Status::new(500),
+                Json(ErrorResponse {
+                    code: 500,
+                    message: db_err.to_string(),
+                    errors: None,
+                }),
+            ))
+        }
     }
 }
 /// get a collection of stages
 pub fn get_stages(conn: &mut Client) -> Result<Vec<Stage>, (Status, Json<ErrorResponse>)> {
     match conn.query(
-        "Select id,code,name,attribute_type,attribute_value,is_default,stage_free,stage_open, stage_close from stage_whitelist",
+        format!("Select {} from stage_whitelist", STAGE_COLUMNS).as_str(),
         &[],
     ) {
-        Ok(rows) => {
-
-            let stages = rows.iter().map (|r|{
-                let code:String = r.get(1);
-                Stage{
-                id: r.get(0),
-                code: code.trim().to_string(),
-                name: r.get(2),
-                attribute_type: r.get(3),
-                attribute_value: r.get(4),
-                is_default: r.get(5),
-                stage_free: r.get(6),
-                stage_open: r.get(7),
-                stage_close : r.get(8)
-                }
-            }).collect::<Vec<Stage>>();
-           Ok( stages)
-        }
-        Err(db_err) =>{
+        Ok(rows) => Ok(rows.iter().map(stage_from_row).collect::<Vec<Stage>>()),
+        Err(db_err) => {
             log::error!("get_stages: {}", db_err.to_string());
             Err((
-            Status::new(500),
-            Json(ErrorResponse {
-                code: 500,
-                message: db_err.to_string(),
-            }),
-        ))},
+                Status::new(500),
+                Json(ErrorResponse {
+                    code: 500,
+                    message: db_err.to_string(),
+                    errors: None,
+                }),
+            ))
+        }
+    }
+}
+
+const STAGE_COLUMNS: &str =
+    "id,code,name,selection_rule,is_default,stage_free,stage_open,stage_close";
+
+fn stage_from_row(r: &Row) -> Stage {
+    let code: String = r.get(1);
+    Stage {
+        id: r.get(0),
+        code: code.trim().to_string(),
+        name: r.get(2),
+        selection_rule: r
+            .get::<_, Option<postgres::types::Json<SelectionRule>>>(3)
+            .map(|rule| rule.0),
+        is_default: r.get(4),
+        stage_free: r.get(5),
+        stage_open: r.get(6),
+        stage_close: r.get(7),
+    }
+}
+/// create a new stage
+pub fn create_stage(conn: &mut Client, stage: &NewStageRequest) -> Result<Stage, Error> {
+    conn.query_one(
+        format!(
+            r#"insert into stage_whitelist(code,name,selection_rule,is_default,stage_free,stage_open,stage_close)
+                values ($1,$2,$3,$4,$5,$6,$7) returning {}"#,
+            STAGE_COLUMNS
+        )
+        .as_str(),
+        &[
+            &stage.code,
+            &stage.name,
+            &stage.selection_rule.as_ref().map(postgres::types::Json),
+            &stage.is_default,
+            &stage.stage_free,
+            &stage.stage_open,
+            &stage.stage_close,
+        ],
+    )
+    .map(|r| stage_from_row(&r))
+}
+/// change the fields present in the update. returns None if the stage doesn't exist
+pub fn update_stage(
+    conn: &mut Client,
+    code: &str,
+    update: &UpdateStageRequest,
+) -> Result<Option<Stage>, Error> {
+    conn.query(
+        format!(
+            r#"update stage_whitelist set
+                name = coalesce($2, name),
+                selection_rule = coalesce($3, selection_rule),
+                is_default = coalesce($4, is_default),
+                stage_free = coalesce($5, stage_free),
+                stage_open = coalesce($6, stage_open),
+                stage_close = coalesce($7, stage_close)
+            where code = $1 returning {}"#,
+            STAGE_COLUMNS
+        )
+        .as_str(),
+        &[
+            &String::from(code),
+            &update.name,
+            &update.selection_rule.as_ref().map(postgres::types::Json),
+            &update.is_default,
+            &update.stage_free,
+            &update.stage_open,
+            &update.stage_close,
+        ],
+    )
+    .map(|rows| rows.first().map(stage_from_row))
+}
+/// close the stage now, unless it has already closed
+pub fn close_stage(conn: &mut Client, code: &str) -> Result<Option<Stage>, Error> {
+    conn.query(
+        format!(
+            "update stage_whitelist set stage_close = least(coalesce(stage_close, now()), now()) where code = $1 returning {}",
+            STAGE_COLUMNS
+        )
+        .as_str(),
+        &[&String::from(code)],
+    )
+    .map(|rows| rows.first().map(stage_from_row))
+}
+/// the allocation of the given wallets in a stage
+pub fn get_wallet_allocations<C: GenericClient>(
+    conn: &mut C,
+    stage_id: Uuid,
+    wallets: &[String],
+) -> Result<Vec<WalletStageAllocation>, Error> {
+    let rows = conn.query(
+        r#"select w.stage, w.wallet_address, w.allocation_count::bigint, w.reserved_count::bigint, w.assigned_count::bigint, s.stage_open
+            from wallet_whitelist w, stage_whitelist s
+            where s.id = w.stage and w.stage = $1 and w.wallet_address = any($2)
+            order by w.wallet_address"#,
+        &[&stage_id, &wallets],
+    )?;
+    Ok(rows
+        .iter()
+        .map(allocation_from_row)
+        .collect::<Vec<WalletStageAllocation>>())
+}
+fn allocation_from_row(r: &Row) -> WalletStageAllocation {
+    WalletStageAllocation {
+        id: r.get(0),
+        wallet_address: r.get(1),
+        allocation_count: r.get(2),
+        reserved_count: r.get(3),
+        assigned_count: r.get(4),
+        stage_open: r.get(5),
+    }
+}
+/// the wallet's allocation in every stage it is whitelisted on
+pub fn get_allocations_for_wallet<C: GenericClient>(
+    conn: &mut C,
+    wallet: &str,
+) -> Result<Vec<WalletStageAllocation>, Error> {
+    let rows = conn.query(
+        r#"select w.stage, w.wallet_address, w.allocation_count::bigint, w.reserved_count::bigint, w.assigned_count::bigint, s.stage_open
+            from wallet_whitelist w, stage_whitelist s
+            where s.id = w.stage and w.wallet_address = $1
+            order by s.stage_open"#,
+        &[&String::from(wallet)],
+    )?;
+    Ok(rows
+        .iter()
+        .map(allocation_from_row)
+        .collect::<Vec<WalletStageAllocation>>())
+}
+/// add wallets to a stage's whitelist, replacing the allocation of wallets already on it
+pub fn upsert_wallet_allocations(
+    conn: &mut Client,
+    stage_id: Uuid,
+    wallets: &[WalletAllocationRequest],
+) -> Result<Vec<WalletStageAllocation>, Error> {
+    let mut tx = conn.transaction()?;
+    let stmt = tx.prepare(
+        r#"insert into wallet_whitelist(wallet_address, stage, allocation_count) values ($1,$2,$3)
+            on conflict (wallet_address, stage) do update set allocation_count = excluded.allocation_count"#,
+    )?;
+    for wallet in wallets {
+        tx.execute(
+            &stmt,
+            &[&wallet.wallet_address, &stage_id, &wallet.allocation_count],
+        )?;
+    }
+    let addresses = wallets
+        .iter()
+        .map(|w| w.wallet_address.clone())
+        .collect::<Vec<String>>();
+    let allocations = get_wallet_allocations(&mut tx, stage_id, &addresses)?;
+    tx.commit()?;
+    Ok(allocations)
+}
+/// change a single wallet's allocation. returns None if the wallet is not on the stage's whitelist
+pub fn set_wallet_allocation(
+    conn: &mut Client,
+    stage_id: Uuid,
+    wallet_address: &str,
+    allocation_count: i32,
+) -> Result<Option<WalletStageAllocation>, Error> {
+    let updated = conn.execute(
+        "update wallet_whitelist set allocation_count=$3 where wallet_address=$1 and stage=$2",
+        &[&String::from(wallet_address), &stage_id, &allocation_count],
+    )?;
+    if updated == 0 {
+        Ok(None)
+    } else {
+        Ok(get_wallet_allocations(conn, stage_id, &[String::from(wallet_address)])?.pop())
     }
 }
 
+/// what has become of the NFTs matching a stage's selection rule. `available` is what the stage could still hand out
 pub fn get_nft_stat(
     conn: &mut Client,
-    attr_type: &Option<String>,
-    attr_value: &Option<String>,
+    rule: &Option<SelectionRule>,
 ) -> Result<NFTTallyStat, (Status, Json<ErrorResponse>)> {
-    let results = match attr_type {
-        Some(a_t) => {
-            if let Some(a_v) = attr_value {
-                conn.query(
-                    "Select sum(case assigned when true then 1  else 0 end),
-       sum(case reserved when true then 1  else 0 end),
-       sum(1) from nft n, json_array_elements(n.meta_data -> 'attributes' ) att
-        where att ->> 'trait_type' = $1 and att ->> 'value' = $2",
-                    &[&a_t, &a_v],
-                )
-            } else {
-                return Err((
-                    Status::new(500),
-                    Json(ErrorResponse {
-                        code: 500,
-                        message: "Missing Value".into(),
-                    }),
-                ));
-            }
-        }
-        None => conn.query(
-            "Select sum(case assigned when true then 1 else 0 end),
-       sum(case reserved when true then 1 else 0 end),
-       sum(1) from nft",
-            &[],
-        ),
-    };
+    let mut values = vec![];
+    let predicate = rule_predicate(rule, &mut values);
+    let results = conn.query(
+        format!(
+            "Select coalesce(sum(case state when 'minted' then 1 else 0 end),0),
+       coalesce(sum(case when state in ({}) then 1 else 0 end),0),
+       count(*),
+       coalesce(sum(case state when 'available' then 1 else 0 end),0)
+        from (select {} as state from nft n where not n.withdrawn and {}) n",
+            state_list(NftState::HELD),
+            NFT_STATE,
+            predicate
+        )
+        .as_str(),
+        &sql_params(&values),
+    );
     match results {
         Ok(rows) => {
             if let Some(row) = rows.first() {
@@ -349,12 +763,14 @@ pub fn get_nft_stat(
                     assigned: row.get(0),
                     reserved: row.get(1),
                     count: row.get(2),
+                    available: row.get(3),
                 })
             } else {
                 Ok(NFTTallyStat {
                     assigned: 0,
                     reserved: 0,
                     count: 0,
+                    available: 0,
                 })
             }
         }
@@ -365,54 +781,136 @@ pub fn get_nft_stat(
                 Json(ErrorResponse {
                     code: 500,
                     message: db_err.to_string(),
+                    errors: None,
                 }),
             ))
         }
     }
 }
-pub fn get_open_stage(
-    mut conn: Client,
-) -> Result<DateTime<chrono::offset::Utc>, (Status, Json<ErrorResponse>)> {
-    match conn.query(
-        "Select min(stage_open) from stage_whitelist where is_default=true ",
-        &[],
-    ) {
-        Ok(rows) => {
-            if let Some(row) = rows.first() {
-                let date: DateTime<chrono::offset::Utc> = row.get(0);
-                Ok(date)
-            } else {
-                Err((
-                    Status::new(404),
-                    Json(ErrorResponse {
-                        code: 404,
-                        message: String::from("No open stages are available"),
-                    }),
-                ))
-            }
-        }
-        Err(db_err) => {
-            log::error!("get_open_stage: {}", db_err.to_string());
-            Err((
+/// each stage's whitelist: how many wallets are on it, and their allocations, reservations and mints
+pub fn get_whitelist_stats(
+    conn: &mut Client,
+) -> Result<HashMap<Uuid, WhitelistTallyStat>, (Status, Json<ErrorResponse>)> {
+    let rows = conn
+        .query(
+            r#"select stage, count(*), coalesce(sum(allocation_count),0), coalesce(sum(reserved_count),0),
+                      coalesce(sum(assigned_count),0)
+               from wallet_whitelist where stage is not null group by stage"#,
+            &[],
+        )
+        .map_err(|db_err| {
+            log::error!("get_whitelist_stats: {}", db_err.to_string());
+            (
                 Status::new(500),
                 Json(ErrorResponse {
                     code: 500,
                     message: db_err.to_string(),
+                    errors: None,
                 }),
-            ))
-        }
+            )
+        })?;
+    Ok(rows
+        .iter()
+        .map(|r| {
+            (
+                r.get(0),
+                WhitelistTallyStat {
+                    wallet_count: r.get(1),
+                    allocation_count: r.get(2),
+                    reserved_count: r.get(3),
+                    assigned_count: r.get(4),
+                },
+            )
+        })
+        .collect())
+}
+
+/// move stages whose open/close time has passed into their new state, recording each transition.
+/// when `revoke_on_close` is set, reservations still held in a closing stage are released.
+pub fn advance_stage_lifecycle(
+    conn: &mut Client,
+    revoke_on_close: bool,
+) -> Result<Vec<StageTransition>, Error> {
+    let mut tx = conn.transaction()?;
+    let rows = tx.query(
+        r#"select id, code, stage_status, current_status
+            from (
+                select id, code, stage_status,
+                       case
+                           when stage_close is not null and stage_close <= now() then 'closed'
+                           when stage_open <= now() then 'open'
+                           else 'pending'
+                       end as current_status
+                from stage_whitelist
+                for update
+            ) s
+            where stage_status <> current_status"#,
+        &[],
+    )?;
+    let mut transitions: Vec<StageTransition> = Vec::with_capacity(rows.len());
+    for row in rows {
+        let stage_id: Uuid = row.get(0);
+        let code: String = row.get(1);
+        let from_status: String = row.get(2);
+        let to_status: String = row.get(3);
+        let to_state = StageState::from_str(&to_status).unwrap_or(StageState::Closed);
+        let revoked = if revoke_on_close && to_state == StageState::Closed {
+            revoke_stage_reservations(&mut tx, stage_id)?
+        } else {
+            0
+        };
+        tx.execute(
+            "update stage_whitelist set stage_status=$1 where id=$2",
+            &[&to_status, &stage_id],
+        )?;
+        tx.execute(
+            "insert into stage_transition(stage, from_status, to_status, revoked_count) values ($1,$2,$3,$4)",
+            &[&stage_id, &from_status, &to_status, &(revoked as i32)],
+        )?;
+        transitions.push(StageTransition {
+            stage_code: code.trim().to_string(),
+            from_state: StageState::from_str(&from_status).unwrap_or(StageState::Pending),
+            to_state,
+            revoked,
+        });
+    }
+    tx.commit()?;
+    Ok(transitions)
+}
+/// release reservations drawn from the stage which have not been submitted yet
+fn revoke_stage_reservations<C: GenericClient>(conn: &mut C, stage_id: Uuid) -> Result<u64, Error> {
+    let rows = conn.query(
+        r#"update NFT set status='available', reserved_to_wallet_address=null, reserved_until=null, in_mint_run=false
+            where id in (
+                select n.id
+                from NFT n, NFT_Reservation r
+                where r.nft_reserved = n.id
+                 and r.stage = $1
+                 and r.completed=false
+                 and r.has_expired=false
+                 and n.status='reserved'
+                for update of n skip locked
+            ) returning id"#,
+        &[&stage_id],
+    )?;
+    let nft_ids = rows.iter().map(|r| r.get(0)).collect::<Vec<Uuid>>();
+    if !nft_ids.is_empty() {
+        expire_reservation_records(conn, &nft_ids)?;
     }
+    Ok(nft_ids.len() as u64)
 }
 
 /// for regular reservations, get a list of 'special stages/whitelists' that the wallet is entitled too
-pub fn get_open_stages_for_wallet(
-    conn: &mut Client,
+pub fn get_open_stages_for_wallet<C: GenericClient>(
+    conn: &mut C,
     wallet: &str,
 ) -> Result<Vec<Stage>, (Status, Json<ErrorResponse>)> {
     let query = conn.query(
-        "select id,code,name,attribute_type,attribute_value,is_Default,stage_free,stage_open, stage_close, 1 as sort_pref 
+        format!(
+            "select {columns}, 1 as sort_pref
         from stage_whitelist where
-        stage_open < now() and  
+        stage_open < now() and
+        (stage_close is null or stage_close > now()) and
         id in (
     select stage
     from wallet_whitelist
@@ -420,30 +918,21 @@ pub fn get_open_stages_for_wallet(
       and allocation_count > (reserved_count + wallet_whitelist.assigned_count)
 )
 union
-select id,code,name,attribute_type,attribute_value,is_Default,stage_free,stage_open,stage_close,2
+select {columns},2
 from stage_whitelist
 where
         stage_open < now() and
+        (stage_close is null or stage_close > now()) and
       is_default = true
 order by sort_pref
 ",
+            columns = STAGE_COLUMNS
+        )
+        .as_str(),
         &[&String::from(wallet)],
     );
     match query {
-        Ok(rows) => Ok(rows
-            .iter()
-            .map(|r| Stage {
-                id: r.get(0),
-                code: r.get(1),
-                name: r.get(2),
-                attribute_type: r.get(3),
-                attribute_value: r.get(4),
-                is_default: r.get(5),
-                stage_free: r.get(6),
-                stage_open: r.get(7),
-                stage_close: r.get(8),
-            })
-            .collect::<Vec<Stage>>()),
+        Ok(rows) => Ok(rows.iter().map(stage_from_row).collect::<Vec<Stage>>()),
         Err(db_err) => {
             log::error!("get_open_stages_for_wallet:{}", db_err.to_string());
             Err((
@@ -451,14 +940,80 @@ order by sort_pref
                 Json(ErrorResponse {
                     code: 500,
                     message: db_err.to_string(),
+                    errors: None,
                 }),
             ))
         }
     }
 }
-/// update wallet reservation count
-pub fn increase_stage_reservation(
+/// the stages, open or yet to open, the wallet is whitelisted on or which are open to all, with what it has left.
+/// a stage is `eligible_now` when `get_open_stages_for_wallet` would draw from it, and the wallet is under `max_reservations`
+pub fn wallet_eligibility(
     conn: &mut Client,
+    wallet: &str,
+    max_reservations: usize,
+) -> Result<WalletEligibility, (Status, Json<ErrorResponse>)> {
+    let reservation_count = get_reservation_count(conn, wallet)?;
+    let reservations_left = max_reservations.saturating_sub(reservation_count);
+    let open = get_open_stages_for_wallet(conn, wallet)?
+        .into_iter()
+        .map(|stage| stage.id)
+        .collect::<Vec<Uuid>>();
+    let mut allocations = get_allocations_for_wallet(conn, wallet)
+        .map_err(|db_err| {
+            log::error!("wallet_eligibility: {}", db_err.to_string());
+            (
+                Status::new(500),
+                Json(ErrorResponse {
+                    code: 500,
+                    message: db_err.to_string(),
+                    errors: None,
+                }),
+            )
+        })?
+        .into_iter()
+        .filter_map(|a| a.id.map(|id| (id, a)))
+        .collect::<HashMap<Uuid, WalletStageAllocation>>();
+    let now = Utc::now();
+    let mut stages = get_stages(conn)?
+        .into_iter()
+        .filter_map(|stage| {
+            let allocation = allocations.remove(&stage.id);
+            let stage_state = stage.state_at(now);
+            if stage_state == StageState::Closed || (allocation.is_none() && !stage.is_default) {
+                return None;
+            }
+            Some(StageEligibility {
+                stage_id: stage.id,
+                stage_code: stage.code,
+                stage_name: stage.name,
+                stage_state,
+                stage_free: stage.stage_free,
+                stage_open: stage.stage_open,
+                stage_close: stage.stage_close,
+                is_default: stage.is_default,
+                allocation_count: allocation.as_ref().map(|a| a.allocation_count),
+                reserved_count: allocation.as_ref().map(|a| a.reserved_count),
+                assigned_count: allocation.as_ref().map(|a| a.assigned_count),
+                remaining: allocation
+                    .as_ref()
+                    .map(|a| (a.allocation_count - a.reserved_count - a.assigned_count).max(0)),
+                eligible_now: reservations_left > 0 && open.contains(&stage.id),
+            })
+        })
+        .collect::<Vec<StageEligibility>>();
+    stages.sort_by_key(|s| (s.stage_state != StageState::Open, s.stage_open));
+    Ok(WalletEligibility {
+        wallet_address: String::from(wallet),
+        max_reservations,
+        reservation_count,
+        reservations_left,
+        stages,
+    })
+}
+/// update wallet reservation count
+pub fn increase_stage_reservation<C: GenericClient>(
+    conn: &mut C,
     stage_id: Uuid,
     wallet_address: &str,
     amount: i32,
@@ -468,62 +1023,526 @@ pub fn increase_stage_reservation(
         &[&String::from(wallet_address), &stage_id,&amount],
     )
 }
+/// the public view of an NFT from its `id, name, status, reserved_until`. the tx hash isn't given out
+fn nft_lite(r: &Row) -> NFT {
+    let status = nft_state(r.get(2), r.get(3));
+    match status {
+        NftState::Reserved => NFT::new(r.get(0), r.get(1), status, r.get(3), None),
+        NftState::Available if r.get::<_, Option<DateTime<Utc>>>(3).is_some() => {
+            log::info!("Past Reservation {}", r.get::<_, Uuid>(0));
+            NFT::new(r.get(0), r.get(1), status, None, None)
+        }
+        _ => NFT::new(
+            r.get(0),
+            r.get(1),
+            status,
+            r.get(3),
+            Some(String::from("-hidden-")),
+        ),
+    }
+}
+/// the public view of an NFT. the tx hash isn't given out
+pub fn get_nft_lite(conn: &mut Client, nft: &Uuid) -> Result<Option<NFT>, Error> {
+    let rows = conn.query(
+        "Select id, name, status, reserved_until from NFT where id=$1",
+        &[nft],
+    )?;
+    Ok(rows.first().map(nft_lite))
+}
+/// a page of NFTs matching the (validated) query, in name order. withdrawn NFTs aren't listed
+pub fn list_nfts(conn: &mut Client, query: &NftListQuery) -> Result<NftPage, Error> {
+    let mut values = vec![];
+    let mut conditions = vec![String::from("not n.withdrawn")];
+    let param = |values: &mut Vec<String>, value: &str| {
+        values.push(String::from(value));
+        format!("${}", values.len())
+    };
+    if let Some(state) = &query.state {
+        conditions.push(format!("{} = {}", NFT_STATE, param(&mut values, state)));
+    }
+    if let (Some(trait_type), Some(value)) = (&query.trait_type, &query.trait_value) {
+        let rule = SelectionRule::Trait {
+            trait_type: trait_type.clone(),
+            value: value.clone(),
+        };
+        conditions.push(rule_predicate(&Some(rule), &mut values));
+    }
+    if let Some(prefix) = &query.name_prefix {
+        let pattern = format!(
+            "{}%",
+            prefix
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        conditions.push(format!(
+            "upper(n.name) like upper({})",
+            param(&mut values, &pattern)
+        ));
+    }
+    if let Some(wallet) = &query.wallet {
+        conditions.push(format!(
+            "n.assigned_to_wallet_address = {}",
+            param(&mut values, wallet)
+        ));
+    }
+    if let Some(after) = query.after() {
+        conditions.push(format!("n.name > {}", param(&mut values, &after)));
+    }
+    let limit = query.limit();
+    let rows = conn.query(
+        format!(
+            "select n.id, n.name, n.status, n.reserved_until from nft n where {} order by n.name limit {}",
+            conditions.join(" and "),
+            limit + 1
+        )
+        .as_str(),
+        &sql_params(&values),
+    )?;
+    let mut nfts = rows.iter().map(nft_lite).collect::<Vec<NFT>>();
+    let next_cursor = if nfts.len() as i64 > limit {
+        nfts.truncate(limit as usize);
+        nfts.last().map(|nft| NftListQuery::cursor_for(&nft.name))
+    } else {
+        None
+    };
+    Ok(NftPage { nfts, next_cursor })
+}
+/// how many NFTs are in each state
+pub fn get_nft_tally(conn: &mut Client) -> Result<NFTTallyResponse, Error> {
+    let row = conn.query_one(
+        format!(
+            r#"select coalesce(sum(case state when 'minted' then 1 else 0 end),0) as assigned,
+                      coalesce(sum(case state when 'reserved' then 1 else 0 end),0) as reserved,
+                      coalesce(sum(case when state in ('pending_verification','submitted','errored','dead_letter') then 1 else 0 end),0) as in_process,
+                      coalesce(sum(case when state = 'available' and not withdrawn then 1 else 0 end),0) as available,
+                      coalesce(sum(case when withdrawn then 1 else 0 end),0) as withdrawn
+               from (select {} as state, withdrawn from nft) n"#,
+            NFT_STATE
+        )
+        .as_str(),
+        &[],
+    )?;
+    Ok(NFTTallyResponse {
+        assigned: row.get(0),
+        reserved: row.get(1),
+        in_process: row.get(2),
+        available: row.get(3),
+        withdrawn: row.get(4),
+    })
+}
+/// `part` as a percentage of `whole`, to 2 decimal places
+fn percentage(part: i64, whole: i64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        (part as f64 * 10000.0 / whole as f64).round() / 100.0
+    }
+}
+/// every trait type and value in the collection, with how many NFTs have it in each state.
+/// counted from `NFT_Trait`, which is kept up to date as NFTs are added or changed
+pub fn trait_stats(conn: &mut Client) -> Result<TraitStatsResponse, Error> {
+    let held = state_list(NftState::HELD);
+    let collection = conn.query_one(
+        format!(
+            r#"select count(*), coalesce(sum(case state when 'available' then 1 else 0 end),0)
+               from (select {} as state from nft where not withdrawn) n"#,
+            NFT_STATE
+        )
+        .as_str(),
+        &[],
+    )?;
+    let nft_count: i64 = collection.get(0);
+    let available: i64 = collection.get(1);
+    let rows = conn.query(
+        format!(
+            r#"select t.trait_type, t.value, count(*),
+                      coalesce(sum(case n.state when 'minted' then 1 else 0 end),0),
+                      coalesce(sum(case when n.state in ({}) then 1 else 0 end),0),
+                      coalesce(sum(case n.state when 'available' then 1 else 0 end),0)
+               from NFT_Trait t
+               join (select id, {} as state from nft where not withdrawn) n on n.id = t.nft_id
+               group by t.trait_type, t.value
+               order by t.trait_type, count(*), t.value"#,
+            held, NFT_STATE
+        )
+        .as_str(),
+        &[],
+    )?;
+    let mut traits: Vec<TraitTypeStat> = vec![];
+    for r in rows {
+        let trait_type: String = r.get(0);
+        let value = TraitValueStat {
+            value: r.get(1),
+            total: r.get(2),
+            assigned: r.get(3),
+            reserved: r.get(4),
+            available: r.get(5),
+            rarity: percentage(r.get(2), nft_count),
+            available_rarity: percentage(r.get(5), available),
+        };
+        match traits.last_mut() {
+            Some(last) if last.trait_type == trait_type => {
+                last.total += value.total;
+                last.values.push(value);
+            }
+            _ => traits.push(TraitTypeStat {
+                trait_type,
+                total: value.total,
+                values: vec![value],
+            }),
+        }
+    }
+    Ok(TraitStatsResponse {
+        nft_count,
+        available,
+        traits,
+    })
+}
 /// retried NFT from database
 pub fn get_nft(conn: &mut Client, nft: &Uuid) -> Result<NftFull, Error> {
     conn.query_one(
         r#"
-            Select  id,name, assigned, reserved, has_submit_error, reserved_until, 
+            Select  id,name, status, reserved_until,
                     meta_data, svg, ipfs_image, ipfs_meta, image_data, external_url, description, background_color, 
-                    animation_url, youtube_url, assigned_on, assigned_to_wallet_address, reserved_to_wallet_address,signed_packet ,in_process,txhash
+                    animation_url, youtube_url, assigned_on, assigned_to_wallet_address, reserved_to_wallet_address,signed_packet ,txhash,
+                    withdrawn
                     from NFT where id = $1"#,
         &[nft],
     )
     .map(|r| {
-        let n = NFT{
-            id: r.get(0),
-            name: r.get(1),
-            assigned: r.get(2),
-            reserved: r.get(3),
-            has_submit_error: r.get(4),
-            reserved_until: r.get(5),
-            in_process:r.get(20),
-            txhash:r.get(21)
-        };
+        let n = NFT::new(
+            r.get(0),
+            r.get(1),
+            nft_state(r.get(2), r.get(3)),
+            r.get(3),
+            r.get(18),
+        );
         NftFull {
             nft_lite: n,
-            meta_data: r.get(6),
-            svg: r.get(7),
-            ipfs_image: r.get(8),
-            ipfs_meta: r.get(9),
-            image_data:r.get(10),
-            external_url: r.get(11),
-            description: r.get(12),
-            background_color: r.get(13),
-            animation_url: r.get(14),
-            youtube_url: r.get(15),
-            assigned_on: r.get(16),
-            assigned_to_wallet_address: r.get(17),
-            reserved_to_wallet_address: r.get(18),
-            signed_packet: r.get(19)
+            meta_data: r.get(4),
+            svg: r.get(5),
+            ipfs_image: r.get(6),
+            ipfs_meta: r.get(7),
+            image_data:r.get(8),
+            external_url: r.get(9),
+            description: r.get(10),
+            background_color: r.get(11),
+            animation_url: r.get(12),
+            youtube_url: r.get(13),
+            assigned_on: r.get(14),
+            assigned_to_wallet_address: r.get(15),
+            reserved_to_wallet_address: r.get(16),
+            signed_packet: r.get(17),
+            withdrawn: r.get(19),
         }})
 }
 
-/// set TXHash for NFT purchase, and set NFT 'in_progress'
+/// set TXHash for NFT purchase, and move the NFT to 'submitted'. 0 if it can't be submitted.
+/// a resubmission after an error takes the whitelist allocation again
 pub fn set_tx_hash_for_nft(conn: &mut Client, nft: &Uuid, txhash: &str) -> Result<u64, Error> {
-    conn.execute(
-        "update NFT set txhash = $1, in_process = true, has_submit_error =false, tx_retry_count = tx_retry_count+1 where id = $2",
+    let mut tx = conn.transaction()?;
+    let updated = tx.execute(
+        format!(
+            "update NFT set txhash = $1, status = 'submitted', tx_retry_count = tx_retry_count+1 where id = $2 and {}",
+            can_become(NftState::Submitted)
+        )
+        .as_str(),
         &[&String::from(txhash), &nft],
+    )?;
+    if updated == 0 {
+        return Ok(0);
+    }
+    retry_errored_records(&mut tx, nft)?;
+    record_submission(&mut tx, nft, Some(txhash))?;
+    tx.commit()?;
+    Ok(updated)
+}
+/// set TXHash for NFT purchase, and hold the NFT in 'pending_verification' until the tx verifier finds
+/// the transaction on chain, or `verify_by` passes. 0 if it can't be submitted.
+pub fn park_tx_hash_for_nft(
+    conn: &mut Client,
+    nft: &Uuid,
+    txhash: &str,
+    verify_by: DateTime<Utc>,
+) -> Result<u64, Error> {
+    let mut tx = conn.transaction()?;
+    let updated = tx.execute(
+        format!(
+            "update NFT set txhash = $1, status = 'pending_verification', verify_by = $2, tx_retry_count = tx_retry_count+1 where id = $3 and {}",
+            can_become(NftState::PendingVerification)
+        )
+        .as_str(),
+        &[&String::from(txhash), &verify_by, &nft],
+    )?;
+    if updated == 0 {
+        return Ok(0);
+    }
+    retry_errored_records(&mut tx, nft)?;
+    record_submission(&mut tx, nft, Some(txhash))?;
+    tx.commit()?;
+    Ok(updated)
+}
+/// tx hashes waiting to be found on chain, the soonest to time out first
+pub fn txs_pending_verification(
+    conn: &mut Client,
+    limit: i64,
+) -> Result<Vec<TxPendingVerification>, Error> {
+    Ok(conn
+        .query(
+            r#"select id, reserved_to_wallet_address, txhash, verify_by
+            from NFT where status = 'pending_verification' and txhash is not null
+            order by verify_by limit $1"#,
+            &[&limit],
+        )?
+        .iter()
+        .map(|r| TxPendingVerification {
+            nft_id: r.get(0),
+            wallet_address: r.get(1),
+            txhash: r.get::<_, String>(2).trim().to_string(),
+            verify_by: r.get(3),
+        })
+        .collect())
+}
+/// the NFT's transaction was found on chain. it is now 'submitted', for the tx watcher to pick up. 0 if it wasn't pending
+pub fn verified_tx_hash(conn: &mut Client, nft: &Uuid, txhash: &str) -> Result<u64, Error> {
+    conn.execute(
+        "update NFT set status = 'submitted', verify_by = null where id = $1 and txhash = $2 and status = 'pending_verification'",
+        &[&nft, &String::from(txhash)],
     )
 }
-/// set TX for NFT purchase, and set NFT 'in_progress'
-pub fn set_tx_for_nft(conn: &mut Client, nft: &Uuid, tx: &str) -> Result<u64, Error> {
+/// the NFT's tx hash couldn't be vouched for. the hash is dropped and the NFT goes back to being reserved,
+/// so the wallet can submit again while its reservation lasts. 0 if it wasn't pending
+pub fn fail_verification(
+    conn: &mut Client,
+    nft: &Uuid,
+    txhash: &str,
+    error: &str,
+) -> Result<u64, Error> {
+    let mut tx = conn.transaction()?;
+    let error = Some(String::from(error));
+    let updated = tx.execute(
+        "update NFT set status = 'reserved', txhash = null, verify_by = null, tx_error = $1 where id = $2 and txhash = $3 and status = 'pending_verification'",
+        &[&error, &nft, &String::from(txhash)],
+    )?;
+    if updated > 0 {
+        tx.execute(
+            r#"update NFT_Reservation set submitted_on=null, txhash=null, tx_error=$2
+                where nft_reserved=$1 and completed=false and has_expired=false"#,
+            &[nft, &error],
+        )?;
+    }
+    tx.commit()?;
+    Ok(updated)
+}
+/// set TX for NFT purchase, and move the NFT to 'submitted' for the broadcaster to send. 0 if it can't be submitted.
+/// a new signed tx starts its broadcast retries afresh
+pub fn set_tx_for_nft(conn: &mut Client, nft: &Uuid, tx: &serde_json::Value) -> Result<u64, Error> {
+    let mut db_tx = conn.transaction()?;
+    let updated = db_tx.execute(
+        format!(
+            "update NFT set signed_packet = $1, status = 'submitted', txhash = null, tx_error = null, tx_retry_count = 0 where id = $2 and {}",
+            can_become(NftState::Submitted)
+        )
+        .as_str(),
+        &[&tx, &nft],
+    )?;
+    if updated == 0 {
+        return Ok(0);
+    }
+    retry_errored_records(&mut db_tx, nft)?;
+    record_submission(&mut db_tx, nft, None)?;
+    db_tx.commit()?;
+    Ok(updated)
+}
+/// a failed reservation is being tried again. it kept its allocation while it was errored
+fn retry_errored_records<C: GenericClient>(conn: &mut C, nft: &Uuid) -> Result<u64, Error> {
     conn.execute(
-        "update NFT set signed_packet = $1, in_process = true where id = $2",
-        &[&String::from(tx), &nft],
+        r#"update NFT_Reservation set has_error=false
+            where nft_reserved=$1 and completed=false and has_expired=false and has_error=true"#,
+        &[&nft],
     )
 }
 
-pub fn is_name_available(conn: &mut Client, name: &str) -> Result<bool, Error> {
+/// signed transactions waiting to be broadcast, oldest reservation first
+pub fn signed_txs_to_broadcast(
+    conn: &mut Client,
+    limit: i64,
+) -> Result<Vec<SignedTxToBroadcast>, Error> {
+    Ok(conn
+        .query(
+            r#"select id, reserved_to_wallet_address, meta_data, signed_packet, tx_retry_count
+            from NFT where status = 'submitted' and txhash is null and signed_packet is not null
+            order by reserved_until limit $1"#,
+            &[&limit],
+        )?
+        .iter()
+        .map(|r| SignedTxToBroadcast {
+            nft_id: r.get(0),
+            wallet_address: r.get(1),
+            meta_data: r.get(2),
+            signed_packet: r.get(3),
+            tx_retry_count: r.get::<_, Option<i32>>(4).unwrap_or(0),
+        })
+        .collect())
+}
+
+/// record the hash of a broadcast signed transaction, for the tx watcher to look for. 0 if it was already recorded
+pub fn set_broadcast_hash(conn: &mut Client, nft: &Uuid, txhash: &str) -> Result<u64, Error> {
+    let mut tx = conn.transaction()?;
+    let updated = tx.execute(
+        "update NFT set txhash = $1, tx_error = null where id = $2 and status = 'submitted' and txhash is null",
+        &[&String::from(txhash), &nft],
+    )?;
+    if updated > 0 {
+        record_submission(&mut tx, nft, Some(txhash))?;
+    }
+    tx.commit()?;
+    Ok(updated)
+}
+
+/// count a failed broadcast of the NFT's signed transaction. after `max_retries` of them (or straight away,
+/// for a transaction which can never be sent) the NFT errors. returns true if it errored
+pub fn note_broadcast_failure(
+    conn: &mut Client,
+    nft: &Uuid,
+    error: &str,
+    max_retries: i32,
+    retryable: bool,
+    policy: &RetryPolicy,
+) -> Result<bool, Error> {
+    let mut tx = conn.transaction()?;
+    let error = Some(String::from(error));
+    let row = tx.query_opt(
+        "update NFT set tx_retry_count = coalesce(tx_retry_count, 0) + 1, tx_error = $1 where id = $2 and status = 'submitted' and txhash is null returning tx_retry_count",
+        &[&error, &nft],
+    )?;
+    let attempts: i32 = match row {
+        Some(row) => row.get(0),
+        None => return Ok(false),
+    };
+    let errored = !retryable || attempts >= max_retries;
+    if errored {
+        let nft_ids = tx
+            .query(
+                format!(
+                    "update nft set status='errored' where id = $1 and {} returning id",
+                    can_become(NftState::Errored)
+                )
+                .as_str(),
+                &[&nft],
+            )?
+            .iter()
+            .map(|r| r.get(0))
+            .collect::<Vec<Uuid>>();
+        fail_reservation_records(&mut tx, &nft_ids, None, &error)?;
+        apply_retry_policy(&mut tx, &nft_ids, &error, policy)?;
+    }
+    tx.commit()?;
+    Ok(errored)
+}
+
+/// note the submission on the NFT's open reservation. the hash isn't known yet for signed transactions
+fn record_submission<C: GenericClient>(
+    conn: &mut C,
+    nft: &Uuid,
+    txhash: Option<&str>,
+) -> Result<u64, Error> {
+    conn.execute(
+        r#"update NFT_Reservation set submitted_on=now(), txhash=coalesce($2, txhash)
+            where nft_reserved=$1 and completed=false and has_expired=false"#,
+        &[nft, &txhash],
+    )
+}
+
+/// add an NFT, returning its id
+pub fn insert_nft<C: GenericClient>(
+    conn: &mut C,
+    nft: &NewNFTRequest,
+    meta_data: &serde_json::Value,
+    svg: &serde_json::Value,
+) -> Result<Uuid, Error> {
+    let row = conn.query_one(
+        r#"Insert into NFT( id,name,meta_data,svg,ipfs_image,
+                            ipfs_meta, image_data, external_url,
+                            description,background_color,
+                            animation_url,youtube_url  )
+        values(DEFAULT,$1,$2,$3,$4, $5,$6,$7, $8,$9, $10,$11) returning id"#,
+        &[
+            &nft.name,
+            meta_data,
+            svg,
+            &nft.ipfs_image,
+            &nft.ipfs_meta,
+            &nft.image_data,
+            &nft.external_url,
+            &nft.description,
+            &nft.background_color,
+            &nft.animation_url,
+            &nft.youtube_url,
+        ],
+    )?;
+    Ok(row.get(0))
+}
+
+/// add a collection of NFTs in one transaction. every row is checked, and tried in a savepoint so a database
+/// error is reported against its row. nothing is added if any row fails, or on a dry run
+pub fn import_nfts(
+    conn: &mut Client,
+    nfts: &[NewNFTRequest],
+    dry_run: bool,
+) -> Result<NftImportResponse, Error> {
+    let mut tx = conn.transaction()?;
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut rows = vec![];
+    for (i, nft) in nfts.iter().enumerate() {
+        let mut row = NftImportRow {
+            row: i + 1,
+            name: Some(nft.name.clone()),
+            nft_id: None,
+            errors: vec![],
+        };
+        let parsed = nft.validate();
+        if let Err(errors) = &parsed {
+            row.errors.extend(errors.iter().map(|e| e.to_string()));
+        }
+        if let Some(first) = seen.insert(nft.name.to_uppercase(), row.row) {
+            row.errors
+                .push(format!("name: is a duplicate of row {}", first));
+        } else if !is_name_available(&mut tx, &nft.name)? {
+            row.errors.push(String::from("name: is already taken"));
+        }
+        if let (Ok((meta_data, svg)), true) = (&parsed, row.errors.is_empty()) {
+            let mut savepoint = tx.transaction()?;
+            match insert_nft(&mut savepoint, nft, meta_data, svg) {
+                Ok(id) => {
+                    savepoint.commit()?;
+                    row.nft_id = Some(id);
+                }
+                Err(e) => row.errors.push(e.to_string()),
+            }
+        }
+        rows.push(row);
+    }
+    let failed = rows.iter().any(|r| !r.errors.is_empty());
+    let imported = if failed || dry_run {
+        tx.rollback()?;
+        for row in rows.iter_mut() {
+            row.nft_id = None;
+        }
+        0
+    } else {
+        tx.commit()?;
+        rows.len()
+    };
+    Ok(NftImportResponse {
+        dry_run,
+        imported,
+        rows,
+    })
+}
+
+pub fn is_name_available<C: GenericClient>(conn: &mut C, name: &str) -> Result<bool, Error> {
     let query = conn.query_one(
         "select count(*) from NFT where upper(name) = upper($1) or upper(token_id) = upper($1)",
         &[&String::from(name)],
@@ -537,9 +1556,296 @@ pub fn is_name_available(conn: &mut Client, name: &str) -> Result<bool, Error> {
     }
 }
 
+/// why an admin change to an NFT was refused
+fn nft_refusal(
+    code: u16,
+    message: String,
+    errors: Option<Vec<FieldError>>,
+) -> (Status, Json<ErrorResponse>) {
+    (
+        Status::new(code),
+        Json(ErrorResponse {
+            code,
+            message,
+            errors,
+        }),
+    )
+}
+
+fn nft_db_error(db_err: Error) -> (Status, Json<ErrorResponse>) {
+    log::error!("NFT admin: {}", db_err);
+    nft_refusal(500, db_err.to_string(), None)
+}
+
+/// an NFT's editable fields, its state and whether it is withdrawn, locked until the transaction ends
+fn editable_nft(
+    tx: &mut Transaction,
+    nft: &Uuid,
+) -> Result<(NewNFTRequest, NftState, bool), (Status, Json<ErrorResponse>)> {
+    let rows = tx
+        .query(
+            r#"select name, meta_data, svg, ipfs_image, ipfs_meta, image_data, external_url, description,
+                      background_color, animation_url, youtube_url, status, reserved_until, withdrawn
+               from NFT where id = $1 for update"#,
+            &[nft],
+        )
+        .map_err(nft_db_error)?;
+    let r = rows
+        .first()
+        .ok_or_else(|| nft_refusal(404, String::from("NFT not found"), None))?;
+    let cid = |i: usize| {
+        r.get::<_, Option<String>>(i)
+            .unwrap_or_default()
+            .trim()
+            .to_string()
+    };
+    let nft = NewNFTRequest {
+        name: r.get(0),
+        meta: r
+            .get::<_, Option<serde_json::Value>>(1)
+            .unwrap_or_default()
+            .to_string(),
+        svg: r
+            .get::<_, Option<serde_json::Value>>(2)
+            .unwrap_or_default()
+            .to_string(),
+        ipfs_image: cid(3),
+        ipfs_meta: cid(4),
+        image_data: r.get(5),
+        external_url: r.get(6),
+        description: r.get(7),
+        background_color: r.get(8),
+        animation_url: r.get(9),
+        youtube_url: r.get(10),
+    };
+    Ok((nft, nft_state(r.get(11), r.get(12)), r.get(13)))
+}
+
+/// the NFT's fields as kept in the audit trail, with `meta` and `svg` as JSON
+fn audit_fields(nft: &NewNFTRequest) -> serde_json::Map<String, serde_json::Value> {
+    let mut fields = match serde_json::to_value(nft) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => serde_json::Map::new(),
+    };
+    for (field, json) in [("meta", &nft.meta), ("svg", &nft.svg)] {
+        if let Ok(value) = serde_json::from_str::<serde_json::Value>(json) {
+            fields.insert(String::from(field), value);
+        }
+    }
+    fields
+}
+
+fn audit_nft(
+    tx: &mut Transaction,
+    nft: &Uuid,
+    action: &str,
+    before: Option<serde_json::Value>,
+    after: Option<serde_json::Value>,
+    signer: &AdminSignature,
+) -> Result<u64, Error> {
+    tx.execute(
+        "insert into NFT_Audit (nft_id, action, before, after, signature, nonce) values ($1,$2,$3,$4,$5,$6)",
+        &[
+            nft,
+            &String::from(action),
+            &before,
+            &after,
+            &signer.signature,
+            &signer.nonce,
+        ],
+    )
+}
+
+/// change an available NFT's fields, checking the result as a new NFT would be. the changed fields are audited
+pub fn update_nft(
+    conn: &mut Client,
+    nft: &Uuid,
+    update: UpdateNFTRequest,
+    signer: &AdminSignature,
+) -> Result<NftFull, (Status, Json<ErrorResponse>)> {
+    let mut tx = conn.transaction().map_err(nft_db_error)?;
+    let (current, state, _) = editable_nft(&mut tx, nft)?;
+    if state != NftState::Available {
+        return Err(nft_refusal(
+            409,
+            format!("NFT is {}. only available NFTs can be changed", state),
+            None,
+        ));
+    }
+    let renamed = update
+        .name
+        .as_ref()
+        .map(|name| name.to_uppercase() != current.name.to_uppercase())
+        .unwrap_or(false);
+    let before = audit_fields(&current);
+    let updated = update.apply(current);
+    let (meta_data, svg) = updated
+        .validate()
+        .map_err(|errors| nft_refusal(422, String::from("Invalid NFT"), Some(errors)))?;
+    if renamed && !is_name_available(&mut tx, &updated.name).map_err(nft_db_error)? {
+        return Err(nft_refusal(
+            422,
+            String::from("Invalid NFT"),
+            Some(vec![FieldError::new("name", "is already taken")]),
+        ));
+    }
+    let after = audit_fields(&updated);
+    let changed = after
+        .keys()
+        .filter(|field| before.get(*field) != after.get(*field))
+        .cloned()
+        .collect::<Vec<String>>();
+    if !changed.is_empty() {
+        tx.execute(
+            r#"update NFT set name=$2, meta_data=$3, svg=$4, ipfs_image=$5, ipfs_meta=$6, image_data=$7,
+                              external_url=$8, description=$9, background_color=$10, animation_url=$11,
+                              youtube_url=$12
+               where id = $1"#,
+            &[
+                nft,
+                &updated.name,
+                &meta_data,
+                &svg,
+                &updated.ipfs_image,
+                &updated.ipfs_meta,
+                &updated.image_data,
+                &updated.external_url,
+                &updated.description,
+                &updated.background_color,
+                &updated.animation_url,
+                &updated.youtube_url,
+            ],
+        )
+        .map_err(nft_db_error)?;
+        let only = |fields: &serde_json::Map<String, serde_json::Value>| {
+            serde_json::Value::Object(
+                changed
+                    .iter()
+                    .map(|field| (field.clone(), fields[field].clone()))
+                    .collect(),
+            )
+        };
+        audit_nft(
+            &mut tx,
+            nft,
+            "update",
+            Some(only(&before)),
+            Some(only(&after)),
+            signer,
+        )
+        .map_err(nft_db_error)?;
+    }
+    tx.commit().map_err(nft_db_error)?;
+    get_nft(conn, nft).map_err(nft_db_error)
+}
+
+/// pull an available NFT from sale, or put a withdrawn one back. audited
+pub fn set_nft_withdrawn(
+    conn: &mut Client,
+    nft: &Uuid,
+    withdrawn: bool,
+    signer: &AdminSignature,
+) -> Result<(), (Status, Json<ErrorResponse>)> {
+    let mut tx = conn.transaction().map_err(nft_db_error)?;
+    let (_, state, was_withdrawn) = editable_nft(&mut tx, nft)?;
+    if was_withdrawn == withdrawn {
+        let already = if withdrawn {
+            "NFT is already withdrawn"
+        } else {
+            "NFT is not withdrawn"
+        };
+        return Err(nft_refusal(409, String::from(already), None));
+    }
+    if state != NftState::Available {
+        return Err(nft_refusal(
+            409,
+            format!("NFT is {}. only available NFTs can be withdrawn", state),
+            None,
+        ));
+    }
+    tx.execute(
+        "update NFT set withdrawn = $2 where id = $1",
+        &[nft, &withdrawn],
+    )
+    .map_err(nft_db_error)?;
+    audit_nft(
+        &mut tx,
+        nft,
+        if withdrawn { "withdraw" } else { "restore" },
+        Some(serde_json::json!({ "withdrawn": was_withdrawn })),
+        Some(serde_json::json!({ "withdrawn": withdrawn })),
+        signer,
+    )
+    .map_err(nft_db_error)?;
+    tx.commit().map_err(nft_db_error)
+}
+
+/// remove an NFT which has never been reserved. its fields are kept in the audit trail
+pub fn delete_nft(
+    conn: &mut Client,
+    nft: &Uuid,
+    signer: &AdminSignature,
+) -> Result<(), (Status, Json<ErrorResponse>)> {
+    let mut tx = conn.transaction().map_err(nft_db_error)?;
+    let (current, state, withdrawn) = editable_nft(&mut tx, nft)?;
+    let reserved_before: bool = tx
+        .query_one(
+            "select exists (select 1 from NFT_Reservation where nft_reserved = $1)",
+            &[nft],
+        )
+        .map_err(nft_db_error)?
+        .get(0);
+    if reserved_before || state != NftState::Available {
+        return Err(nft_refusal(
+            409,
+            String::from("NFT has been reserved, so it can't be deleted"),
+            None,
+        ));
+    }
+    let mut before = audit_fields(&current);
+    before.insert(
+        String::from("withdrawn"),
+        serde_json::Value::Bool(withdrawn),
+    );
+    audit_nft(
+        &mut tx,
+        nft,
+        "delete",
+        Some(serde_json::Value::Object(before)),
+        None,
+        signer,
+    )
+    .map_err(nft_db_error)?;
+    tx.execute("delete from NFT where id = $1", &[nft])
+        .map_err(nft_db_error)?;
+    tx.commit().map_err(nft_db_error)
+}
+
+/// every admin change to an NFT, oldest first. kept after the NFT is deleted
+pub fn nft_audit_trail(conn: &mut Client, nft: &Uuid) -> Result<Vec<NftAuditEntry>, Error> {
+    let rows = conn.query(
+        r#"select id, nft_id, action, before, after, signature, nonce, created_on
+           from NFT_Audit where nft_id = $1 order by created_on, id"#,
+        &[nft],
+    )?;
+    Ok(rows
+        .iter()
+        .map(|r| NftAuditEntry {
+            id: r.get(0),
+            nft_id: r.get(1),
+            action: r.get(2),
+            before: r.get(3),
+            after: r.get(4),
+            signature: r.get(5),
+            nonce: r.get(6),
+            created_on: r.get(7),
+        })
+        .collect())
+}
+
 pub fn reservations_in_process(conn: &mut Client, limit: i64) -> Result<Vec<String>, Error> {
     let query = conn.query(
-        "select txhash from nft where in_process = true and has_submit_error=false and in_mint_run=false limit $1",
+        "select txhash from nft where status = 'submitted' and txhash is not null and in_mint_run=false limit $1",
         &[&limit],
     );
     match query {
@@ -553,7 +1859,7 @@ pub fn reservations_in_mint_process(
     limit: i64,
 ) -> Result<Vec<(String, String)>, Error> {
     let query = conn.query(
-        "select txhash, name from nft where in_process = true and has_submit_error=false and in_mint_run=true limit $1",
+        "select txhash, name from nft where status = 'submitted' and txhash is not null and in_mint_run=true limit $1",
         &[&limit],
     );
     match query {
@@ -569,7 +1875,7 @@ pub fn reservations_stuck_in_mint_process(
     limit: i64,
 ) -> Result<Vec<MintReservation>, Error> {
     let query = conn.query(
-        "select reserved_to_wallet_address, id,meta_data from nft where in_process = false and assigned=false and has_submit_error=false and in_mint_run=true limit $1",
+        "select reserved_to_wallet_address, id,meta_data,name from nft where status = 'reserved' and in_mint_run=true limit $1",
         &[&limit],
     );
     match query {
@@ -580,6 +1886,7 @@ pub fn reservations_stuck_in_mint_process(
                 MintReservation {
                     wallet_address: r.get(0),
                     nft_id: r.get(1),
+                    name: r.get(3),
                     meta_data: meta,
                 }
             })
@@ -589,7 +1896,7 @@ pub fn reservations_stuck_in_mint_process(
 }
 pub fn reservations_in_mint_reserved(conn: &mut Client, limit: i64) -> Result<Vec<String>, Error> {
     let query = conn.query(
-        "select name from nft where assigned=false and reserved = true and has_submit_error=false and in_mint_run=true limit $1",
+        "select name from nft where status in ('reserved','submitted') and in_mint_run=true limit $1",
         &[&limit],
     );
     match query {
@@ -598,35 +1905,329 @@ pub fn reservations_in_mint_reserved(conn: &mut Client, limit: i64) -> Result<Ve
     }
 }
 
+/// record the outcome of a mint transaction. a failure is handled as the retry policy says
 pub fn nft_assign_tx_result(
     conn: &mut Client,
-    wallet: Option<String>,
-    txhash: String,
-    result: bool,
-    tx_time: Option<DateTime<chrono::offset::Utc>>,
-    error_message: Option<String>,
-    token_id: Option<String>,
+    result: ReservationTxResultRequest,
+    policy: &RetryPolicy,
 ) -> Result<u64, Error> {
-    if result {
-        conn.execute(
-            "update nft set has_submit_error=false, in_process=false, assigned=true, reserved=false, tx_error=null, assigned_to_wallet_address=$1, assigned_on=$2, token_id=$3 where txhash=$4",
-            &[&wallet,&tx_time, &token_id, &txhash],
-        )
+    let ReservationTxResultRequest {
+        wallet_address: wallet,
+        tx: txhash,
+        success,
+        assigned_on: tx_time,
+        error: error_message,
+        token_id,
+    } = result;
+    let mut tx = conn.transaction()?;
+    let updated = if success {
+        let nft_ids = tx
+            .query(
+                format!(
+                    "update nft set status='minted', tx_error=null, assigned_to_wallet_address=$1, assigned_on=$2, token_id=$3 where txhash=$4 and {} returning id",
+                    can_become(NftState::Minted)
+                )
+                .as_str(),
+                &[&wallet, &tx_time, &token_id, &txhash],
+            )?
+            .iter()
+            .map(|r| r.get(0))
+            .collect::<Vec<Uuid>>();
+        complete_reservation_records(&mut tx, &nft_ids)?;
+        nft_ids.len() as u64
     } else {
-        conn.execute(
-            "update nft set has_submit_error=true, tx_error=$1 where txhash=$2",
-            &[&error_message, &txhash],
-        )
-    }
+        let nft_ids = tx
+            .query(
+                format!(
+                    "update nft set status='errored', tx_error=$1 where txhash=$2 and {} returning id",
+                    can_become(NftState::Errored)
+                )
+                .as_str(),
+                &[&error_message, &txhash],
+            )?
+            .iter()
+            .map(|r| r.get(0))
+            .collect::<Vec<Uuid>>();
+        fail_reservation_records(&mut tx, &nft_ids, Some(&txhash), &error_message)?;
+        apply_retry_policy(&mut tx, &nft_ids, &error_message, policy)?;
+        nft_ids.len() as u64
+    };
+    tx.commit()?;
+    Ok(updated)
 }
 
 pub fn nft_assign_owner(conn: &mut Client, wallet: String, token_id: String) -> Result<u64, Error> {
     log::debug!("nft_assign_owner: {} {}", wallet, token_id);
+    let mut tx = conn.transaction()?;
+    let nft_ids = tx
+        .query(
+            format!(
+                r#"update nft set status='minted', tx_error=null, assigned_to_wallet_address=$1, token_id=$2
+                where reserved_to_wallet_address=$3 and name=$4 and {} returning id"#,
+                can_become(NftState::Minted)
+            )
+            .as_str(),
+            &[&wallet, &token_id, &wallet, &token_id],
+        )?
+        .iter()
+        .map(|r| r.get(0))
+        .collect::<Vec<Uuid>>();
+    complete_reservation_records(&mut tx, &nft_ids)?;
+    tx.commit()?;
+    Ok(nft_ids.len() as u64)
+}
+
+/// mark the open reservations of these NFTs errored. the errored NFT is still the wallet's, so it keeps
+/// its allocation until it is minted or released back to the pool
+fn fail_reservation_records<C: GenericClient>(
+    conn: &mut C,
+    nft_ids: &[Uuid],
+    txhash: Option<&str>,
+    error_message: &Option<String>,
+) -> Result<u64, Error> {
     conn.execute(
-            r#"update nft set has_submit_error=false, in_process=false, assigned=true, reserved=false, tx_error=null, assigned_to_wallet_address=$1, token_id=$2 
-            where reserved_to_wallet_address=$3 and name=$4"#,
-            &[&wallet, &token_id,&wallet,&token_id],
+        r#"update NFT_Reservation set has_error=true, errored_on=now(), tx_error=$2, txhash=coalesce($1, txhash)
+            where nft_reserved = any($3)
+             and completed=false and has_expired=false and has_error=false"#,
+        &[&txhash, error_message, &nft_ids],
+    )
+}
+
+/// count the failure against each of these (just errored) NFTs, and retry, dead letter or release them
+/// as the policy says. each failure is kept in NFT_Tx_Error
+fn apply_retry_policy<C: GenericClient>(
+    conn: &mut C,
+    nft_ids: &[Uuid],
+    error_message: &Option<String>,
+    policy: &RetryPolicy,
+) -> Result<(), Error> {
+    let rows = conn.query(
+        r#"update NFT set mint_failures = mint_failures + 1
+            where id = any($1)
+            returning id, mint_failures, reserved_to_wallet_address, txhash"#,
+        &[&nft_ids],
+    )?;
+    let mut retried = vec![];
+    let mut released = vec![];
+    for row in &rows {
+        let nft_id: Uuid = row.get(0);
+        let outcome = policy.outcome(row.get(1), error_message.as_deref().unwrap_or_default());
+        let wallet_address: Option<String> = row.get(2);
+        let txhash: Option<String> = row.get(3);
+        conn.execute(
+            "insert into NFT_Tx_Error (nft_id, wallet_address, txhash, tx_error, outcome) values ($1, $2, $3, $4, $5)",
+            &[&nft_id, &wallet_address, &txhash, error_message, &outcome.as_str()],
+        )?;
+        match outcome {
+            FailureOutcome::Retry => retried.push(nft_id),
+            FailureOutcome::DeadLetter => {
+                log::warn!(
+                    "{} dead lettered after {} failures",
+                    nft_id,
+                    row.get::<_, i32>(1)
+                );
+                conn.execute(
+                    format!(
+                        "update NFT set status='dead_letter' where id = $1 and {}",
+                        can_become(NftState::DeadLetter)
+                    )
+                    .as_str(),
+                    &[&nft_id],
+                )?;
+            }
+            FailureOutcome::Release => released.push(nft_id),
+        }
+    }
+    if !retried.is_empty() {
+        retry_failed_nfts(conn, &retried, policy)?;
+    }
+    if !released.is_empty() {
+        log::info!("{} failed NFTs released to the pool", released.len());
+        release_failed_nfts(conn, &released)?;
+    }
+    Ok(())
+}
+
+/// errored NFTs whose signed transaction the LCD turned away (so it never got a hash on chain) go back to the
+/// broadcaster, which sends it again. one that failed on chain has used its account sequence, so resending it would
+/// be refused: those, and the rest, wait for the wallet to resubmit, for `policy.errored_hold` minutes.
+/// returns the number handed to the broadcaster
+fn retry_failed_nfts<C: GenericClient>(
+    conn: &mut C,
+    nft_ids: &[Uuid],
+    policy: &RetryPolicy,
+) -> Result<u64, Error> {
+    let rebroadcast = conn
+        .query(
+            format!(
+                r#"update NFT set status='submitted', txhash=null, tx_retry_count=0
+                where id = any($1) and status='errored' and signed_packet is not null and txhash is null and {}
+                returning id"#,
+                can_become(NftState::Submitted)
+            )
+            .as_str(),
+            &[&nft_ids],
+        )?
+        .iter()
+        .map(|r| r.get(0))
+        .collect::<Vec<Uuid>>();
+    for nft in &rebroadcast {
+        retry_errored_records(conn, nft)?;
+        record_submission(conn, nft, None)?;
+    }
+    conn.execute(
+        "update NFT set reserved_until = now() + make_interval(mins => $2) where id = any($1) and status='errored'",
+        &[&nft_ids, &policy.errored_hold],
+    )?;
+    Ok(rebroadcast.len() as u64)
+}
+
+/// put errored or dead lettered NFTs back in the pool, closing their reservations and handing the allocation back
+fn release_failed_nfts<C: GenericClient>(conn: &mut C, nft_ids: &[Uuid]) -> Result<u64, Error> {
+    let rows = conn.query(
+        format!(
+            r#"update NFT set status='available', reserved_to_wallet_address=null, reserved_until=null, in_mint_run=false,
+                txhash=null, signed_packet=null, tx_error=null, tx_retry_count=0, mint_failures=0
+            where id = any($1) and status in ('errored','dead_letter') and {}
+            returning id"#,
+            can_become(NftState::Available)
         )
+        .as_str(),
+        &[&nft_ids],
+    )?;
+    let released = rows.iter().map(|r| r.get(0)).collect::<Vec<Uuid>>();
+    expire_reservation_records(conn, &released)?;
+    Ok(released.len() as u64)
+}
+
+/// release an errored or dead lettered NFT back to the pool. 0 if it wasn't either
+pub fn release_failed_nft(conn: &mut Client, nft: &Uuid) -> Result<u64, Error> {
+    let mut tx = conn.transaction()?;
+    let released = release_failed_nfts(&mut tx, &[*nft])?;
+    tx.commit()?;
+    Ok(released)
+}
+
+/// give an errored or dead lettered NFT another go, with its failures forgiven. one with a signed transaction which
+/// never reached the chain is broadcast again, otherwise the wallet may resubmit it. 0 if it wasn't either
+pub fn requeue_failed_nft(
+    conn: &mut Client,
+    nft: &Uuid,
+    policy: &RetryPolicy,
+) -> Result<u64, Error> {
+    let mut tx = conn.transaction()?;
+    let requeued = tx.execute(
+        "update NFT set status='errored', mint_failures=0 where id = $1 and status in ('errored','dead_letter')",
+        &[nft],
+    )?;
+    if requeued > 0 {
+        retry_failed_nfts(&mut tx, &[*nft], policy)?;
+    }
+    tx.commit()?;
+    Ok(requeued)
+}
+
+/// NFTs whose mint failed, dead lettered first, with their failures
+pub fn errored_nfts(conn: &mut Client, limit: i64) -> Result<Vec<ErroredNft>, Error> {
+    let rows = conn.query(
+        r#"select id, name, status, reserved_to_wallet_address, mint_failures, tx_error
+            from NFT where status in ('errored','dead_letter')
+            order by status = 'dead_letter' desc, name
+            limit $1"#,
+        &[&limit],
+    )?;
+    let nft_ids = rows.iter().map(|r| r.get(0)).collect::<Vec<Uuid>>();
+    let mut errors: HashMap<Uuid, Vec<TxErrorRecord>> = HashMap::new();
+    for r in conn.query(
+        r#"select nft_id, wallet_address, txhash, tx_error, outcome, created_on
+            from NFT_Tx_Error where nft_id = any($1)
+            order by created_on"#,
+        &[&nft_ids],
+    )? {
+        let txhash: Option<String> = r.get(2);
+        errors.entry(r.get(0)).or_default().push(TxErrorRecord {
+            wallet_address: r.get(1),
+            tx_hash: txhash.map(|h| h.trim().to_string()),
+            tx_error: r.get(3),
+            outcome: r.get(4),
+            created_on: r.get(5),
+        });
+    }
+    Ok(rows
+        .iter()
+        .map(|r| {
+            let nft_id: Uuid = r.get(0);
+            ErroredNft {
+                nft_id,
+                name: r.get(1),
+                status: nft_state(r.get(2), None),
+                wallet_address: r.get(3),
+                mint_failures: r.get(4),
+                tx_error: r.get(5),
+                errors: errors.remove(&nft_id).unwrap_or_default(),
+            }
+        })
+        .collect())
+}
+
+/// mark the open reservations of these NFTs completed,
+/// moving the allocation from reserved to assigned
+fn complete_reservation_records<C: GenericClient>(
+    conn: &mut C,
+    nft_ids: &[Uuid],
+) -> Result<u64, Error> {
+    let rows = conn.query(
+        r#"update NFT_Reservation set completed=true, completed_on=now(),
+                txhash=coalesce(txhash, (select txhash from NFT where id = nft_reserved))
+            where nft_reserved = any($1)
+             and completed=false and has_expired=false
+            returning wallet_address, stage"#,
+        &[&nft_ids],
+    )?;
+    for row in &rows {
+        adjust_whitelist_counts(conn, row.get(0), row.get(1), -1, 1)?;
+    }
+    Ok(rows.len() as u64)
+}
+
+/// recompute the stage's whitelist counters from the NFTs drawn from it, returning the wallets which were off
+pub fn reconcile_whitelist(
+    conn: &mut Client,
+    stage_id: Uuid,
+) -> Result<Vec<WhitelistCorrection>, Error> {
+    let mut tx = conn.transaction()?;
+    let rows = tx.query(
+        format!(r#"with actual as (
+                select w.id, w.reserved_count as reserved_was, w.assigned_count as assigned_was,
+                    (select count(*) from nft n
+                        where n.stage = w.stage and n.reserved_to_wallet_address = w.wallet_address
+                         and n.status in ({}))::int as reserved,
+                    (select count(*) from nft n
+                        where n.stage = w.stage and n.status='minted'
+                         and coalesce(n.reserved_to_wallet_address, n.assigned_to_wallet_address) = w.wallet_address)::int as assigned
+                from wallet_whitelist w
+                where w.stage = $1
+                for update of w
+            )
+            update wallet_whitelist w set reserved_count = a.reserved, assigned_count = a.assigned
+            from actual a
+            where w.id = a.id
+             and (coalesce(w.reserved_count, -1) <> a.reserved or coalesce(w.assigned_count, -1) <> a.assigned)
+            returning w.wallet_address, a.reserved_was::bigint, a.reserved::bigint, a.assigned_was::bigint, a.assigned::bigint"#,
+            state_list(NftState::HELD)).as_str(),
+        &[&stage_id],
+    )?;
+    tx.commit()?;
+    Ok(rows
+        .iter()
+        .map(|r| WhitelistCorrection {
+            wallet_address: r.get(0),
+            reserved_count_was: r.get(1),
+            reserved_count: r.get(2),
+            assigned_count_was: r.get(3),
+            assigned_count: r.get(4),
+        })
+        .collect())
 }
 
 /// get a list of open wallets for a stage
@@ -662,38 +2263,57 @@ pub fn mint_nft_for_wallet_in_stage(
     conn: &mut Client,
     stage: &Stage,
     wallet_address: &str,
+    salt: &str,
     amount: i64,
 ) -> Result<Vec<MintReservation>, (Status, Json<ErrorResponse>)> {
+    let db_error = |e: Error| {
+        log::error!("mint_nft_for_wallet_in_stage:{}", e.to_string());
+        (
+            Status::InternalServerError,
+            Json(ErrorResponse {
+                code: 500,
+                message: e.to_string(),
+                errors: None,
+            }),
+        )
+    };
     let close = stage
         .stage_close
         .unwrap_or_else(|| chrono::Utc::now().add(chrono::Duration::hours(24)));
-    let query = do_reservation_in_stage(conn, stage, wallet_address, amount, true, &close);
+    let mut tx = conn.transaction().map_err(db_error)?;
+    let query = do_reservation_in_stage(&mut tx, stage, wallet_address, salt, amount, true, &close);
     match query {
         Ok(rows) => {
             log::debug!("mint_nft_for_wallet_in_stage/rows={}", rows.len());
-            let _r = increase_stage_reservation(conn, stage.id, wallet_address, rows.len() as i32)
-                .map_err(|e| {
-                    log::error!("mint_nft_for_wallet_in_stage:{}", e.to_string());
-                    (
-                        Status::InternalServerError,
-                        Json(ErrorResponse {
-                            code: 500,
-                            message: e.to_string(),
-                        }),
-                    )
-                })?;
-
-            Ok(rows
+            let reservations = rows
                 .iter()
                 .map(|row| {
-                    let meta: Metadata = serde_json::from_value(row.get(1)).unwrap();
-                    MintReservation {
+                    let meta: Metadata = serde_json::from_value(row.get(1)).map_err(|e| {
+                        log::error!("mint_nft_for_wallet_in_stage/meta_data:{}", e);
+                        (
+                            Status::InternalServerError,
+                            Json(ErrorResponse {
+                                code: 500,
+                                message: format!(
+                                    "Unable to read the meta_data of {}",
+                                    row.get::<_, Uuid>(0)
+                                ),
+                                errors: None,
+                            }),
+                        )
+                    })?;
+                    Ok(MintReservation {
                         wallet_address: wallet_address.to_string(),
                         nft_id: row.get(0),
+                        name: row.get(2),
                         meta_data: meta,
-                    }
+                    })
                 })
-                .collect::<Vec<MintReservation>>())
+                .collect::<Result<Vec<MintReservation>, (Status, Json<ErrorResponse>)>>()?;
+            increase_stage_reservation(&mut tx, stage.id, wallet_address, rows.len() as i32)
+                .map_err(db_error)?;
+            tx.commit().map_err(db_error)?;
+            Ok(reservations)
         }
         Err(e) => {
             log::error!("mint_nft_for_wallet_in_stage: {}", e.1.message);
@@ -701,110 +2321,221 @@ pub fn mint_nft_for_wallet_in_stage(
         }
     }
 }
-pub fn do_reservation_in_stage(
-    conn: &mut Client,
+/// the SQL condition (against `nft n`) for a stage's selection rule. no rule matches every NFT.
+/// values are appended to `values` and referenced as parameters, so nothing from the rule is spliced into the SQL
+fn rule_predicate(rule: &Option<SelectionRule>, values: &mut Vec<String>) -> String {
+    match rule {
+        Some(rule) => rule_sql(rule, values),
+        None => String::from("true"),
+    }
+}
+
+fn rule_sql(rule: &SelectionRule, values: &mut Vec<String>) -> String {
+    let mut param = |value: &str| {
+        values.push(String::from(value));
+        format!("${}", values.len())
+    };
+    match rule {
+        SelectionRule::Names(names) if names.is_empty() => String::from("false"),
+        SelectionRule::Names(names) => format!(
+            "n.name in ({})",
+            names
+                .iter()
+                .map(|name| param(name))
+                .collect::<Vec<String>>()
+                .join(",")
+        ),
+        SelectionRule::Trait { trait_type, value } => format!(
+            "exists (select 1 from json_array_elements(n.meta_data -> 'attributes') att where att ->> 'trait_type' = {} and att ->> 'value' = {})",
+            param(trait_type),
+            param(value)
+        ),
+        SelectionRule::All(rules) if rules.is_empty() => String::from("true"),
+        SelectionRule::All(rules) => format!(
+            "({})",
+            rules
+                .iter()
+                .map(|r| rule_sql(r, values))
+                .collect::<Vec<String>>()
+                .join(" and ")
+        ),
+        SelectionRule::Any(rules) if rules.is_empty() => String::from("false"),
+        SelectionRule::Any(rules) => format!(
+            "({})",
+            rules
+                .iter()
+                .map(|r| rule_sql(r, values))
+                .collect::<Vec<String>>()
+                .join(" or ")
+        ),
+        SelectionRule::Not(rule) => format!("not ({})", rule_sql(rule, values)),
+    }
+}
+
+fn sql_params(values: &[String]) -> Vec<&(dyn ToSql + Sync)> {
+    values.iter().map(|v| v as &(dyn ToSql + Sync)).collect()
+}
+
+/// the NFTs a stage could currently hand out, in a stable (id) order
+pub fn stage_candidates<C: GenericClient>(
+    conn: &mut C,
     stage: &Stage,
-    wallet_address: &str,
-    amount: i64,
-    is_mint: bool,
-    reserved_until: &DateTime<Utc>,
-) -> Result<Vec<Row>, (Status, Json<ErrorResponse>)> {
-    let pg_ts: &DateTime<chrono::offset::Utc> = reserved_until;
-    let query = if &stage.code == "bagel" {
-        let select_stmt = r#"                                
-                                update nft set reserved=true, reserved_to_wallet_address=$1 ,reserved_until=$2, in_mint_run=$4
-                                where id in (
-                                    select id as available
-                                    from nft n, json_array_elements(n.meta_data -> 'attributes' ) att
-                                    where assigned = false 
-                                     and (reserved=false or reserved_until < now())
-                                     and name =$3
-                                     and has_submit_error=false
-                                     and in_process=false
-                                    order by random()
-                                    limit 1
-                                ) returning id,meta_data "#;
-        let stmt_reserve_nft: Statement = conn.prepare(select_stmt).unwrap();
-        conn.query(
-            &stmt_reserve_nft,
-            &[
-                &String::from(wallet_address),
-                pg_ts,
-                &String::from("Evan Bagelmeister"),
-                &is_mint,
-            ],
+) -> Result<Vec<Uuid>, (Status, Json<ErrorResponse>)> {
+    let mut values = vec![];
+    let predicate = rule_predicate(&stage.selection_rule, &mut values);
+    let query = conn.query(
+        format!(
+            r#"
+                select id
+                from nft n
+                where {}
+                 and not n.withdrawn
+                 and {}
+                order by id"#,
+            can_become(NftState::Reserved),
+            predicate
         )
-    } else if let Some(att_type) = &stage.attribute_type {
-        if let Some(att_value) = &stage.attribute_value {
-            log::info!(
-                "Stage: {} {}/{} - {}",
-                stage.code,
-                att_type,
-                att_value,
-                wallet_address
-            );
-            let select_stmt = r#"                                
-                                update nft set reserved=true, reserved_to_wallet_address=$1 ,reserved_until=$2, in_mint_run=$6
-                                where id in (
-                                    select id as available
-                                    from nft n, json_array_elements(n.meta_data -> 'attributes' ) att
-                                    where assigned = false 
-                                     and (reserved=false or reserved_until < now())
-                                     and  att ->> 'trait_type' = $3 and att ->> 'value' = $4
-                                     and has_submit_error=false
-                                     and in_process=false
-                                    order by random()
-                                    limit $5
-                                ) returning id,meta_data "#;
-            let stmt_reserve_nft: Statement = conn.prepare(select_stmt).unwrap();
-            conn.query(
-                &stmt_reserve_nft,
-                &[
-                    &String::from(wallet_address),
-                    pg_ts,
-                    &att_type,
-                    &att_value,
-                    &amount,
-                    &is_mint,
-                ],
-            )
-        } else {
-            return Err((
+        .as_str(),
+        &sql_params(&values),
+    );
+    query
+        .map(|rows| rows.iter().map(|r| r.get(0)).collect::<Vec<Uuid>>())
+        .map_err(|db_err| {
+            log::error!("stage_candidates: {}", db_err.to_string());
+            (
                 Status::new(500),
                 Json(ErrorResponse {
                     code: 500,
-                    message: format!("Stage: {} is misconfigured", stage.code),
+                    message: format!("{}", db_err),
+                    errors: None,
                 }),
-            ));
-        }
-    } else {
-        log::info!("Stage: {} - {}", stage.code, wallet_address);
-        let select_stmt = r#"        
-                            update nft set reserved=true, reserved_to_wallet_address=$1 ,reserved_until=$2, in_mint_run=$4
-                            where id in (
-                                select id as available
-                                from nft
-                                where assigned = false 
-                                and (reserved=false or reserved_until < now())                   
-                                and has_submit_error=false
-                                and in_process=false
-                                order by random()
-                                limit $3
-                            ) returning id,meta_data "#;
-        let stmt_reserve_nft: Statement = conn.prepare(select_stmt).unwrap();
-        conn.query(
-            &stmt_reserve_nft,
-            &[&String::from(wallet_address), pg_ts, &amount, &is_mint],
-        )
-    };
-    query.map_err(|db_err| {
+            )
+        })
+}
+/// reserve up to `amount` NFTs from the stage, in the wallet's selection order, recording each reservation.
+/// candidates locked by a concurrent reservation are skipped.
+pub fn do_reservation_in_stage<C: GenericClient>(
+    conn: &mut C,
+    stage: &Stage,
+    wallet_address: &str,
+    salt: &str,
+    amount: i64,
+    is_mint: bool,
+    reserved_until: &DateTime<Utc>,
+) -> Result<Vec<Row>, (Status, Json<ErrorResponse>)> {
+    let candidates = stage_candidates(conn, stage)?;
+    log::info!(
+        "Stage: {} - {} candidates - {}",
+        stage.code,
+        candidates.len(),
+        wallet_address
+    );
+    let db_error = |db_err: Error| {
         log::error!("do_reservation_in_stage: {}", db_err.to_string());
         (
             Status::new(500),
             Json(ErrorResponse {
                 code: 500,
                 message: format!("{}", db_err),
+                errors: None,
             }),
         )
-    })
+    };
+    let stmt_reserve_nft: Statement = conn
+        .prepare(
+            format!(
+                r#"
+            update nft set status='reserved', reserved_to_wallet_address=$1 ,reserved_until=$2, in_mint_run=$3, stage=$5
+            where id in (
+                select id
+                from nft
+                where id = $4
+                 and not withdrawn
+                 and {}
+                for update skip locked
+            ) returning id,meta_data,name "#,
+                can_become(NftState::Reserved)
+            )
+            .as_str(),
+        )
+        .map_err(db_error)?;
+    let mut reserved: Vec<Row> = Vec::new();
+    for pick in SelectionOrder::new(wallet_address, salt, &candidates) {
+        if reserved.len() as i64 >= amount {
+            break;
+        }
+        let rows = conn
+            .query(
+                &stmt_reserve_nft,
+                &[
+                    &String::from(wallet_address),
+                    reserved_until,
+                    &is_mint,
+                    &pick.nft_id,
+                    &stage.id,
+                ],
+            )
+            .map_err(db_error)?;
+        if !rows.is_empty() {
+            record_reservation(conn, &pick, wallet_address, stage.id, reserved_until)
+                .map_err(db_error)?;
+        }
+        reserved.extend(rows);
+    }
+    Ok(reserved)
+}
+
+/// how each NFT reserved to the wallet was picked, newest first. the salt is shared by every stage, so it is only
+/// shown once none of them is pending or open
+pub fn selection_proofs(
+    conn: &mut Client,
+    wallet_address: &str,
+    salt: &str,
+) -> Result<Vec<SelectionProof>, Error> {
+    let drop_closed: bool = conn
+        .query_one(
+            "select not exists(select 1 from stage_whitelist where stage_close is null or stage_close > now())",
+            &[],
+        )?
+        .get(0);
+    let rows = conn.query(
+        r#"select r.nft_reserved, n.name, s.code, r.selection_seed, r.candidate_count,
+                r.candidates_hash, r.selection_index, r.created_on
+            from NFT_Reservation r
+            join NFT n on n.id = r.nft_reserved
+            join stage_whitelist s on s.id = r.stage
+            where r.wallet_address = $1 and r.selection_seed is not null
+            order by r.created_on desc"#,
+        &[&String::from(wallet_address)],
+    )?;
+    Ok(rows
+        .iter()
+        .map(|r| SelectionProof {
+            wallet_address: wallet_address.to_string(),
+            salt: drop_closed.then(|| salt.to_string()),
+            salt_hash: salt_hash(salt),
+            seed: r.get(3),
+            stage_code: r.get::<_, String>(2).trim().to_string(),
+            candidate_count: r.get::<_, i32>(4) as usize,
+            candidates_hash: r.get(5),
+            index: r.get::<_, i32>(6) as usize,
+            nft_id: r.get(0),
+            nft_name: r.get(1),
+            reserved_on: r.get(7),
+        })
+        .collect())
+}
+
+/// record a nonce from a signed request. false if it has been used already
+pub fn use_nonce(conn: &mut Client, nonce: &str, issued_at: DateTime<Utc>) -> Result<bool, Error> {
+    let inserted = conn.execute(
+        "insert into used_nonce (nonce, issued_at) values ($1, $2) on conflict (nonce) do nothing",
+        &[&nonce, &issued_at],
+    )?;
+    Ok(inserted == 1)
+}
+
+/// forget nonces issued before `before`. their timestamps are no longer accepted
+pub fn prune_nonces(conn: &mut Client, before: DateTime<Utc>) -> Result<u64, Error> {
+    conn.execute("delete from used_nonce where issued_at < $1", &[&before])
 }
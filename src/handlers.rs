@@ -1,3 +1,4 @@
+pub mod admin;
 pub mod mint;
 pub mod nft;
 pub mod reservation;
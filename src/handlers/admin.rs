@@ -0,0 +1,366 @@
+use crate::auth::{is_valid_address, AdminScope, Signed};
+use crate::db::{
+    close_stage, create_stage, get_stage, reconcile_whitelist, set_wallet_allocation, update_stage,
+    upsert_wallet_allocations,
+};
+use crate::models::{Stage, WalletStageAllocation, WhitelistCorrection};
+use crate::requests::{
+    ErrorResponse, NewStageRequest, UpdateStageRequest, WalletAllocationRequest,
+    WhitelistUploadRequest,
+};
+use crate::{NFTDatabase, ReservationState};
+use postgres::error::SqlState;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+
+fn db_error(e: postgres::Error) -> (Status, Json<ErrorResponse>) {
+    if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+        (
+            Status::new(409),
+            Json(ErrorResponse {
+                code: 409,
+                message: e.to_string(),
+                errors: None,
+            }),
+        )
+    } else {
+        log::error!("admin: {}", e.to_string());
+        (
+            Status::new(500),
+            Json(ErrorResponse {
+                code: 500,
+                message: e.to_string(),
+                errors: None,
+            }),
+        )
+    }
+}
+
+fn invalid(message: &str) -> (Status, Json<ErrorResponse>) {
+    (
+        Status::new(422),
+        Json(ErrorResponse {
+            code: 422,
+            message: message.to_string(),
+            errors: None,
+        }),
+    )
+}
+
+fn stage_not_found() -> (Status, Json<ErrorResponse>) {
+    (
+        Status::new(404),
+        Json(ErrorResponse {
+            code: 404,
+            message: "stage not found".to_string(),
+            errors: None,
+        }),
+    )
+}
+
+#[post("/stage", format = "json", data = "<stage_in>")]
+async fn new_stage(
+    conn: NFTDatabase,
+    signature: Signed<AdminScope>,
+    state: &State<ReservationState>,
+    stage_in: Json<NewStageRequest>,
+) -> (Status, Result<Json<Stage>, Json<ErrorResponse>>) {
+    let stage_in_stuff = stage_in.into_inner();
+    let stage_in_json = serde_json::to_string(&stage_in_stuff).unwrap();
+    if let Err(e) = signature.verify(&stage_in_json, state, &conn).await {
+        return (e.0, Err(e.1));
+    }
+    if stage_in_stuff.code.is_empty() || stage_in_stuff.code.len() > 20 {
+        let e = invalid("Stage code must be between 1 and 20 characters");
+        return (e.0, Err(e.1));
+    }
+    if let Some(Err(msg)) = stage_in_stuff.selection_rule.as_ref().map(|r| r.validate()) {
+        let e = invalid(&msg);
+        return (e.0, Err(e.1));
+    }
+    match conn.run(move |c| create_stage(c, &stage_in_stuff)).await {
+        Ok(stage) => (Status::new(201), Ok(Json(stage))),
+        Err(e) => {
+            let e = db_error(e);
+            (e.0, Err(e.1))
+        }
+    }
+}
+
+#[patch("/stage/<code>", format = "json", data = "<stage_in>")]
+async fn edit_stage(
+    conn: NFTDatabase,
+    signature: Signed<AdminScope>,
+    state: &State<ReservationState>,
+    code: String,
+    stage_in: Json<UpdateStageRequest>,
+) -> (Status, Result<Json<Stage>, Json<ErrorResponse>>) {
+    let stage_in_stuff = stage_in.into_inner();
+    let stage_in_json = serde_json::to_string(&stage_in_stuff).unwrap();
+    if let Err(e) = signature.verify(&stage_in_json, state, &conn).await {
+        return (e.0, Err(e.1));
+    }
+    if let Some(Err(msg)) = stage_in_stuff.selection_rule.as_ref().map(|r| r.validate()) {
+        let e = invalid(&msg);
+        return (e.0, Err(e.1));
+    }
+    match conn
+        .run(move |c| update_stage(c, &code, &stage_in_stuff))
+        .await
+    {
+        Ok(Some(stage)) => (Status::new(200), Ok(Json(stage))),
+        Ok(None) => {
+            let e = stage_not_found();
+            (e.0, Err(e.1))
+        }
+        Err(e) => {
+            let e = db_error(e);
+            (e.0, Err(e.1))
+        }
+    }
+}
+
+#[post("/stage/<code>/close")]
+async fn close(
+    conn: NFTDatabase,
+    signature: Signed<AdminScope>,
+    state: &State<ReservationState>,
+    code: String,
+) -> (Status, Result<Json<Stage>, Json<ErrorResponse>>) {
+    let ss = format!("{{\"stage\":\"{}\"}}", code);
+    if let Err(e) = signature.verify(&ss, state, &conn).await {
+        return (e.0, Err(e.1));
+    }
+    match conn.run(move |c| close_stage(c, &code)).await {
+        Ok(Some(stage)) => (Status::new(200), Ok(Json(stage))),
+        Ok(None) => {
+            let e = stage_not_found();
+            (e.0, Err(e.1))
+        }
+        Err(e) => {
+            let e = db_error(e);
+            (e.0, Err(e.1))
+        }
+    }
+}
+
+/// recompute the stage's whitelist reserved/assigned counts from its NFTs.
+/// the signature is of `{"stage":"<code>"}`
+#[post("/stage/<code>/whitelist/reconcile")]
+async fn reconcile(
+    conn: NFTDatabase,
+    signature: Signed<AdminScope>,
+    state: &State<ReservationState>,
+    code: String,
+) -> (
+    Status,
+    Result<Json<Vec<WhitelistCorrection>>, Json<ErrorResponse>>,
+) {
+    let ss = format!("{{\"stage\":\"{}\"}}", code);
+    if let Err(e) = signature.verify(&ss, state, &conn).await {
+        return (e.0, Err(e.1));
+    }
+    conn.run(move |c| match get_stage(c, &code) {
+        Ok(Some(stage)) => match reconcile_whitelist(c, stage.id) {
+            Ok(corrections) => {
+                if !corrections.is_empty() {
+                    log::warn!(
+                        "Stage {}: corrected whitelist counts for {} wallets",
+                        stage.code,
+                        corrections.len()
+                    );
+                }
+                (Status::new(200), Ok(Json(corrections)))
+            }
+            Err(e) => {
+                let e = db_error(e);
+                (e.0, Err(e.1))
+            }
+        },
+        Ok(None) => {
+            let e = stage_not_found();
+            (e.0, Err(e.1))
+        }
+        Err(e) => (e.0, Err(e.1)),
+    })
+    .await
+}
+
+/// parse `wallet_address[,allocation_count]` lines. the allocation defaults to 1, and a header line is skipped
+fn parse_whitelist_csv(csv: &str) -> Result<Vec<WalletAllocationRequest>, String> {
+    let mut wallets: Vec<WalletAllocationRequest> = Vec::new();
+    for (line_no, line) in csv.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || (line_no == 0 && !line.starts_with("terra")) {
+            continue;
+        }
+        let mut fields = line.split(',').map(|f| f.trim());
+        let wallet_address = fields.next().unwrap_or_default().to_string();
+        let allocation_count = match fields.next() {
+            Some(count) if !count.is_empty() => count
+                .parse::<i32>()
+                .map_err(|e| format!("line {}: {}", line_no + 1, e))?,
+            _ => 1,
+        };
+        wallets.push(WalletAllocationRequest {
+            wallet_address,
+            allocation_count,
+        });
+    }
+    Ok(wallets)
+}
+
+async fn upload_whitelist(
+    conn: NFTDatabase,
+    code: String,
+    wallets: Vec<WalletAllocationRequest>,
+) -> (
+    Status,
+    Result<Json<Vec<WalletStageAllocation>>, Json<ErrorResponse>>,
+) {
+    let invalid_wallets = wallets
+        .iter()
+        .filter(|w| is_valid_address(&w.wallet_address).is_err() || w.allocation_count < 0)
+        .map(|w| w.wallet_address.clone())
+        .collect::<Vec<String>>();
+    if !invalid_wallets.is_empty() {
+        let e = invalid(&format!(
+            "Invalid wallet address or allocation: {}",
+            invalid_wallets.join(", ")
+        ));
+        return (e.0, Err(e.1));
+    }
+    conn.run(move |c| match get_stage(c, &code) {
+        Ok(Some(stage)) => match upsert_wallet_allocations(c, stage.id, &wallets) {
+            Ok(allocations) => (Status::new(200), Ok(Json(allocations))),
+            Err(e) => {
+                let e = db_error(e);
+                (e.0, Err(e.1))
+            }
+        },
+        Ok(None) => {
+            let e = stage_not_found();
+            (e.0, Err(e.1))
+        }
+        Err(e) => (e.0, Err(e.1)),
+    })
+    .await
+}
+
+#[post("/stage/<code>/whitelist", format = "json", data = "<whitelist_in>")]
+async fn whitelist_json(
+    conn: NFTDatabase,
+    signature: Signed<AdminScope>,
+    state: &State<ReservationState>,
+    code: String,
+    whitelist_in: Json<WhitelistUploadRequest>,
+) -> (
+    Status,
+    Result<Json<Vec<WalletStageAllocation>>, Json<ErrorResponse>>,
+) {
+    let whitelist_in_stuff = whitelist_in.into_inner();
+    let whitelist_in_json = serde_json::to_string(&whitelist_in_stuff).unwrap();
+    if let Err(e) = signature.verify(&whitelist_in_json, state, &conn).await {
+        return (e.0, Err(e.1));
+    }
+    upload_whitelist(conn, code, whitelist_in_stuff.wallets).await
+}
+
+/// the signature is over the CSV body as sent
+#[post(
+    "/stage/<code>/whitelist",
+    format = "text/csv",
+    data = "<whitelist_in>",
+    rank = 2
+)]
+async fn whitelist_csv(
+    conn: NFTDatabase,
+    signature: Signed<AdminScope>,
+    state: &State<ReservationState>,
+    code: String,
+    whitelist_in: String,
+) -> (
+    Status,
+    Result<Json<Vec<WalletStageAllocation>>, Json<ErrorResponse>>,
+) {
+    if let Err(e) = signature.verify(&whitelist_in, state, &conn).await {
+        return (e.0, Err(e.1));
+    }
+    match parse_whitelist_csv(&whitelist_in) {
+        Ok(wallets) => upload_whitelist(conn, code, wallets).await,
+        Err(message) => {
+            let e = invalid(&message);
+            (e.0, Err(e.1))
+        }
+    }
+}
+
+#[patch(
+    "/stage/<code>/whitelist/<address>",
+    format = "json",
+    data = "<allocation_in>"
+)]
+async fn adjust_allocation(
+    conn: NFTDatabase,
+    signature: Signed<AdminScope>,
+    state: &State<ReservationState>,
+    code: String,
+    address: String,
+    allocation_in: Json<WalletAllocationRequest>,
+) -> (
+    Status,
+    Result<Json<WalletStageAllocation>, Json<ErrorResponse>>,
+) {
+    let allocation_in_stuff = allocation_in.into_inner();
+    let allocation_in_json = serde_json::to_string(&allocation_in_stuff).unwrap();
+    if let Err(e) = signature.verify(&allocation_in_json, state, &conn).await {
+        return (e.0, Err(e.1));
+    }
+    if let Err(e) = is_valid_address(&address) {
+        return (Status::new(403), Err(e));
+    }
+    if allocation_in_stuff.wallet_address != address || allocation_in_stuff.allocation_count < 0 {
+        let e =
+            invalid("wallet_address must match the URL, and allocation_count can't be negative");
+        return (e.0, Err(e.1));
+    }
+    conn.run(move |c| match get_stage(c, &code) {
+        Ok(Some(stage)) => {
+            match set_wallet_allocation(c, stage.id, &address, allocation_in_stuff.allocation_count)
+            {
+                Ok(Some(allocation)) => (Status::new(200), Ok(Json(allocation))),
+                Ok(None) => (
+                    Status::new(404),
+                    Err(Json(ErrorResponse {
+                        code: 404,
+                        message: "wallet is not on the stage's whitelist".to_string(),
+                        errors: None,
+                    })),
+                ),
+                Err(e) => {
+                    let e = db_error(e);
+                    (e.0, Err(e.1))
+                }
+            }
+        }
+        Ok(None) => {
+            let e = stage_not_found();
+            (e.0, Err(e.1))
+        }
+        Err(e) => (e.0, Err(e.1)),
+    })
+    .await
+}
+
+pub fn get_routes() -> Vec<Route> {
+    routes![
+        new_stage,
+        edit_stage,
+        close,
+        whitelist_json,
+        whitelist_csv,
+        adjust_allocation,
+        reconcile
+    ]
+}
@@ -1,12 +1,15 @@
-use crate::auth::{generate_signature, is_valid_address, verify_signature, SignatureB64};
+use crate::auth::{generate_signature, is_valid_address, OracleScope, Signed, UserScope};
+use crate::chain::{check_signed_mint, decode_signed_tx, is_tx_hash, signed_mint_msg};
 use crate::db::{
-    get_nft, nft_assign_owner, nft_assign_tx_result, set_tx_for_nft, set_tx_hash_for_nft,
+    get_nft, nft_assign_owner, nft_assign_tx_result, park_tx_hash_for_nft, set_tx_for_nft,
+    set_tx_hash_for_nft,
 };
 use crate::models::NFT;
 use crate::requests::{
     AssignHashRequest, AssignOwner, AssignSignedTxRequest, ErrorResponse, Metadata,
-    MetadataResponse, NewReservationResponse, ReservationTxResultRequest,
+    MetadataResponse, NewReservationResponse, NftState, ReservationTxResultRequest,
 };
+use crate::tasks::tx_verifier::{verify_tx_hash, TxHashVerification, Verified};
 use crate::{NFTDatabase, ReservationState};
 use chrono::Utc;
 use rocket::http::Status;
@@ -16,35 +19,34 @@ use serde_json::Value;
 use terra_rust_api::PrivateKey;
 use uuid::Uuid;
 
-/// Ensures that NFT is reserved, and the reservation has not expired
-///
+/// Ensures that NFT can be submitted for minting. It is reserved and the reservation has not expired,
+/// or the last submission failed and is being retried
 fn validate_reservation(nft: &NFT) -> (Status, Result<bool, Json<ErrorResponse>>) {
-    if !nft.reserved {
-        (
-            Status::new(401),
-            Err(Json(ErrorResponse {
-                code: 401,
-                message: String::from("Not Reserved"),
-            })),
-        )
-    } else if nft.has_submit_error {
-        log::info!(
-            "{} - Reservation with error being retried - {:?}",
-            nft.id,
-            nft.reserved_until
-        );
-        (Status::new(200), Ok(true))
-    } else if nft.reserved_until.is_none() || nft.reserved_until.unwrap() < Utc::now() {
-        (
-            Status::new(401),
-            Err(Json(ErrorResponse {
-                code: 401,
-                message: String::from("Reservation has expired"),
-            })),
-        )
-    } else {
-        (Status::new(200), Ok(true))
-    }
+    let message = match nft.status.transition(NftState::Submitted) {
+        Ok(_) => {
+            if nft.status == NftState::Errored {
+                log::info!(
+                    "{} - Reservation with error being retried - {:?}",
+                    nft.id,
+                    nft.reserved_until
+                );
+            }
+            return (Status::new(200), Ok(true));
+        }
+        Err(_) if nft.status == NftState::Available && nft.reserved_until.is_some() => {
+            String::from("Reservation has expired")
+        }
+        Err(_) if nft.status == NftState::Available => String::from("Not Reserved"),
+        Err(e) => e.to_string(),
+    };
+    (
+        Status::new(401),
+        Err(Json(ErrorResponse {
+            code: 401,
+            message,
+            errors: None,
+        })),
+    )
 }
 /// returns metadata for a given NFT, and a signature of it. with the wallet address embedded.
 ///  let hash_message = format!("{}/{}", info.sender, msg.attributes);
@@ -75,6 +77,7 @@ pub fn build_metadata_response(
             Err(Json(ErrorResponse {
                 code: 500,
                 message: e.to_string(),
+                errors: None,
             })),
         ),
     }
@@ -90,7 +93,7 @@ async fn options_signed_metadata(
 #[get("/<wallet>/<nft>")]
 async fn get_signed_metadata(
     conn: NFTDatabase,
-    signature: SignatureB64,
+    signature: Signed<UserScope>,
     state: &State<ReservationState>,
     wallet: String,
     nft: Uuid,
@@ -101,21 +104,11 @@ async fn get_signed_metadata(
     if let Err(e) = is_valid_address(&wallet) {
         return (Status::new(403), Err(e));
     }
-    let ss = format!("{{\"nft\":\"{}\"}}", nft.to_string());
+    let ss = format!("{{\"nft\":\"{}\"}}", nft);
 
     //  log::info!("{}", ss);
-    if let Err(e) = verify_signature(&ss, &signature, &state.verification_key) {
-        if state.debug_mode {
-            log::warn!("IGNORING SIGNATURES");
-        } else {
-            return (
-                Status::new(403),
-                Err(Json(ErrorResponse {
-                    code: 403,
-                    message: e.to_string(),
-                })),
-            );
-        }
+    if let Err(e) = signature.verify_wallet(&ss, &wallet, state, &conn).await {
+        return (e.0, Err(e.1));
     }
     let signing_key = &state.signing_key;
 
@@ -151,6 +144,7 @@ async fn get_signed_metadata(
                         Err(Json(ErrorResponse {
                             code: 401,
                             message: String::from("Invalid Reservation"),
+                            errors: None,
                         })),
                     )
                 }
@@ -160,6 +154,7 @@ async fn get_signed_metadata(
                     Err(Json(ErrorResponse {
                         code: 500,
                         message: String::from("NFT is not reserved"),
+                        errors: None,
                     })),
                 )
             }
@@ -169,6 +164,7 @@ async fn get_signed_metadata(
             Err(Json(ErrorResponse {
                 code: 500,
                 message: e.to_string(),
+                errors: None,
             })),
         ),
     }
@@ -181,79 +177,85 @@ async fn options_assign_txhash() -> rocket::response::status::Custom<String> {
 #[post("/hash", format = "json", data = "<assign_hash_request>")]
 async fn assign_txhash(
     conn: NFTDatabase,
-    signature: SignatureB64,
+    signature: Signed<UserScope>,
     state: &State<ReservationState>,
     assign_hash_request: Json<AssignHashRequest>,
 ) -> (Status, Result<Json<bool>, Json<ErrorResponse>>) {
     let assign_hash_request_stuff = assign_hash_request.into_inner();
     let assign_hash_request_json = serde_json::to_string(&assign_hash_request_stuff).unwrap();
 
-    if let Err(e) = verify_signature(
-        &assign_hash_request_json,
-        &signature,
-        &state.verification_key,
-    ) {
-        if state.debug_mode {
-            log::warn!("IGNORING SIGNATURES");
-        } else {
-            return (
-                Status::new(403),
-                Err(Json(ErrorResponse {
-                    code: 403,
-                    message: e.to_string(),
-                })),
-            );
-        }
+    if let Err(e) = signature
+        .verify_wallet(
+            &assign_hash_request_json,
+            &assign_hash_request_stuff.wallet_address,
+            state,
+            &conn,
+        )
+        .await
+    {
+        return (e.0, Err(e.1));
     }
     let nft_id = assign_hash_request_stuff.nft_id;
-    conn.run(move |c| {
-        let nft_r = get_nft(c, &nft_id);
-
-        match nft_r {
-            Ok(nft_full) => {
-                if let Some(reserved_to) = &nft_full.reserved_to_wallet_address {
-                    if reserved_to.eq(&assign_hash_request_stuff.wallet_address)
-                        && nft_full.nft_lite.reserved
-                    {
-                        match set_tx_hash_for_nft(c, &nft_id, &assign_hash_request_stuff.tx_hash) {
-                            Ok(n) => (Status::new(200), Ok(Json(n == 1))),
-                            Err(e) => (
-                                Status::new(500),
-                                Err(Json(ErrorResponse {
-                                    message: e.to_string(),
-                                    code: 500,
-                                })),
-                            ),
-                        }
-                    } else {
-                        (
-                            Status::new(500),
-                            Err(Json(ErrorResponse {
-                                code: 500,
-                                message: String::from("NFT is not reserved to wallet"),
-                            })),
-                        )
-                    }
-                } else {
-                    (
-                        Status::new(500),
-                        Err(Json(ErrorResponse {
-                            code: 500,
-                            message: String::from("NFT is not reserved"),
-                        })),
-                    )
-                }
+    let wallet = assign_hash_request_stuff.wallet_address;
+    let tx_hash = assign_hash_request_stuff.tx_hash;
+    let error = |code: u16, message: String| {
+        (
+            Status::new(code),
+            Err(Json(ErrorResponse {
+                code,
+                message,
+                errors: None,
+            })),
+        )
+    };
+    if !is_tx_hash(&tx_hash) {
+        return error(422, String::from("tx_hash should be 64 hex digits"));
+    }
+    let nft_full = match conn.run(move |c| get_nft(c, &nft_id)).await {
+        Ok(nft_full) => nft_full,
+        Err(e) => return error(500, e.to_string()),
+    };
+    match &nft_full.reserved_to_wallet_address {
+        Some(reserved_to) if reserved_to.eq(&wallet) => {}
+        Some(_) => return error(500, String::from("NFT is not reserved to wallet")),
+        None => return error(500, String::from("NFT is not reserved")),
+    }
+    if let (status, Err(e)) = validate_reservation(&nft_full.nft_lite) {
+        return (status, Err(e));
+    }
+    let parked = match state.tx_hash_verification {
+        TxHashVerification::Off => false,
+        _ => match verify_tx_hash(
+            &state.tx_verification_lcd,
+            &state.chain,
+            &state.nft_contract,
+            &wallet,
+            &tx_hash,
+        )
+        .await
+        {
+            Verified::Vouched => false,
+            Verified::Refused(message) => return error(400, message),
+            Verified::NotFound(e) if state.tx_hash_verification == TxHashVerification::Reject => {
+                return error(400, format!("tx {} not found: {}", tx_hash, e))
             }
-            Err(e) => (
-                Status::new(500),
-                Err(Json(ErrorResponse {
-                    code: 500,
-                    message: e.to_string(),
-                })),
-            ),
-        }
-    })
-    .await
+            Verified::NotFound(_) => true,
+        },
+    };
+    let verify_by = Utc::now() + state.tx_verification_timeout;
+    let updated = conn
+        .run(move |c| {
+            if parked {
+                park_tx_hash_for_nft(c, &nft_id, &tx_hash, verify_by)
+            } else {
+                set_tx_hash_for_nft(c, &nft_id, &tx_hash)
+            }
+        })
+        .await;
+    match updated {
+        Ok(n) => (Status::new(200), Ok(Json(n == 1))),
+        Err(e) => error(500, e.to_string()),
+    }
 }
 
 #[options("/tx")]
@@ -263,80 +265,85 @@ async fn options_assign_tx() -> rocket::response::status::Custom<String> {
 #[post("/tx", format = "json", data = "<assign_hash_request>")]
 async fn assign_tx(
     conn: NFTDatabase,
-    signature: SignatureB64,
+    signature: Signed<UserScope>,
     state: &State<ReservationState>,
     assign_hash_request: Json<AssignSignedTxRequest>,
 ) -> (Status, Result<Json<bool>, Json<ErrorResponse>>) {
     let assign_hash_request_stuff = assign_hash_request.into_inner();
     let assign_hash_request_json = serde_json::to_string(&assign_hash_request_stuff).unwrap();
 
-    if let Err(e) = verify_signature(
-        &assign_hash_request_json,
-        &signature,
-        &state.verification_key,
-    ) {
-        if state.debug_mode {
-            log::warn!("IGNORING SIGNATURES");
-        } else {
-            return (
-                Status::new(403),
-                Err(Json(ErrorResponse {
-                    code: 403,
-                    message: e.to_string(),
-                })),
-            );
-        }
+    if let Err(e) = signature
+        .verify_wallet(
+            &assign_hash_request_json,
+            &assign_hash_request_stuff.wallet_address,
+            state,
+            &conn,
+        )
+        .await
+    {
+        return (e.0, Err(e.1));
     }
     let nft_id = assign_hash_request_stuff.nft_id;
-    conn.run(move |c| {
-        let nft_r = get_nft(c, &nft_id);
+    let wallet = assign_hash_request_stuff.wallet_address;
+    let error = |code: u16, message: String| {
+        (
+            Status::new(code),
+            Err(Json(ErrorResponse {
+                code,
+                message,
+                errors: None,
+            })),
+        )
+    };
+    let packet: Value = match serde_json::from_str(&assign_hash_request_stuff.signed_tx) {
+        Ok(packet) => packet,
+        Err(e) => return error(400, format!("signed_tx is not a transaction: {}", e)),
+    };
+    let nft_full = match conn.run(move |c| get_nft(c, &nft_id)).await {
+        Ok(nft_full) => nft_full,
+        Err(e) => return error(500, e.to_string()),
+    };
+    match &nft_full.reserved_to_wallet_address {
+        Some(reserved_to) if reserved_to.eq(&wallet) => {}
+        Some(_) => return error(500, String::from("NFT is not reserved to wallet")),
+        None => return error(500, String::from("NFT is not reserved")),
+    }
+    if let (status, Err(e)) = validate_reservation(&nft_full.nft_lite) {
+        return (status, Err(e));
+    }
+    if let Err(message) = check_signed_tx(
+        &packet,
+        &wallet,
+        &nft_full.meta_data,
+        &state.signing_key,
+        &state.nft_contract,
+    ) {
+        return error(400, message);
+    }
+    match conn.run(move |c| set_tx_for_nft(c, &nft_id, &packet)).await {
+        Ok(n) => (Status::new(200), Ok(Json(n == 1))),
+        Err(e) => error(500, e.to_string()),
+    }
+}
 
-        match nft_r {
-            Ok(nft_full) => {
-                if let Some(reserved_to) = &nft_full.reserved_to_wallet_address {
-                    if reserved_to.eq(&assign_hash_request_stuff.wallet_address)
-                        && nft_full.nft_lite.reserved
-                    {
-                        match set_tx_for_nft(c, &nft_id, &assign_hash_request_stuff.signed_tx) {
-                            Ok(n) => (Status::new(200), Ok(Json(n == 1))),
-                            Err(e) => (
-                                Status::new(500),
-                                Err(Json(ErrorResponse {
-                                    message: e.to_string(),
-                                    code: 500,
-                                })),
-                            ),
-                        }
-                    } else {
-                        (
-                            Status::new(500),
-                            Err(Json(ErrorResponse {
-                                code: 500,
-                                message: String::from("NFT is not reserved to wallet"),
-                            })),
-                        )
-                    }
-                } else {
-                    (
-                        Status::new(500),
-                        Err(Json(ErrorResponse {
-                            code: 500,
-                            message: String::from("NFT is not reserved"),
-                        })),
-                    )
-                }
-            }
-            Err(e) => (
-                Status::new(500),
-                Err(Json(ErrorResponse {
-                    code: 500,
-                    message: e.to_string(),
-                })),
-            ),
-        }
-    })
-    .await
+/// the transaction in a signed packet, if it does nothing but mint this NFT from the wallet it's reserved to,
+/// with the metadata signed by `signing_key`
+pub fn check_signed_tx(
+    packet: &Value,
+    wallet: &str,
+    meta_data: &Value,
+    signing_key: &PrivateKey,
+    nft_contract: &str,
+) -> Result<Value, String> {
+    let tx = decode_signed_tx(packet)?;
+    let metadata = build_metadata_response(wallet, signing_key, meta_data)
+        .1
+        .map_err(|e| e.message.clone())?;
+    let expected = signed_mint_msg(&metadata.attributes, &metadata.signature);
+    check_signed_mint(&tx, nft_contract, wallet, &expected)?;
+    Ok(tx)
 }
+
 #[options("/tx_result")]
 async fn options_assign_tx_result() -> rocket::response::status::Custom<String> {
     rocket::response::status::Custom(Status::new(200), "OK".into())
@@ -344,37 +351,23 @@ async fn options_assign_tx_result() -> rocket::response::status::Custom<String>
 #[post("/tx_result", format = "json", data = "<hash_result>")]
 async fn assign_tx_result(
     conn: NFTDatabase,
-    signature: SignatureB64,
+    signature: Signed<OracleScope>,
     state: &State<ReservationState>,
     hash_result: Json<ReservationTxResultRequest>,
 ) -> (Status, Result<Json<bool>, Json<ErrorResponse>>) {
     let hash_result_stuff = hash_result.into_inner();
     let hash_result_stuff_json = serde_json::to_string(&hash_result_stuff).unwrap();
     log::info!("hash_result:{}", hash_result_stuff_json);
-    if let Err(e) = verify_signature(&hash_result_stuff_json, &signature, &state.verification_key) {
-        if state.debug_mode {
-            log::warn!("IGNORING SIGNATURES");
-        } else {
-            return (
-                Status::new(403),
-                Err(Json(ErrorResponse {
-                    code: 403,
-                    message: e.to_string(),
-                })),
-            );
-        }
+    if let Err(e) = signature
+        .verify(&hash_result_stuff_json, state, &conn)
+        .await
+    {
+        return (e.0, Err(e.1));
     }
     //  let tx = hash_result_stuff.tx;
+    let policy = state.retry_policy.clone();
     conn.run(move |c| {
-        let assign_result = nft_assign_tx_result(
-            c,
-            hash_result_stuff.wallet_address,
-            hash_result_stuff.tx,
-            hash_result_stuff.success,
-            hash_result_stuff.assigned_on,
-            hash_result_stuff.error,
-            hash_result_stuff.token_id,
-        );
+        let assign_result = nft_assign_tx_result(c, hash_result_stuff, &policy);
 
         match assign_result {
             Ok(rows_updated) => {
@@ -386,6 +379,7 @@ async fn assign_tx_result(
                         Err(Json(ErrorResponse {
                             code: 500,
                             message: String::from("TX not found"),
+                            errors: None,
                         })),
                     )
                 }
@@ -395,6 +389,7 @@ async fn assign_tx_result(
                 Err(Json(ErrorResponse {
                     code: 500,
                     message: e.to_string(),
+                    errors: None,
                 })),
             ),
         }
@@ -409,29 +404,18 @@ async fn options_assign_owner() -> rocket::response::status::Custom<String> {
 #[post("/assign-owner", format = "json", data = "<assign_owner>")]
 async fn assign_owner(
     conn: NFTDatabase,
-    signature: SignatureB64,
+    signature: Signed<OracleScope>,
     state: &State<ReservationState>,
     assign_owner: Json<AssignOwner>,
 ) -> (Status, Result<Json<bool>, Json<ErrorResponse>>) {
     let assign_owner_stuff = assign_owner.into_inner();
     let assign_owner_stuff_json = serde_json::to_string(&assign_owner_stuff).unwrap();
     log::debug!("assign_assign_owner:{}", assign_owner_stuff_json);
-    if let Err(e) = verify_signature(
-        &assign_owner_stuff_json,
-        &signature,
-        &state.verification_key,
-    ) {
-        if state.debug_mode {
-            log::warn!("IGNORING SIGNATURES");
-        } else {
-            return (
-                Status::new(403),
-                Err(Json(ErrorResponse {
-                    code: 403,
-                    message: e.to_string(),
-                })),
-            );
-        }
+    if let Err(e) = signature
+        .verify(&assign_owner_stuff_json, state, &conn)
+        .await
+    {
+        return (e.0, Err(e.1));
     }
     //  let tx = hash_result_stuff.tx;
     conn.run(move |c| {
@@ -451,6 +435,7 @@ async fn assign_owner(
                         Err(Json(ErrorResponse {
                             code: 500,
                             message: String::from("token/wallet not found"),
+                            errors: None,
                         })),
                     )
                 }
@@ -460,6 +445,7 @@ async fn assign_owner(
                 Err(Json(ErrorResponse {
                     code: 500,
                     message: e.to_string(),
+                    errors: None,
                 })),
             ),
         }
@@ -1,14 +1,19 @@
-use crate::auth::{verify_signature, SignatureB64};
+use crate::auth::{is_valid_address, AdminScope, Signed};
 use crate::requests::NewNFTResponse;
 use crate::NFTDatabase;
 use crate::{requests, ReservationState};
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 
-use crate::db::{get_nft_stat, get_stages, is_name_available};
+use crate::db::{
+    delete_nft, get_nft_lite, get_nft_stat, get_nft_tally, get_stages, get_whitelist_stats,
+    import_nfts, insert_nft, is_name_available, list_nfts, nft_audit_trail, set_nft_withdrawn,
+    trait_stats, update_nft,
+};
 use crate::requests::{
-    ErrorResponse, NFTStageTallyStat, NFTTallyResponse, NFTTallyStat, NameNFTResponse,
+    ErrorResponse, FieldError, NFTStageTallyStat, NFTTallyResponse, NameNFTResponse, NftAuditEntry,
+    NftImportRequest, NftImportResponse, NftListQuery, TraitStatsResponse, UpdateNFTRequest,
 };
-use postgres::Statement;
+use postgres::error::SqlState;
 use rocket::http::Status;
 use rocket::serde::json::Json;
 use rocket::{Route, State};
@@ -16,40 +21,19 @@ use serde_json::Value;
 use terra_rust_api::Terra;
 use uuid::Uuid;
 
-use crate::models::NFT;
+use crate::models::{AdminSignature, NftFull, NftPage, NFT};
 
 /// returns the status of the NFTs
 #[get("/")]
 async fn index(conn: NFTDatabase) -> (Status, Result<Json<NFTTallyResponse>, Json<ErrorResponse>>) {
-    match conn
-        .run(move |c| {
-            c.query_one(
-               r#"
-               select  sum(case assigned when true then 1 else 0 end) as assigned,
-                       sum(case reserved and reserved_until > now() and not assigned and not in_process when true then 1 else 0 end) as reserved,
-                       sum(case in_process when true then 1 else 0 end ) as in_process,
-                       sum(case not assigned and not in_process and (not reserved or reserved_until <= now()) when true then 1 else 0 end) as available
-               from nft"#,
-                &[],
-            )
-        })
-        .await
-    {
-        Ok(row) => {
-            let tally =NFTTallyResponse {
-                assigned: row.get(0),
-                reserved: row.get(1),
-                in_process: row.get(2),
-                available: row.get(3)
-            };
-
-            (Status::new(200), Ok(Json(tally)))
-        }
+    match conn.run(get_nft_tally).await {
+        Ok(tally) => (Status::new(200), Ok(Json(tally))),
         Err(e) => (
             Status::new(500),
             Err(Json(ErrorResponse {
                 code: 500,
                 message: format!("DB Error:{})", e),
+                errors: None,
             })),
         ),
     }
@@ -62,115 +46,108 @@ async fn get_stage_stats(
     Status,
     Result<Json<Vec<NFTStageTallyStat>>, Json<ErrorResponse>>,
 ) {
-    conn.run(move |mut c| match get_stages(&mut c) {
-        Ok(stages) => {
-            let stats = stages.iter().map(|s| {
-                let st = match get_nft_stat(c, &s.attribute_type, &s.attribute_value) {
-                    Ok(t) => t,
-                    Err(e) => {
-                        log::error!("Get State Stats: {}", e.1.message);
-                        NFTTallyStat {
-                            assigned: -1,
-                            reserved: -1,
-                            count: -1,
-                        }
-                    }
-                };
-
-                NFTStageTallyStat {
-                    stage_id: s.id,
-                    stage_code: s.code.clone(),
-                    stage_name: s.name.clone(),
-                    wallet_count: -1,
-                    stats: st,
-                }
-            });
-            (Status::new(200), Ok(Json(stats.collect::<Vec<_>>())))
+    let stats = conn
+        .run(move |c| {
+            let stages = get_stages(c)?;
+            let mut whitelists = get_whitelist_stats(c)?;
+            let now = Utc::now();
+            stages
+                .iter()
+                .map(|s| {
+                    Ok(NFTStageTallyStat {
+                        stage_id: s.id,
+                        stage_code: s.code.clone(),
+                        stage_name: s.name.clone(),
+                        stage_state: s.state_at(now),
+                        whitelist: whitelists.remove(&s.id).unwrap_or_default(),
+                        stats: get_nft_stat(c, &s.selection_rule)?,
+                    })
+                })
+                .collect::<Result<Vec<_>, (Status, Json<ErrorResponse>)>>()
+        })
+        .await;
+    match stats {
+        Ok(stats) => (Status::new(200), Ok(Json(stats))),
+        Err(e) => {
+            log::error!("Get Stage Stats: {}", e.1.message);
+            (e.0, Err(e.1))
         }
-        Err(e) => (e.0, Err(e.1)),
-    })
-    .await
+    }
+}
+/// every trait in the collection, with how rare it is and how many are left
+#[get("/traits")]
+async fn traits(
+    conn: NFTDatabase,
+) -> (
+    Status,
+    Result<Json<TraitStatsResponse>, Json<ErrorResponse>>,
+) {
+    match conn.run(trait_stats).await {
+        Ok(stats) => (Status::new(200), Ok(Json(stats))),
+        Err(e) => (
+            Status::new(500),
+            Err(Json(ErrorResponse {
+                code: 500,
+                message: format!("DB Error:{})", e),
+                errors: None,
+            })),
+        ),
+    }
+}
+/// browse the collection a page at a time, in name order. see `NftListQuery` for the filters
+#[get("/list?<query..>")]
+async fn list(
+    conn: NFTDatabase,
+    query: NftListQuery,
+) -> (Status, Result<Json<NftPage>, Json<ErrorResponse>>) {
+    if let Err(errors) = query.validate() {
+        return (
+            Status::new(422),
+            Err(Json(ErrorResponse {
+                code: 422,
+                message: String::from("Invalid filter"),
+                errors: Some(errors),
+            })),
+        );
+    }
+    if let Some(wallet) = &query.wallet {
+        if let Err(e) = is_valid_address(wallet) {
+            return (Status::new(403), Err(e));
+        }
+    }
+    match conn.run(move |c| list_nfts(c, &query)).await {
+        Ok(page) => (Status::new(200), Ok(Json(page))),
+        Err(e) => (
+            Status::new(500),
+            Err(Json(ErrorResponse {
+                code: 500,
+                message: format!("DB Error:{})", e),
+                errors: None,
+            })),
+        ),
+    }
 }
 #[get("/<id>")]
 async fn get_by_id(
     conn: NFTDatabase,
     id: Uuid,
 ) -> (Status, Result<Json<NFT>, Json<ErrorResponse>>) {
-    //    let uuid_id: Uuid = Uuid::from_str(&id).unwrap();
-    match conn
-        .run(move |c| c.query("Select id, name, assigned, reserved, has_submit_error,reserved_until, in_process from NFT where id=$1", &[&id]))
-        .await
-    {
-        Ok(results) => {
-            if let Some(row) = results.first() {
-                let now: DateTime<chrono::offset::Utc> = Utc::now();
-                let assigned:bool = row.get(2);
-                let in_process:bool = row.get(6);
-                if  assigned || in_process {
-                     (Status::new(200), Ok(Json(NFT {
-                        id: row.get(0),
-                        name: row.get(1),
-                        assigned: row.get(2),
-                        reserved: row.get(3),
-                        has_submit_error: row.get(4),
-                        reserved_until: row.get(5),
-                        in_process: row.get(6),
-                        txhash: Some(String::from("-hidden-"))
-                    })))
-                } else {
-                    let reserved_date_o: Option<DateTime<chrono::offset::Utc>> = row.get(5);
-                    if let Some(reserved_date) = reserved_date_o {
-                        if now < reserved_date {
-                         //   log::info!("In Reservation {} {}",now, reserved_date);
-                            (Status::new(200), Ok(Json(NFT {
-                                id: row.get(0),
-                                name: row.get(1),
-                                assigned: row.get(2),
-                                reserved: true,
-                                has_submit_error: row.get(4),
-                                reserved_until: Some(reserved_date),
-                                in_process: row.get(6),
-                                txhash: None
-                            })))
-                        } else {
-                            log::info!("Past Reservation {} {}",now, reserved_date);
-                            (Status::new(200), Ok(Json(NFT {
-                                id: row.get(0),
-                                name: row.get(1),
-                                assigned: row.get(2),
-                                reserved: false,
-                                has_submit_error: row.get(4),
-                                reserved_until: None,
-                                in_process: row.get(6),
-                                txhash: None
-                            })))
-                        }
-                    } else {
-                         (Status::new(200), Ok(Json(NFT {
-                            id: row.get(0),
-                            name: row.get(1),
-                            assigned: row.get(2),
-                            reserved: false,
-                            has_submit_error: row.get(4),
-                            reserved_until: None,
-                            in_process: row.get(6),
-                            txhash: Some(String::from("-hidden-"))
-                        })))
-                    }
-                }
-            } else {
-                 (Status::new(404), Err(Json(ErrorResponse {
-                    code: 404,
-                    message: String::from("NFT not found"),
-                })))
-            }
-        }
-
+    match conn.run(move |c| get_nft_lite(c, &id)).await {
+        Ok(Some(nft)) => (Status::new(200), Ok(Json(nft))),
+        Ok(None) => (
+            Status::new(404),
+            Err(Json(ErrorResponse {
+                code: 404,
+                message: String::from("NFT not found"),
+                errors: None,
+            })),
+        ),
         Err(e) => (
             Status::new(500),
             Err(Json(ErrorResponse {
                 code: 500,
                 message: format!("DB Error:{})", e),
+                errors: None,
             })),
         ),
     }
@@ -182,70 +159,209 @@ async fn options_new_nft() -> rocket::response::status::Custom<String> {
 #[post("/new", format = "json", data = "<nft_in>")]
 async fn new_nft(
     conn: NFTDatabase,
-    signature: SignatureB64,
+    signature: Signed<AdminScope>,
     state: &State<ReservationState>,
     nft_in: Json<requests::NewNFTRequest>,
 ) -> (Status, Result<Json<NewNFTResponse>, Json<ErrorResponse>>) {
     // log::info!("{}", signature.signature);
     let nft_in_stuff = nft_in.into_inner();
     let nft_in_json = serde_json::to_string(&nft_in_stuff).unwrap();
-    match verify_signature(&nft_in_json, &signature, &state.verification_key) {
+    match signature.verify(&nft_in_json, state, &conn).await {
         Ok(()) => {
-            let meta_json: Value = serde_json::from_str(&nft_in_stuff.meta).unwrap();
-            let svg_json: Value = serde_json::from_str(&nft_in_stuff.svg).unwrap();
+            let (meta_json, svg_json) = match nft_in_stuff.validate() {
+                Ok(parsed) => parsed,
+                Err(errors) => return invalid_nft(errors),
+            };
             match conn
-                .run(move |c| {
-                    let stmt: Statement = c
-                        .prepare(
-                            r#"Insert into NFT( id,name,meta_data,svg,ipfs_image,
-                                                ipfs_meta, image_data, external_url,
-                                                description,background_color,
-                                                animation_url,youtube_url  )     
-                            values(DEFAULT,$1,$2,$3,$4, $5,$6,$7, $8,$9, $10,$11) returning id"#,
-                        )
-                        .unwrap();
-                    c.query(
-                        &stmt,
-                        &[
-                            &nft_in_stuff.name,
-                            &meta_json,
-                            &svg_json,
-                            &nft_in_stuff.ipfs_image,
-                            &nft_in_stuff.ipfs_meta,
-                            &nft_in_stuff.image_data,
-                            &nft_in_stuff.external_url,
-                            &nft_in_stuff.description,
-                            &nft_in_stuff.background_color,
-                            &nft_in_stuff.animation_url,
-                            &nft_in_stuff.youtube_url,
-                        ],
-                    )
-                })
+                .run(move |c| insert_nft(c, &nft_in_stuff, &meta_json, &svg_json))
                 .await
             {
-                Ok(new_nft) => {
-                    let row = new_nft.first();
-                    let id_returned: Uuid = row.unwrap().get(0);
+                Ok(id_returned) => {
                     log::info!("{:?}", id_returned);
                     let response = NewNFTResponse {
                         nft_id: id_returned,
                     };
                     (Status::new(201), Ok(Json(response)))
                 }
+                Err(db_err) if db_err.code() == Some(&SqlState::UNIQUE_VIOLATION) => {
+                    invalid_nft(vec![FieldError::new("name", "is already taken")])
+                }
                 Err(db_err) => (
                     Status::new(500),
                     Err(Json(ErrorResponse {
                         code: 500,
                         message: db_err.to_string(),
+                        errors: None,
                     })),
                 ),
             }
         }
+        Err(e) => (e.0, Err(e.1)),
+    }
+}
+fn invalid_nft(
+    errors: Vec<FieldError>,
+) -> (Status, Result<Json<NewNFTResponse>, Json<ErrorResponse>>) {
+    (
+        Status::new(422),
+        Err(Json(ErrorResponse {
+            code: 422,
+            message: String::from("Invalid NFT"),
+            errors: Some(errors),
+        })),
+    )
+}
+/// add a collection of NFTs in one transaction, reporting on each. nothing is added unless every NFT is fine
+#[post("/import", format = "json", data = "<import_in>")]
+async fn import(
+    conn: NFTDatabase,
+    signature: Signed<AdminScope>,
+    state: &State<ReservationState>,
+    import_in: Json<NftImportRequest>,
+) -> (Status, Result<Json<NftImportResponse>, Json<ErrorResponse>>) {
+    let import_in_stuff = import_in.into_inner();
+    let import_in_json = serde_json::to_string(&import_in_stuff).unwrap();
+    if let Err(e) = signature.verify(&import_in_json, state, &conn).await {
+        return (e.0, Err(e.1));
+    }
+    match conn
+        .run(move |c| import_nfts(c, &import_in_stuff.nfts, import_in_stuff.dry_run))
+        .await
+    {
+        Ok(report) => {
+            let status = if report.rows.iter().any(|r| !r.errors.is_empty()) {
+                422
+            } else if report.dry_run {
+                200
+            } else {
+                201
+            };
+            (Status::new(status), Ok(Json(report)))
+        }
+        Err(e) => (
+            Status::new(500),
+            Err(Json(ErrorResponse {
+                code: 500,
+                message: e.to_string(),
+                errors: None,
+            })),
+        ),
+    }
+}
+/// the signature an admin change is made with, for its audit entry
+fn admin_signature(signature: &Signed<AdminScope>) -> AdminSignature {
+    AdminSignature {
+        signature: signature.signature.signature.clone(),
+        nonce: signature.nonce.clone(),
+    }
+}
+/// change the fields of an NFT which hasn't been reserved. the signature is of the request
+#[patch("/<id>", format = "json", data = "<update_in>")]
+async fn update(
+    conn: NFTDatabase,
+    signature: Signed<AdminScope>,
+    state: &State<ReservationState>,
+    id: Uuid,
+    update_in: Json<UpdateNFTRequest>,
+) -> (Status, Result<Json<NftFull>, Json<ErrorResponse>>) {
+    let update_in_stuff = update_in.into_inner();
+    let update_in_json = serde_json::to_string(&update_in_stuff).unwrap();
+    if let Err(e) = signature.verify(&update_in_json, state, &conn).await {
+        return (e.0, Err(e.1));
+    }
+    let signer = admin_signature(&signature);
+    match conn
+        .run(move |c| update_nft(c, &id, update_in_stuff, &signer))
+        .await
+    {
+        Ok(nft) => (Status::new(200), Ok(Json(nft))),
+        Err(e) => (e.0, Err(e.1)),
+    }
+}
+/// `withdraw` pulls an available NFT from sale, and `restore` puts it back.
+/// the signature is of `{"nft":"<id>","action":"<action>"}`
+#[post("/<id>/<action>")]
+async fn withdraw(
+    conn: NFTDatabase,
+    signature: Signed<AdminScope>,
+    state: &State<ReservationState>,
+    id: Uuid,
+    action: String,
+) -> (Status, Result<Json<bool>, Json<ErrorResponse>>) {
+    let ss = format!("{{\"nft\":\"{}\",\"action\":\"{}\"}}", id, action);
+    if let Err(e) = signature.verify(&ss, state, &conn).await {
+        return (e.0, Err(e.1));
+    }
+    let withdrawn = match action.as_str() {
+        "withdraw" => true,
+        "restore" => false,
+        _ => {
+            return (
+                Status::new(404),
+                Err(Json(ErrorResponse {
+                    code: 404,
+                    message: format!("Unknown action '{}'. expecting withdraw or restore", action),
+                    errors: None,
+                })),
+            )
+        }
+    };
+    let signer = admin_signature(&signature);
+    match conn
+        .run(move |c| set_nft_withdrawn(c, &id, withdrawn, &signer))
+        .await
+    {
+        Ok(()) => {
+            log::info!("{} {}", id, action);
+            (Status::new(200), Ok(Json(true)))
+        }
+        Err(e) => (e.0, Err(e.1)),
+    }
+}
+/// remove an NFT which has never been reserved. the signature is of `{"nft":"<id>","action":"delete"}`
+#[delete("/<id>")]
+async fn delete(
+    conn: NFTDatabase,
+    signature: Signed<AdminScope>,
+    state: &State<ReservationState>,
+    id: Uuid,
+) -> (Status, Result<Json<bool>, Json<ErrorResponse>>) {
+    let ss = format!("{{\"nft\":\"{}\",\"action\":\"delete\"}}", id);
+    if let Err(e) = signature.verify(&ss, state, &conn).await {
+        return (e.0, Err(e.1));
+    }
+    let signer = admin_signature(&signature);
+    match conn.run(move |c| delete_nft(c, &id, &signer)).await {
+        Ok(()) => {
+            log::info!("{} deleted", id);
+            (Status::new(200), Ok(Json(true)))
+        }
+        Err(e) => (e.0, Err(e.1)),
+    }
+}
+/// every admin change made to an NFT, oldest first. the signature is of `{"nft":"<id>","action":"audit"}`
+#[get("/<id>/audit", rank = 2)]
+async fn audit(
+    conn: NFTDatabase,
+    signature: Signed<AdminScope>,
+    state: &State<ReservationState>,
+    id: Uuid,
+) -> (
+    Status,
+    Result<Json<Vec<NftAuditEntry>>, Json<ErrorResponse>>,
+) {
+    let ss = format!("{{\"nft\":\"{}\",\"action\":\"audit\"}}", id);
+    if let Err(e) = signature.verify(&ss, state, &conn).await {
+        return (e.0, Err(e.1));
+    }
+    match conn.run(move |c| nft_audit_trail(c, &id)).await {
+        Ok(trail) => (Status::new(200), Ok(Json(trail))),
         Err(e) => (
-            Status::new(403),
+            Status::new(500),
             Err(Json(ErrorResponse {
-                code: 403,
+                code: 500,
                 message: e.to_string(),
+                errors: None,
             })),
         ),
     }
@@ -269,6 +385,7 @@ async fn check_name(
                         Err(Json(ErrorResponse {
                             code: 500,
                             message: e.to_string(),
+                            errors: None,
                         })),
                     ),
                     Ok(terra) => {
@@ -311,6 +428,7 @@ async fn check_name(
             Err(Json(ErrorResponse {
                 code: 500,
                 message: e.to_string(),
+                errors: None,
             })),
         ),
     }
@@ -318,8 +436,15 @@ async fn check_name(
 pub fn get_routes() -> Vec<Route> {
     routes![
         index,
+        list,
+        traits,
         get_by_id,
         new_nft,
+        import,
+        update,
+        withdraw,
+        delete,
+        audit,
         get_stage_stats,
         check_name,
         options_new_nft
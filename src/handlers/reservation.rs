@@ -1,11 +1,16 @@
-use crate::auth::{is_valid_address, verify_signature, SignatureB64};
+use crate::auth::{is_valid_address, AdminScope, OracleScope, Signed, UserScope};
 use crate::db::{
-    do_reservation, get_open_wallets_for_stage, get_reservations_for_wallet, get_stage,
-    mint_nft_for_wallet_in_stage, reservations_in_mint_process, reservations_in_mint_reserved,
-    reservations_stuck_in_mint_process,
+    do_reservation, errored_nfts, get_open_wallets_for_stage, get_reservation_history,
+    get_reservations_for_wallet, get_stage, mint_nft_for_wallet_in_stage, release_failed_nft,
+    requeue_failed_nft, reservations_in_mint_process, reservations_in_mint_reserved,
+    reservations_stuck_in_mint_process, selection_proofs, set_tx_hash_for_nft, wallet_eligibility,
 };
 use crate::handlers::mint::build_metadata_response;
-use crate::requests::{ErrorResponse, NewReservationRequest, NewReservationResponse, Reservation};
+use crate::minter::Minter;
+use crate::requests::{
+    ErrorResponse, ErroredNft, FreeMintResponse, NewReservationRequest, NewReservationResponse,
+    Reservation, ReservationHistory, SelectionProof, StageState, WalletEligibility,
+};
 use crate::{NFTDatabase, ReservationState};
 use chrono::Utc;
 use rocket::http::Status;
@@ -29,6 +34,84 @@ async fn get_by_address(
         .await
 }
 
+/// everything that happened to the wallet's reservations, newest first
+#[get("/<address>/history")]
+async fn get_history(
+    conn: NFTDatabase,
+    address: String,
+) -> (
+    Status,
+    Result<Json<Vec<ReservationHistory>>, Json<ErrorResponse>>,
+) {
+    if let Err(e) = is_valid_address(&address) {
+        return (Status::new(403), Err(e));
+    }
+    match conn
+        .run(move |c| get_reservation_history(c, &address))
+        .await
+    {
+        Ok(history) => (Status::new(200), Ok(Json(history))),
+        Err(db_err) => (
+            Status::new(500),
+            Err(Json(ErrorResponse {
+                code: 500,
+                message: db_err.to_string(),
+                errors: None,
+            })),
+        ),
+    }
+}
+
+/// the stages this wallet can mint from, when they open, and how many mints it has left in each
+#[get("/<address>/eligibility")]
+async fn get_eligibility(
+    conn: NFTDatabase,
+    state: &State<ReservationState>,
+    address: String,
+) -> (Status, Result<Json<WalletEligibility>, Json<ErrorResponse>>) {
+    if let Err(e) = is_valid_address(&address) {
+        return (Status::new(403), Err(e));
+    }
+    let max_reservations = state.max_reservations;
+    match conn
+        .run(move |c| wallet_eligibility(c, &address, max_reservations))
+        .await
+    {
+        Ok(eligibility) => (Status::new(200), Ok(Json(eligibility))),
+        Err(e) => (e.0, Err(e.1)),
+    }
+}
+
+/// how each NFT reserved to this wallet was picked, with what is needed to verify the pick
+#[get("/<address>/proof")]
+async fn get_selection_proof(
+    conn: NFTDatabase,
+    state: &State<ReservationState>,
+    address: String,
+) -> (
+    Status,
+    Result<Json<Vec<SelectionProof>>, Json<ErrorResponse>>,
+) {
+    if let Err(e) = is_valid_address(&address) {
+        return (Status::new(403), Err(e));
+    }
+    let salt = state.drop_salt.clone();
+    match conn
+        .run(move |c| selection_proofs(c, &address, &salt))
+        .await
+    {
+        Ok(proofs) => (Status::new(200), Ok(Json(proofs))),
+        Err(e) => (
+            Status::new(500),
+            Err(Json(ErrorResponse {
+                code: 500,
+                message: e.to_string(),
+                errors: None,
+            })),
+        ),
+    }
+}
+
 #[options("/new")]
 async fn options_new_reservation() -> rocket::response::status::Custom<String> {
     rocket::response::status::Custom(Status::new(200), "OK".into())
@@ -37,7 +120,7 @@ async fn options_new_reservation() -> rocket::response::status::Custom<String> {
 #[post("/new", format = "json", data = "<reservation_in>")]
 async fn new_reservation(
     conn: NFTDatabase,
-    signature: SignatureB64,
+    signature: Signed<UserScope>,
     state: &State<ReservationState>,
     reservation_in: Json<NewReservationRequest>,
 ) -> (
@@ -47,23 +130,16 @@ async fn new_reservation(
     let reservation_in_stuff = reservation_in.into_inner();
     let reservation_in_json = serde_json::to_string(&reservation_in_stuff).unwrap();
 
-    if let Err(e) = verify_signature(&reservation_in_json, &signature, &state.verification_key) {
-        if state.debug_mode {
-            log::warn!("IGNORING SIGNATURES");
-        } else {
-            log::warn!(
-                "Signature Failed {}/{}",
-                reservation_in_json,
-                &state.verification_key.join(",")
-            );
-            return (
-                Status::new(403),
-                Err(Json(ErrorResponse {
-                    code: 403,
-                    message: e.to_string(),
-                })),
-            );
-        }
+    if let Err(e) = signature
+        .verify_wallet(
+            &reservation_in_json,
+            &reservation_in_stuff.wallet_address,
+            state,
+            &conn,
+        )
+        .await
+    {
+        return (e.0, Err(e.1));
     }
     let duration_max = Utc::now() + state.max_reservation_duration;
     if reservation_in_stuff.reserved_until.gt(&duration_max) {
@@ -72,6 +148,7 @@ async fn new_reservation(
             Err(Json(ErrorResponse {
                 code: 401,
                 message: "Exceeds maximum reservation length".to_string(),
+                errors: None,
             })),
         );
     }
@@ -81,6 +158,7 @@ async fn new_reservation(
             Err(Json(ErrorResponse {
                 code: 401,
                 message: "reservation time has already expired".to_string(),
+                errors: None,
             })),
         );
     }
@@ -91,6 +169,7 @@ async fn new_reservation(
     let max_reservations = state.max_reservations;
     let wallet_address = reservation_in_stuff.wallet_address.clone();
     let reserved_until = reservation_in_stuff.reserved_until;
+    let salt = state.drop_salt.clone();
     let result: (
         Status,
         Result<(Uuid, serde_json::Value), Json<ErrorResponse>>,
@@ -101,6 +180,7 @@ async fn new_reservation(
                 &wallet_address,
                 &reserved_until.clone(),
                 max_reservations,
+                &salt,
             )
         })
         .await;
@@ -139,6 +219,7 @@ async fn get_in_process(
             Err(Json(ErrorResponse {
                 code: 500,
                 message: e.to_string(),
+                errors: None,
             })),
         ),
     }
@@ -160,6 +241,7 @@ async fn get_in_mint_process(
             Err(Json(ErrorResponse {
                 code: 500,
                 message: e.to_string(),
+                errors: None,
             })),
         ),
     }
@@ -181,6 +263,7 @@ async fn get_stuck_mint_process(
             Err(Json(ErrorResponse {
                 code: 500,
                 message: e.to_string(),
+                errors: None,
             })),
         ),
     }
@@ -200,109 +283,244 @@ async fn get_in_mint_reserved(
             Err(Json(ErrorResponse {
                 code: 500,
                 message: e.to_string(),
+                errors: None,
             })),
         ),
     }
 }
 
+/// reserve a NFT for each wallet on a free stage's whitelist with some allocation left
+fn reserve_free_stage(
+    c: &mut postgres::Client,
+    stage: &str,
+    salt: &str,
+) -> Result<Vec<MintReservation>, (Status, Json<ErrorResponse>)> {
+    let error = |code: u16, message: &str| {
+        (
+            Status::new(code),
+            Json(ErrorResponse {
+                code,
+                message: message.to_string(),
+                errors: None,
+            }),
+        )
+    };
+    let stage_rec = match get_stage(c, stage)? {
+        Some(stage_rec) => stage_rec,
+        None => return Err(error(404, "stage not found")),
+    };
+    if stage_rec.state_at(Utc::now()) == StageState::Closed {
+        return Err(error(403, "Stage is closed"));
+    }
+    if !stage_rec.stage_free {
+        return Err(error(403, "Stage is not free"));
+    }
+    let rows =
+        get_open_wallets_for_stage(c, stage_rec.id).map_err(|e| error(500, &e.to_string()))?;
+    let mut reservations_generated: Vec<MintReservation> = Default::default();
+    for row in rows {
+        let amount = row.allocated - row.assigned - row.reserved;
+        if amount > 0 {
+            let reservations =
+                mint_nft_for_wallet_in_stage(c, &stage_rec, &row.wallet_address, salt, 1)?;
+            reservations_generated.extend(reservations);
+        }
+    }
+    Ok(reservations_generated)
+}
+
 #[get("/free/stage/<stage>")]
 async fn get_free_stage(
     conn: NFTDatabase,
-    signature: SignatureB64,
+    signature: Signed<OracleScope>,
     stage: String,
     state: &State<ReservationState>,
 ) -> (
     Status,
     Result<Json<Vec<MintReservation>>, Json<ErrorResponse>>,
 ) {
-    let ss = format!("{{\"stage\":\"{}\"}}", stage.to_string());
+    let ss = format!("{{\"stage\":\"{}\"}}", stage);
 
     //  log::info!("{}", ss);
-    if let Err(e) = verify_signature(&ss, &signature, &state.verification_key) {
-        if state.debug_mode {
-            log::warn!("IGNORING SIGNATURES");
-        } else {
+    if let Err(e) = signature.verify(&ss, state, &conn).await {
+        return (e.0, Err(e.1));
+    }
+    let salt = state.drop_salt.clone();
+    match conn
+        .run(move |c| reserve_free_stage(c, &stage, &salt))
+        .await
+    {
+        Ok(reservations) => (Status::new(200), Ok(Json(reservations))),
+        Err(e) => (e.0, Err(e.1)),
+    }
+}
+
+/// as `/free/stage/<stage>`, with the minter minting the reservations. in a dry run the unsigned
+/// messages are handed back, and the mints are left to the caller
+#[post("/free/stage/<stage>/mint")]
+async fn mint_free_stage(
+    conn: NFTDatabase,
+    signature: Signed<OracleScope>,
+    stage: String,
+    state: &State<ReservationState>,
+    minter: &State<Option<Minter>>,
+) -> (Status, Result<Json<FreeMintResponse>, Json<ErrorResponse>>) {
+    let ss = format!("{{\"stage\":\"{}\"}}", stage);
+    if let Err(e) = signature.verify(&ss, state, &conn).await {
+        return (e.0, Err(e.1));
+    }
+    let minter = match minter.inner() {
+        Some(minter) => minter,
+        None => {
             return (
-                Status::new(403),
+                Status::new(503),
                 Err(Json(ErrorResponse {
-                    code: 403,
-                    message: e.to_string(),
+                    code: 503,
+                    message: "No minter is configured".to_string(),
+                    errors: None,
                 })),
-            );
+            )
         }
-    }
-    conn.run(move |c| match get_stage(c, &stage) {
-        Ok(stage_opt) => {
-            if let Some(stage_rec) = stage_opt {
-                if stage_rec.stage_free {
-                    match get_open_wallets_for_stage(c, stage_rec.id) {
-                        Ok(rows) => {
-                            let mut reservations_generated: Vec<MintReservation> =
-                                Default::default();
-                            for row in rows {
-                                let amount = row.allocated - row.assigned - row.reserved;
-                                if amount > 0 {
-                                    let reservations_result = mint_nft_for_wallet_in_stage(
-                                        c,
-                                        &stage_rec,
-                                        &row.wallet_address,
-                                        1,
-                                    );
-                                    match reservations_result {
-                                        Err(e) => {
-                                            return (e.0, Err(e.1));
-                                        }
-                                        Ok(reservations) => {
-                                            reservations.iter().for_each(|f| {
-                                                reservations_generated.push(f.clone())
-                                            });
-                                        }
-                                    }
-                                }
-                            }
-                            (Status::new(200), Ok(Json(reservations_generated)))
-                        }
-                        Err(e) => (
-                            Status::new(500),
-                            Err(Json(ErrorResponse {
-                                code: 500,
-                                message: e.to_string(),
-                            })),
-                        ),
+    };
+    let salt = state.drop_salt.clone();
+    let reservations = match conn
+        .run(move |c| reserve_free_stage(c, &stage, &salt))
+        .await
+    {
+        Ok(reservations) => reservations,
+        Err(e) => return (e.0, Err(e.1)),
+    };
+    let batches = minter.mint(&reservations).await;
+    let sent = batches
+        .iter()
+        .filter_map(|b| b.tx_hash.clone().map(|hash| (hash, b.nft_ids.clone())))
+        .collect::<Vec<(String, Vec<Uuid>)>>();
+    let recorded = conn
+        .run(move |c| {
+            for (hash, nft_ids) in sent {
+                for nft_id in nft_ids {
+                    if set_tx_hash_for_nft(c, &nft_id, &hash)? == 0 {
+                        log::warn!("mint_free_stage: {} could not be set to {}", nft_id, hash);
                     }
-                } else {
-                    (
-                        Status::new(403),
-                        Err(Json(ErrorResponse {
-                            code: 403,
-                            message: "Stage is not free".to_string(),
-                        })),
-                    )
                 }
-            } else {
-                (
-                    Status::new(404),
-                    Err(Json(ErrorResponse {
-                        code: 404,
-                        message: "stage not found".to_string(),
-                    })),
-                )
             }
+            Ok::<(), postgres::Error>(())
+        })
+        .await;
+    match recorded {
+        Ok(_) => (
+            Status::new(200),
+            Ok(Json(FreeMintResponse {
+                reservations,
+                batches,
+            })),
+        ),
+        Err(e) => (
+            Status::new(500),
+            Err(Json(ErrorResponse {
+                code: 500,
+                message: e.to_string(),
+                errors: None,
+            })),
+        ),
+    }
+}
+
+/// NFTs whose mint failed, dead lettered first, with each failure and what the retry policy made of it.
+/// the signature is of `{"errored":true}`
+#[get("/errored")]
+async fn get_errored(
+    conn: NFTDatabase,
+    signature: Signed<AdminScope>,
+    state: &State<ReservationState>,
+) -> (Status, Result<Json<Vec<ErroredNft>>, Json<ErrorResponse>>) {
+    if let Err(e) = signature.verify("{\"errored\":true}", state, &conn).await {
+        return (e.0, Err(e.1));
+    }
+    match conn.run(move |c| errored_nfts(c, 100)).await {
+        Ok(x) => (Status::new(200), Ok(Json(x))),
+        Err(e) => (
+            Status::new(500),
+            Err(Json(ErrorResponse {
+                code: 500,
+                message: e.to_string(),
+                errors: None,
+            })),
+        ),
+    }
+}
+
+/// `requeue` gives an errored or dead lettered NFT another go, forgiving its failures.
+/// `release` puts it back in the pool. the signature is of `{"nft":"<nft>","action":"<action>"}`
+#[post("/errored/<nft>/<action>")]
+async fn resolve_errored(
+    conn: NFTDatabase,
+    signature: Signed<AdminScope>,
+    state: &State<ReservationState>,
+    nft: Uuid,
+    action: String,
+) -> (Status, Result<Json<bool>, Json<ErrorResponse>>) {
+    let ss = format!("{{\"nft\":\"{}\",\"action\":\"{}\"}}", nft, action);
+    if let Err(e) = signature.verify(&ss, state, &conn).await {
+        return (e.0, Err(e.1));
+    }
+    let resolved = match action.as_str() {
+        "requeue" => {
+            let policy = state.retry_policy.clone();
+            conn.run(move |c| requeue_failed_nft(c, &nft, &policy))
+                .await
         }
-        Err(e) => (e.0, Err(e.1)),
-    })
-    .await
+        "release" => conn.run(move |c| release_failed_nft(c, &nft)).await,
+        _ => {
+            return (
+                Status::new(404),
+                Err(Json(ErrorResponse {
+                    code: 404,
+                    message: format!("Unknown action '{}'. expecting requeue or release", action),
+                    errors: None,
+                })),
+            )
+        }
+    };
+    match resolved {
+        Ok(1) => {
+            log::info!("{} {}d", nft, action);
+            (Status::new(200), Ok(Json(true)))
+        }
+        Ok(_) => (
+            Status::new(409),
+            Err(Json(ErrorResponse {
+                code: 409,
+                message: String::from("NFT is not errored or dead lettered"),
+                errors: None,
+            })),
+        ),
+        Err(e) => (
+            Status::new(500),
+            Err(Json(ErrorResponse {
+                code: 500,
+                message: e.to_string(),
+                errors: None,
+            })),
+        ),
+    }
 }
 
 pub fn get_routes() -> Vec<Route> {
     routes![
         get_by_address,
+        get_history,
+        get_eligibility,
+        get_selection_proof,
         new_reservation,
         options_new_reservation,
         get_in_process,
         get_in_mint_process,
         get_in_mint_reserved,
         get_free_stage,
-        get_stuck_mint_process
+        mint_free_stage,
+        get_stuck_mint_process,
+        get_errored,
+        resolve_errored
     ]
 }
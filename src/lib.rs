@@ -1 +1,8 @@
+pub mod chain;
+pub mod db;
+pub mod manifest;
+pub mod migrations;
+pub mod minter;
+pub mod models;
 pub mod requests;
+pub mod selection;
@@ -1,9 +1,15 @@
 pub mod auth;
 pub mod catchers;
+pub mod chain;
 pub mod db;
 pub mod handlers;
+pub mod manifest;
+pub mod migrations;
+pub mod minter;
 pub mod models;
 pub mod requests;
+pub mod selection;
+pub mod tasks;
 
 #[macro_use]
 extern crate rocket;
@@ -15,8 +21,20 @@ use rocket::figment::{
 };
 use rocket::http::Header;
 use rocket::{Build, Request, Response, Rocket};
+use std::collections::HashMap;
 use std::env;
+use std::sync::Arc;
+use tasks::nonce_pruner::NoncePruner;
+use tasks::reaper::ReservationReaper;
+use tasks::stage_lifecycle::{StageClosePolicy, StageLifecycle};
+use tasks::tx_broadcaster::TxBroadcaster;
+use tasks::tx_verifier::{TxHashVerification, TxVerifier};
+use tasks::tx_watcher::TxWatcher;
+use tasks::TaskPool;
 
+use auth::{KeyRole, UserAuthMode, VerificationKeys};
+use chain::RetryPolicy;
+use minter::{Minter, TerraLcd};
 use rocket_sync_db_pools::database;
 use secp256k1::{All, Secp256k1};
 use terra_rust_api::PrivateKey;
@@ -39,11 +57,11 @@ impl Fairing for CORS {
         ));
         response.set_header(Header::new(
             "Access-Control-Allow-Methods",
-            "POST, GET, PATCH, OPTIONS",
+            "POST, GET, PATCH, DELETE, OPTIONS",
         ));
         response.set_header(Header::new(
             "Access-Control-Allow-Headers",
-            "DNT,User-Agent,X-Requested-With,If-Modified-Since,Cache-Control,Content-Type,Range,X-Reservation-Signature",
+            "DNT,User-Agent,X-Requested-With,If-Modified-Since,Cache-Control,Content-Type,Range,X-Reservation-Signature,X-Reservation-Timestamp,X-Reservation-Nonce,X-Reservation-Public-Key",
         ));
         response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
     }
@@ -53,33 +71,150 @@ pub struct NFTDatabase(rocket_sync_db_pools::postgres::Client);
 
 pub struct ReservationState {
     pub signing_key: PrivateKey,
-    pub verification_key: Vec<String>,
+    pub verification_keys: VerificationKeys,
     pub max_reservations: usize,
     pub max_reservation_duration: Duration,
     pub debug_mode: bool,
+    pub signature_max_skew: Duration,
+    pub user_auth_mode: UserAuthMode,
     pub chain: String,
     pub lcd: String,
     pub fcd: String,
     pub nft_contract: String,
+    pub drop_salt: String,
+    pub tx_hash_verification: TxHashVerification,
+    /// where tx hashes handed to `/mint/hash` are looked up. the LCD, unless pointed elsewhere
+    pub tx_verification_lcd: String,
+    /// how long a parked tx hash has to show up on chain
+    pub tx_verification_timeout: Duration,
+    pub retry_policy: RetryPolicy,
 }
 
-#[launch]
-fn rocket() -> Rocket<Build> {
+/// `import <manifest> [--dry-run]`. adds the manifest's NFTs as `/nft/import` does, printing the report
+fn import_manifest(db_url: &str, args: &[String]) -> anyhow::Result<()> {
+    let path = args
+        .iter()
+        .find(|a| !a.starts_with("--"))
+        .ok_or_else(|| anyhow::anyhow!("Usage: import <manifest.jsonl|directory> [--dry-run]"))?;
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let records = manifest::read_manifest(std::path::Path::new(path))?;
+    let unreadable = records
+        .iter()
+        .filter_map(|(source, record)| record.as_ref().err().map(|e| format!("{}: {}", source, e)))
+        .collect::<Vec<String>>();
+    if !unreadable.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Unable to read the manifest. nothing imported\n{}",
+            unreadable.join("\n")
+        ));
+    }
+    let (sources, nfts): (Vec<String>, Vec<requests::NewNFTRequest>) = records
+        .into_iter()
+        .filter_map(|(source, record)| record.ok().map(|nft| (source, nft)))
+        .unzip();
+    let mut conn = postgres::Client::connect(db_url, postgres::NoTls)?;
+    let report = db::import_nfts(&mut conn, &nfts, dry_run)?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    let failed = report
+        .rows
+        .iter()
+        .filter(|r| !r.errors.is_empty())
+        .map(|r| format!("{}: {}", sources[r.row - 1], r.errors.join("; ")))
+        .collect::<Vec<String>>();
+    if !failed.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} NFTs failed. nothing imported\n{}",
+            failed.len(),
+            failed.join("\n")
+        ));
+    }
+    if dry_run {
+        println!("dry run: {} NFTs checked", report.rows.len());
+    } else {
+        println!("{} NFTs imported", report.imported);
+    }
+    Ok(())
+}
+
+#[rocket::main]
+async fn main() {
     dotenv::dotenv().ok();
     env_logger::init();
-    let secp: Secp256k1<All> = Secp256k1::new();
     let db_url = env::var("DATABASE_URL").unwrap();
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(|a| a.as_str()) == Some("migrate") {
+        let command = args[1..].to_vec();
+        let result =
+            rocket::tokio::task::spawn_blocking(move || migrations::run_command(&db_url, &command))
+                .await
+                .unwrap();
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.first().map(|a| a.as_str()) == Some("import") {
+        let command = args[1..].to_vec();
+        let result =
+            rocket::tokio::task::spawn_blocking(move || import_manifest(&db_url, &command))
+                .await
+                .unwrap();
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let apply_migrations = env::var("MIGRATE_ON_STARTUP")
+        .map(|x| x == "true")
+        .unwrap_or(false);
+    let startup_url = db_url.clone();
+    let migrated = rocket::tokio::task::spawn_blocking(move || {
+        migrations::on_startup(&startup_url, apply_migrations)
+    })
+    .await
+    .unwrap();
+    if let Err(e) = migrated {
+        log::error!("Not starting: {}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = rocket(db_url).launch().await {
+        log::error!("{}", e);
+    }
+}
+
+fn rocket(db_url: String) -> Rocket<Build> {
+    let secp: Secp256k1<All> = Secp256k1::new();
     let pool_size = env::var("DATABASE_POOL")
         .unwrap_or_else(|_| "10".into())
         .parse::<usize>()
         .unwrap();
+    let task_pool_size = env::var("TASK_DATABASE_POOL")
+        .unwrap_or_else(|_| "2".into())
+        .parse::<u32>()
+        .unwrap();
+    let task_pool = TaskPool::new(&db_url, task_pool_size).unwrap();
     let signing_key_phrase = env::var("RESERVATION_RESPONSE").unwrap();
     let signing_key = PrivateKey::from_words(&secp, &signing_key_phrase).unwrap();
-    let public_keys = env::var("RESERVATION_AUTH_PUBLIC_KEY")
-        .unwrap()
-        .split(',')
-        .map(|key| key.to_string())
-        .collect::<Vec<String>>();
+    let mut verification_keys: VerificationKeys = HashMap::new();
+    for (role, var) in [
+        (KeyRole::User, "RESERVATION_AUTH_PUBLIC_KEY"),
+        (KeyRole::Admin, "RESERVATION_ADMIN_PUBLIC_KEY"),
+        (KeyRole::Oracle, "RESERVATION_ORACLE_PUBLIC_KEY"),
+    ] {
+        match env::var(var) {
+            Ok(keys) => {
+                verification_keys.insert(
+                    role,
+                    keys.split(',')
+                        .map(|key| key.to_string())
+                        .collect::<Vec<String>>(),
+                );
+            }
+            Err(_) => log::warn!("{} not set. No {} keys will be accepted", var, role),
+        }
+    }
     let max_reservations: usize = env::var("MAX_RESERVATIONS").unwrap().parse().unwrap();
     let debug_mode = match std::env::var("DEBUG_IGNORE_SIG") {
         Ok(x) => x == "true",
@@ -99,16 +234,154 @@ fn rocket() -> Rocket<Build> {
     let chain = env::var("CHAIN_ID").expect("Missing CHAIN_ID server in environment");
     let nft_contract =
         env::var("NFT_CONTRACT").expect("Missing NFT_CONTRACT server in environment");
+    let signature_max_skew = Duration::seconds(
+        env::var("SIGNATURE_MAX_SKEW")
+            .unwrap_or_else(|_| "300".into())
+            .parse()
+            .unwrap(),
+    );
+    let user_auth_mode: UserAuthMode = env::var("USER_AUTH_MODE")
+        .unwrap_or_else(|_| "either".into())
+        .parse()
+        .unwrap();
+    let drop_salt = env::var("DROP_SALT").expect("Missing DROP_SALT in environment");
+    log::info!(
+        "DROP_SALT SHA-256 (publish before the drop): {}",
+        selection::salt_hash(&drop_salt)
+    );
+    let tx_hash_verification: TxHashVerification = env::var("TX_HASH_VERIFICATION")
+        .unwrap_or_else(|_| "off".into())
+        .parse()
+        .unwrap();
+    let tx_verification_lcd = env::var("TX_HASH_VERIFICATION_LCD").unwrap_or_else(|_| lcd.clone());
+    let tx_verification_timeout = Duration::seconds(
+        env::var("TX_HASH_VERIFICATION_TIMEOUT")
+            .unwrap_or_else(|_| "300".into())
+            .parse()
+            .unwrap(),
+    );
+    let retry_policy = RetryPolicy {
+        max_retries: env::var("MINT_RETRY_MAX")
+            .unwrap_or_else(|_| "3".into())
+            .parse()
+            .unwrap(),
+        retryable_codespaces: env::var("MINT_RETRYABLE_CODESPACES")
+            .unwrap_or_default()
+            .split(',')
+            .map(|c| c.trim().to_string())
+            .filter(|c| !c.is_empty())
+            .collect(),
+        release_after: env::var("MINT_RELEASE_AFTER")
+            .unwrap_or_else(|_| "0".into())
+            .parse()
+            .unwrap(),
+        errored_hold: env::var("MINT_ERRORED_HOLD")
+            .unwrap_or_else(|_| "60".into())
+            .parse()
+            .unwrap(),
+    };
     let reservation_state = ReservationState {
         signing_key,
-        verification_key: public_keys,
+        verification_keys,
         max_reservations,
         max_reservation_duration: Duration::minutes(max_reservation_duration),
         debug_mode,
+        signature_max_skew,
+        user_auth_mode,
         lcd,
         fcd,
         chain,
         nft_contract,
+        drop_salt,
+        tx_hash_verification,
+        tx_verification_lcd,
+        tx_verification_timeout,
+        retry_policy,
+    };
+    let reaper_interval: u64 = env::var("RESERVATION_REAPER_INTERVAL")
+        .unwrap_or_else(|_| "60".into())
+        .parse()
+        .unwrap();
+    let reaper = ReservationReaper {
+        interval: std::time::Duration::from_secs(reaper_interval),
+    };
+    let stage_interval: u64 = env::var("STAGE_LIFECYCLE_INTERVAL")
+        .unwrap_or_else(|_| "30".into())
+        .parse()
+        .unwrap();
+    let close_policy: StageClosePolicy = env::var("STAGE_CLOSE_POLICY")
+        .unwrap_or_else(|_| "honor".into())
+        .parse()
+        .unwrap();
+    let stage_lifecycle = StageLifecycle {
+        interval: std::time::Duration::from_secs(stage_interval),
+        close_policy,
+    };
+    let nonce_interval: u64 = env::var("NONCE_PRUNE_INTERVAL")
+        .unwrap_or_else(|_| "300".into())
+        .parse()
+        .unwrap();
+    let nonce_pruner = NoncePruner {
+        interval: std::time::Duration::from_secs(nonce_interval),
+        max_skew: signature_max_skew,
+    };
+    let tx_watcher_interval: u64 = env::var("TX_WATCHER_INTERVAL")
+        .unwrap_or_else(|_| "15".into())
+        .parse()
+        .unwrap();
+    let tx_watcher_max_backoff: u64 = env::var("TX_WATCHER_MAX_BACKOFF")
+        .unwrap_or_else(|_| "600".into())
+        .parse()
+        .unwrap();
+    let tx_broadcast_interval: u64 = env::var("TX_BROADCAST_INTERVAL")
+        .unwrap_or_else(|_| "15".into())
+        .parse()
+        .unwrap();
+    let tx_broadcaster = TxBroadcaster {
+        interval: std::time::Duration::from_secs(tx_broadcast_interval),
+        max_backoff: std::time::Duration::from_secs(
+            env::var("TX_BROADCAST_MAX_BACKOFF")
+                .unwrap_or_else(|_| "600".into())
+                .parse()
+                .unwrap(),
+        ),
+        max_retries: env::var("TX_BROADCAST_RETRIES")
+            .unwrap_or_else(|_| "3".into())
+            .parse()
+            .unwrap(),
+        signing_key: Arc::new(PrivateKey::from_words(&secp, &signing_key_phrase).unwrap()),
+    };
+    let minter = match env::var("MINTER_KEY") {
+        Ok(minter_key_phrase) => {
+            let minter_key = PrivateKey::from_words(&secp, &minter_key_phrase).unwrap();
+            let gas_price = env::var("MINTER_GAS_PRICE").unwrap_or_else(|_| "0.15uusd".into());
+            let gas_adjustment: f64 = env::var("MINTER_GAS_ADJUSTMENT")
+                .unwrap_or_else(|_| "1.4".into())
+                .parse()
+                .unwrap();
+            let lcd_client = TerraLcd::new(
+                &reservation_state.lcd,
+                &reservation_state.chain,
+                secp,
+                minter_key,
+                &gas_price,
+                gas_adjustment,
+            )
+            .expect("Invalid MINTER_GAS_PRICE");
+            Some(Minter {
+                sender: lcd_client.address().unwrap(),
+                nft_contract: reservation_state.nft_contract.clone(),
+                batch_size: env::var("MINTER_BATCH_SIZE")
+                    .unwrap_or_else(|_| "10".into())
+                    .parse()
+                    .unwrap(),
+                dry_run: env::var("MINTER_DRY_RUN")
+                    .map(|x| x == "true")
+                    .unwrap_or(false),
+                lcd: Box::new(lcd_client),
+            })
+        }
+        Err(_) => None,
     };
     let db: Map<_, Value> = map! {"url"=>db_url.into(),"pool_size"=>pool_size.into()};
     let figment = rocket::Config::figment().merge(("databases", map!["NFT"=>db]));
@@ -116,12 +389,47 @@ fn rocket() -> Rocket<Build> {
         log::error!("RUNNING IN DEBUG MODE: Signature generation/verification omitted")
     }
 
-    rocket::custom(figment)
+    let mut build = rocket::custom(figment)
         .manage(reservation_state)
+        .manage(task_pool)
         .attach(NFTDatabase::fairing())
         .attach(cors)
+        .attach(reaper)
+        .attach(stage_lifecycle)
+        .attach(nonce_pruner);
+    if tx_watcher_interval > 0 {
+        build = build.attach(TxWatcher {
+            interval: std::time::Duration::from_secs(tx_watcher_interval),
+            max_backoff: std::time::Duration::from_secs(tx_watcher_max_backoff),
+        });
+    } else {
+        log::warn!("TX_WATCHER_INTERVAL is 0. Mint transactions need confirming externally")
+    }
+    if tx_broadcast_interval > 0 {
+        build = build.attach(tx_broadcaster);
+    } else {
+        log::warn!(
+            "TX_BROADCAST_INTERVAL is 0. Transactions sent to /mint/tx will not be broadcast"
+        )
+    }
+    if tx_hash_verification == TxHashVerification::Park {
+        build = build.attach(TxVerifier {
+            interval: std::time::Duration::from_secs(
+                env::var("TX_HASH_VERIFICATION_INTERVAL")
+                    .unwrap_or_else(|_| "10".into())
+                    .parse()
+                    .unwrap(),
+            ),
+        });
+    }
+    if minter.as_ref().map(|m| m.dry_run).unwrap_or(false) {
+        log::warn!("MINTER_DRY_RUN is set. Free stage mints will not be broadcast")
+    }
+    build
+        .manage(minter)
         .register("/", catchers::get_catchers())
         .mount("/nft", handlers::nft::get_routes())
         .mount("/reservation", handlers::reservation::get_routes())
         .mount("/mint", handlers::mint::get_routes())
+        .mount("/admin", handlers::admin::get_routes())
 }
@@ -0,0 +1,85 @@
+//! Reading a collection manifest for `pfc-reservation import`: a JSONL file with an NFT on each line,
+//! or a directory with an NFT in each `.json` file (taken in file name order).
+use crate::requests::NewNFTRequest;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// an NFT in a manifest. as `NewNFTRequest`, but the metadata and svg can be given as JSON rather than strings
+#[derive(Deserialize)]
+pub struct ManifestNFT {
+    pub name: String,
+    /// CW721 `Metadata`
+    pub meta: Value,
+    pub svg: Value,
+    #[serde(default)]
+    pub ipfs_image: String,
+    #[serde(default)]
+    pub ipfs_meta: String,
+    pub image_data: Option<String>,
+    pub external_url: Option<String>,
+    pub description: Option<String>,
+    pub background_color: Option<String>,
+    pub animation_url: Option<String>,
+    pub youtube_url: Option<String>,
+}
+
+fn json_string(value: Value) -> String {
+    match value {
+        Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+impl From<ManifestNFT> for NewNFTRequest {
+    fn from(nft: ManifestNFT) -> Self {
+        NewNFTRequest {
+            name: nft.name,
+            meta: json_string(nft.meta),
+            svg: json_string(nft.svg),
+            ipfs_image: nft.ipfs_image,
+            ipfs_meta: nft.ipfs_meta,
+            image_data: nft.image_data,
+            external_url: nft.external_url,
+            description: nft.description,
+            background_color: nft.background_color,
+            animation_url: nft.animation_url,
+            youtube_url: nft.youtube_url,
+        }
+    }
+}
+
+fn parse(record: &str) -> Result<NewNFTRequest, String> {
+    serde_json::from_str::<ManifestNFT>(record)
+        .map(NewNFTRequest::from)
+        .map_err(|e| e.to_string())
+}
+
+/// the NFTs in the manifest at `path`, each with where it came from (`<file>:<line>`, or the file).
+/// a record which can't be read carries the reason
+pub fn read_manifest(path: &Path) -> anyhow::Result<Vec<(String, Result<NewNFTRequest, String>)>> {
+    if path.is_dir() {
+        let mut files = fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|p| p.extension().map(|e| e == "json").unwrap_or(false))
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+            .into_iter()
+            .map(|file| {
+                let record = fs::read_to_string(&file)?;
+                Ok((file.display().to_string(), parse(&record)))
+            })
+            .collect()
+    } else {
+        Ok(fs::read_to_string(path)?
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| (format!("{}:{}", path.display(), i + 1), parse(line)))
+            .collect())
+    }
+}
@@ -0,0 +1,255 @@
+//! Schema migrations, embedded in the binary.
+//!
+//! Each migration is a folder in `migrations/` named `<version>` with an `up.sql` and a `down.sql`.
+//! Applied versions are recorded in `schema_migrations`. New migrations need adding to `MIGRATIONS`,
+//! which must stay in version order.
+use anyhow::anyhow;
+use postgres::{Client, NoTls};
+
+pub struct Migration {
+    pub version: &'static str,
+    up: &'static str,
+    down: &'static str,
+}
+
+macro_rules! migration {
+    ($version:literal) => {
+        Migration {
+            version: $version,
+            up: include_str!(concat!("../migrations/", $version, "/up.sql")),
+            down: include_str!(concat!("../migrations/", $version, "/down.sql")),
+        }
+    };
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    migration!("2021-10-17-initial"),
+    migration!("2026-10-17-100000-reservation-reaper"),
+    migration!("2026-10-17-110000-stage-lifecycle"),
+    migration!("2026-10-17-120000-admin-whitelist"),
+    migration!("2026-10-17-130000-used-nonce"),
+    migration!("2026-10-17-140000-stage-selection-rule"),
+    migration!("2026-10-17-150000-nft-stage"),
+    migration!("2026-10-17-160000-reservation-history"),
+    migration!("2026-10-17-170000-nft-status"),
+    migration!("2026-10-17-180000-pending-verification"),
+    migration!("2026-10-17-190000-mint-retry-policy"),
+    migration!("2026-10-17-200000-nft-admin"),
+    migration!("2026-10-17-210000-nft-trait"),
+    migration!("2026-10-17-220000-selection-proof"),
+    migration!("2026-10-17-230000-rejected-reservations"),
+];
+
+/// only one process migrates at a time
+const MIGRATION_LOCK: i64 = 0x7066_635f_6d69_6772;
+
+fn ensure_schema_table(conn: &mut Client) -> Result<(), postgres::Error> {
+    conn.batch_execute(
+        "create table if not exists schema_migrations (
+            version    varchar(255) primary key,
+            applied_on timestamp with time zone not null default now()
+        )",
+    )
+}
+
+/// versions recorded in the database, oldest first
+fn applied_versions(conn: &mut Client) -> Result<Vec<String>, postgres::Error> {
+    Ok(conn
+        .query(
+            "select version from schema_migrations order by version",
+            &[],
+        )?
+        .iter()
+        .map(|r| r.get(0))
+        .collect())
+}
+
+/// fails if the database has had migrations applied which this binary doesn't know about
+fn check_not_newer(applied: &[String]) -> anyhow::Result<()> {
+    let unknown = applied
+        .iter()
+        .filter(|v| !MIGRATIONS.iter().any(|m| &m.version == v))
+        .cloned()
+        .collect::<Vec<String>>();
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Database schema is newer than this binary. Unknown migrations: {}",
+            unknown.join(", ")
+        ))
+    }
+}
+
+/// fails if the database has tables but no migrations recorded, as replaying the initial migration over a
+/// database set up by hand would fail part way, or worse
+fn check_baselined(conn: &mut Client, applied: &[String]) -> anyhow::Result<()> {
+    if !applied.is_empty() {
+        return Ok(());
+    }
+    let existing: bool = conn
+        .query_one("select to_regclass('nft') is not null", &[])?
+        .get(0);
+    if existing {
+        Err(anyhow!(
+            "Database has tables but no recorded migrations. If it was set up by hand, run `pfc-reservation migrate baseline {}` first",
+            MIGRATIONS[0].version
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn pending(applied: &[String]) -> Vec<&'static Migration> {
+    MIGRATIONS
+        .iter()
+        .filter(|m| !applied.iter().any(|v| v == m.version))
+        .collect()
+}
+
+/// apply every pending migration, each in its own transaction. returns the versions applied
+pub fn migrate_up(conn: &mut Client) -> anyhow::Result<Vec<&'static str>> {
+    ensure_schema_table(conn)?;
+    conn.execute("select pg_advisory_lock($1)", &[&MIGRATION_LOCK])?;
+    let result = (|| {
+        let applied = applied_versions(conn)?;
+        check_not_newer(&applied)?;
+        check_baselined(conn, &applied)?;
+        let mut done = vec![];
+        for migration in pending(&applied) {
+            let mut tx = conn.transaction()?;
+            tx.batch_execute(migration.up)
+                .map_err(|e| anyhow!("{} up: {}", migration.version, e))?;
+            tx.execute(
+                "insert into schema_migrations (version) values ($1)",
+                &[&migration.version],
+            )?;
+            tx.commit()?;
+            log::info!("Applied migration {}", migration.version);
+            done.push(migration.version);
+        }
+        Ok(done)
+    })();
+    conn.execute("select pg_advisory_unlock($1)", &[&MIGRATION_LOCK])?;
+    result
+}
+
+/// roll back the last `steps` applied migrations, newest first. returns the versions rolled back
+pub fn migrate_down(conn: &mut Client, steps: usize) -> anyhow::Result<Vec<&'static str>> {
+    ensure_schema_table(conn)?;
+    conn.execute("select pg_advisory_lock($1)", &[&MIGRATION_LOCK])?;
+    let result = (|| {
+        let applied = applied_versions(conn)?;
+        check_not_newer(&applied)?;
+        let mut done = vec![];
+        for migration in MIGRATIONS
+            .iter()
+            .rev()
+            .filter(|m| applied.iter().any(|v| v == m.version))
+            .take(steps)
+        {
+            let mut tx = conn.transaction()?;
+            tx.batch_execute(migration.down)
+                .map_err(|e| anyhow!("{} down: {}", migration.version, e))?;
+            tx.execute(
+                "delete from schema_migrations where version = $1",
+                &[&migration.version],
+            )?;
+            tx.commit()?;
+            log::info!("Rolled back migration {}", migration.version);
+            done.push(migration.version);
+        }
+        Ok(done)
+    })();
+    conn.execute("select pg_advisory_unlock($1)", &[&MIGRATION_LOCK])?;
+    result
+}
+
+/// record every migration up to and including `version` as applied, without running it.
+/// for databases which were set up by hand before migrations were tracked
+pub fn baseline(conn: &mut Client, version: &str) -> anyhow::Result<Vec<&'static str>> {
+    if !MIGRATIONS.iter().any(|m| m.version == version) {
+        return Err(anyhow!("Unknown migration {}", version));
+    }
+    ensure_schema_table(conn)?;
+    let mut done = vec![];
+    for migration in MIGRATIONS.iter().filter(|m| m.version <= version) {
+        if conn.execute(
+            "insert into schema_migrations (version) values ($1) on conflict (version) do nothing",
+            &[&migration.version],
+        )? == 1
+        {
+            done.push(migration.version);
+        }
+    }
+    Ok(done)
+}
+
+/// called before the server starts. refuses to go on if the schema is newer than the binary,
+/// or if there are pending migrations and `apply` is off
+pub fn on_startup(db_url: &str, apply: bool) -> anyhow::Result<()> {
+    let mut conn = Client::connect(db_url, NoTls)?;
+    if apply {
+        migrate_up(&mut conn)?;
+        return Ok(());
+    }
+    ensure_schema_table(&mut conn)?;
+    let applied = applied_versions(&mut conn)?;
+    check_not_newer(&applied)?;
+    check_baselined(&mut conn, &applied)?;
+    let outstanding = pending(&applied);
+    if outstanding.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} pending migrations. Run `pfc-reservation migrate`",
+            outstanding.len()
+        ))
+    }
+}
+
+/// `migrate [up | down [steps] | status | baseline <version>]`
+pub fn run_command(db_url: &str, args: &[String]) -> anyhow::Result<()> {
+    let mut conn = Client::connect(db_url, NoTls)?;
+    match args.first().map(|a| a.as_str()).unwrap_or("up") {
+        "up" => {
+            let done = migrate_up(&mut conn)?;
+            println!("{} migrations applied", done.len());
+        }
+        "down" => {
+            let steps = match args.get(1) {
+                Some(s) => s.parse()?,
+                None => 1,
+            };
+            let done = migrate_down(&mut conn, steps)?;
+            println!("{} migrations rolled back", done.len());
+        }
+        "baseline" => {
+            let version = args
+                .get(1)
+                .ok_or_else(|| anyhow!("Usage: migrate baseline <version>"))?;
+            let done = baseline(&mut conn, version)?;
+            println!("{} migrations marked as applied", done.len());
+        }
+        "status" => {
+            ensure_schema_table(&mut conn)?;
+            let applied = applied_versions(&mut conn)?;
+            for migration in MIGRATIONS {
+                let state = if applied.iter().any(|v| v == migration.version) {
+                    "applied"
+                } else {
+                    "pending"
+                };
+                println!("{}\t{}", state, migration.version);
+            }
+            check_not_newer(&applied)?;
+        }
+        other => {
+            return Err(anyhow!(
+                "Unknown migrate command '{}'. expecting up, down, status or baseline",
+                other
+            ))
+        }
+    }
+    Ok(())
+}
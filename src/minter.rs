@@ -0,0 +1,183 @@
+//! Minting free stage NFTs from the contract owner's wallet, in place of an operator minting the
+//! `MintReservation`s from `/reservation/free/stage/<stage>` and calling `/mint/assign-owner`.
+use crate::requests::{MintBatch, MintReservation};
+use rocket::tokio::sync::Mutex;
+use secp256k1::{All, Secp256k1};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use terra_rust_api::core_types::Coin;
+use terra_rust_api::{GasOptions, Message, MsgExecuteContract, PrivateKey, Terra};
+
+/// the most seconds to wait for the previous transaction to land before sending the next
+const SEQUENCE_WAIT: u32 = 30;
+
+/// sends the minter's transactions. `TerraLcd` broadcasts to an LCD, tests can stand in their own
+#[rocket::async_trait]
+pub trait LcdClient: Send + Sync {
+    /// sign and broadcast one transaction executing each of `execute_msgs` on `contract`, returning its hash
+    async fn broadcast(&self, contract: &str, execute_msgs: Vec<Value>) -> anyhow::Result<String>;
+}
+
+/// mints reserved NFTs `batch_size` to a transaction
+pub struct Minter {
+    /// the minter's wallet address
+    pub sender: String,
+    pub nft_contract: String,
+    pub batch_size: usize,
+    /// hand back the unsigned messages instead of broadcasting them
+    pub dry_run: bool,
+    pub lcd: Box<dyn LcdClient>,
+}
+
+/// the contract's `mint` message for a reservation. the metadata goes on chain as the `extension`
+pub fn mint_msg(reservation: &MintReservation) -> Value {
+    let mut extension = serde_json::to_value(&reservation.meta_data).unwrap_or_default();
+    if let Some(meta) = extension.as_object_mut() {
+        meta.remove("token_uri");
+    }
+    serde_json::json!({
+        "mint": {
+            "token_id": reservation.name,
+            "owner": reservation.wallet_address,
+            "token_uri": reservation.meta_data.token_uri,
+            "extension": extension,
+        }
+    })
+}
+
+impl Minter {
+    /// the `MsgExecuteContract` the minter would sign for `execute_msg`
+    pub fn unsigned_msg(&self, execute_msg: &Value) -> Value {
+        let msg: Message = MsgExecuteContract::create_from_value(
+            &self.sender,
+            &self.nft_contract,
+            execute_msg,
+            &[],
+        );
+        serde_json::to_value(&msg).unwrap_or_default()
+    }
+
+    /// mint the reservations, one transaction per batch. a batch which fails to broadcast carries
+    /// the error, and its NFTs stay reserved
+    pub async fn mint(&self, reservations: &[MintReservation]) -> Vec<MintBatch> {
+        let mut batches: Vec<MintBatch> = vec![];
+        for chunk in reservations.chunks(self.batch_size.max(1)) {
+            let nft_ids = chunk.iter().map(|r| r.nft_id).collect();
+            let execute_msgs = chunk.iter().map(mint_msg).collect::<Vec<Value>>();
+            if self.dry_run {
+                batches.push(MintBatch {
+                    nft_ids,
+                    tx_hash: None,
+                    messages: Some(execute_msgs.iter().map(|m| self.unsigned_msg(m)).collect()),
+                    error: None,
+                });
+                continue;
+            }
+            match self.lcd.broadcast(&self.nft_contract, execute_msgs).await {
+                Ok(hash) => batches.push(MintBatch {
+                    nft_ids,
+                    tx_hash: Some(hash),
+                    messages: None,
+                    error: None,
+                }),
+                Err(e) => {
+                    log::error!("Minter: {}", e);
+                    batches.push(MintBatch {
+                        nft_ids,
+                        tx_hash: None,
+                        messages: None,
+                        error: Some(e.to_string()),
+                    })
+                }
+            }
+        }
+        batches
+    }
+}
+
+/// broadcasts through an LCD, signing with the minter's key
+pub struct TerraLcd {
+    lcd: String,
+    chain: String,
+    secp: Arc<Secp256k1<All>>,
+    key: Arc<PrivateKey>,
+    gas_price: String,
+    gas_adjustment: f64,
+    /// the account sequence of the last transaction sent
+    last_sequence: Arc<Mutex<Option<u64>>>,
+}
+
+impl TerraLcd {
+    pub fn new(
+        lcd: &str,
+        chain: &str,
+        secp: Secp256k1<All>,
+        key: PrivateKey,
+        gas_price: &str,
+        gas_adjustment: f64,
+    ) -> anyhow::Result<TerraLcd> {
+        Coin::parse(gas_price)?;
+        Ok(TerraLcd {
+            lcd: String::from(lcd),
+            chain: String::from(chain),
+            secp: Arc::new(secp),
+            key: Arc::new(key),
+            gas_price: String::from(gas_price),
+            gas_adjustment,
+            last_sequence: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    pub fn address(&self) -> anyhow::Result<String> {
+        self.key.public_key(&self.secp).account()
+    }
+}
+
+#[rocket::async_trait]
+impl LcdClient for TerraLcd {
+    async fn broadcast(&self, contract: &str, execute_msgs: Vec<Value>) -> anyhow::Result<String> {
+        let lcd = self.lcd.clone();
+        let chain = self.chain.clone();
+        let secp = self.secp.clone();
+        let key = self.key.clone();
+        let gas_price = self.gas_price.clone();
+        let gas_adjustment = self.gas_adjustment;
+        let last_sequence = self.last_sequence.clone();
+        let contract = String::from(contract);
+        // terra-rust-api's messages can't be sent between threads, so the transaction is put
+        // together and sent on a thread of its own
+        rocket::tokio::task::spawn_blocking(move || {
+            rocket::tokio::runtime::Handle::current().block_on(async move {
+                let gas_options = GasOptions::create_with_gas_estimate(&gas_price, gas_adjustment)?;
+                let terra = Terra::lcd_client(&lcd, &chain, &gas_options, None).await?;
+                let sender = key.public_key(&secp).account()?;
+                let mut last_sequence = last_sequence.lock().await;
+                // the LCD only shows the account's next sequence once the last transaction is in a block
+                let mut sequence = None;
+                for _ in 0..SEQUENCE_WAIT {
+                    let account = terra.auth().account(&sender).await?;
+                    let current = account.result.value.sequence.unwrap_or(0);
+                    if last_sequence.map(|last| current > last).unwrap_or(true) {
+                        sequence = Some(current);
+                        break;
+                    }
+                    rocket::tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+                let sequence = sequence.ok_or_else(|| {
+                    anyhow::anyhow!("previous mint transaction is still not in a block")
+                })?;
+                let messages = execute_msgs
+                    .iter()
+                    .map(|m| MsgExecuteContract::create_from_value(&sender, &contract, m, &[]))
+                    .collect::<Vec<Message>>();
+                let result = terra
+                    .submit_transaction_sync(&secp, &key, &messages, None)
+                    .await?;
+                *last_sequence = Some(sequence);
+                Ok(result.txhash)
+            })
+        })
+        .await?
+    }
+}
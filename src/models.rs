@@ -1,4 +1,5 @@
-use chrono::DateTime;
+use crate::requests::{NftState, SelectionRule, StageState};
+use chrono::{DateTime, Utc};
 //use rocket_sync_db_pools::diesel::Queryable;
 use serde::Serialize;
 use serde_json::Value;
@@ -8,6 +9,8 @@ use uuid::Uuid;
 pub struct NFT {
     pub id: Uuid,
     pub name: String,
+    pub status: NftState,
+    // the flags follow from status, and are kept for older clients
     pub assigned: bool,
     pub reserved: bool,
     pub has_submit_error: bool,
@@ -15,6 +18,40 @@ pub struct NFT {
     pub in_process: bool,
     pub txhash: Option<String>,
 }
+impl NFT {
+    /// `status` is the state as it stands now (see `NftState::at`)
+    pub fn new(
+        id: Uuid,
+        name: String,
+        status: NftState,
+        reserved_until: Option<DateTime<Utc>>,
+        txhash: Option<String>,
+    ) -> NFT {
+        NFT {
+            id,
+            name,
+            status,
+            assigned: status == NftState::Minted,
+            reserved: NftState::HELD.contains(&status),
+            has_submit_error: matches!(status, NftState::Errored | NftState::DeadLetter),
+            reserved_until,
+            in_process: matches!(
+                status,
+                NftState::PendingVerification
+                    | NftState::Submitted
+                    | NftState::Errored
+                    | NftState::DeadLetter
+            ),
+            txhash,
+        }
+    }
+}
+/// a page of `/nft/list`. `next_cursor` fetches the page after, if there is one
+#[derive(Serialize)]
+pub struct NftPage {
+    pub nfts: Vec<NFT>,
+    pub next_cursor: Option<String>,
+}
 #[derive(Serialize)]
 pub struct NftFull {
     pub nft_lite: NFT,
@@ -32,6 +69,30 @@ pub struct NftFull {
     pub assigned_to_wallet_address: Option<String>,
     pub reserved_to_wallet_address: Option<String>,
     pub signed_packet: Option<Value>,
+    pub withdrawn: bool,
+}
+
+/// the signature an admin change to an NFT was made with, kept in its audit entry
+pub struct AdminSignature {
+    pub signature: String,
+    pub nonce: Option<String>,
+}
+
+/// a wallet's signed mint transaction, waiting to be broadcast
+pub struct SignedTxToBroadcast {
+    pub nft_id: Uuid,
+    pub wallet_address: String,
+    pub meta_data: Value,
+    pub signed_packet: Value,
+    pub tx_retry_count: i32,
+}
+
+/// a tx hash handed to `/mint/hash`, waiting to be found on chain
+pub struct TxPendingVerification {
+    pub nft_id: Uuid,
+    pub wallet_address: String,
+    pub txhash: String,
+    pub verify_by: DateTime<Utc>,
 }
 
 #[derive(Serialize)]
@@ -39,19 +100,55 @@ pub struct Stage {
     pub id: Uuid,
     pub code: String,
     pub name: String,
-    pub attribute_type: Option<String>,
-    pub attribute_value: Option<String>,
+    pub selection_rule: Option<SelectionRule>,
     pub is_default: bool,
     pub stage_free: bool,
     pub stage_open: DateTime<chrono::offset::Utc>,
     pub stage_close: Option<DateTime<chrono::offset::Utc>>,
 }
+impl Stage {
+    /// the state the stage is in at `now`, based on its open/close times
+    pub fn state_at(&self, now: DateTime<Utc>) -> StageState {
+        match self.stage_close {
+            Some(close) if close <= now => StageState::Closed,
+            _ => {
+                if self.stage_open <= now {
+                    StageState::Open
+                } else {
+                    StageState::Pending
+                }
+            }
+        }
+    }
+}
 
+/// a stage moving from one state to the next, as recorded by the lifecycle task
+pub struct StageTransition {
+    pub stage_code: String,
+    pub from_state: StageState,
+    pub to_state: StageState,
+    /// reservations released because the stage closed
+    pub revoked: u64,
+}
+
+/// a wallet's allocation in a stage
 #[derive(Serialize)]
-pub(crate) struct WalletStageAllocation {
+pub struct WalletStageAllocation {
+    /// the stage
     pub id: Option<Uuid>,
+    pub wallet_address: String,
     pub allocation_count: i64,
     pub reserved_count: i64,
     pub assigned_count: i64,
     pub stage_open: Option<DateTime<chrono::offset::Utc>>,
 }
+
+/// a wallet whose whitelist counters didn't match its NFTs, and what they were set to
+#[derive(Serialize)]
+pub struct WhitelistCorrection {
+    pub wallet_address: String,
+    pub reserved_count_was: i64,
+    pub reserved_count: i64,
+    pub assigned_count_was: i64,
+    pub assigned_count: i64,
+}
@@ -1,5 +1,7 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 #[derive(Serialize, Deserialize)]
 pub struct NewNFTRequest {
@@ -15,10 +17,252 @@ pub struct NewNFTRequest {
     pub animation_url: Option<String>,
     pub youtube_url: Option<String>,
 }
+/// the base58 alphabet CIDv0s are written in
+const BASE58: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// what's wrong with one field of a request
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+impl FieldError {
+    pub fn new(field: &str, message: &str) -> FieldError {
+        FieldError {
+            field: String::from(field),
+            message: String::from(message),
+        }
+    }
+}
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// an IPFS CIDv0 (`Qm` and 44 base58 characters), which is what fits the NFT's `char(46)` columns
+fn is_cid(cid: &str) -> bool {
+    cid.len() == 46 && cid.starts_with("Qm") && cid.chars().all(|c| BASE58.contains(c))
+}
+
+fn is_url(url: &str) -> bool {
+    ["http://", "https://", "ipfs://"]
+        .iter()
+        .any(|scheme| url.len() > scheme.len() && url.starts_with(scheme))
+        && !url.chars().any(char::is_whitespace)
+}
+
+impl NewNFTRequest {
+    /// the metadata and svg as JSON, or what's wrong with each field.
+    /// the metadata has to be CW721 `Metadata`. the IPFS CIDs can be left empty
+    pub fn validate(&self) -> Result<(serde_json::Value, serde_json::Value), Vec<FieldError>> {
+        let mut errors = vec![];
+        if self.name.trim().is_empty() {
+            errors.push(FieldError::new("name", "is empty"));
+        } else if self.name.chars().count() > 255 {
+            errors.push(FieldError::new("name", "is longer than 255 characters"));
+        } else if self.name.trim() != self.name {
+            errors.push(FieldError::new("name", "has leading or trailing spaces"));
+        }
+        let meta = match serde_json::from_str::<serde_json::Value>(&self.meta) {
+            Ok(meta) => match serde_json::from_value::<Metadata>(meta.clone()) {
+                Ok(metadata) if metadata.token_uri.is_empty() => {
+                    errors.push(FieldError::new("meta", "token_uri is empty"));
+                    None
+                }
+                Ok(_) => Some(meta),
+                Err(e) => {
+                    errors.push(FieldError::new(
+                        "meta",
+                        &format!("is not CW721 metadata: {}", e),
+                    ));
+                    None
+                }
+            },
+            Err(e) => {
+                errors.push(FieldError::new("meta", &format!("is not JSON: {}", e)));
+                None
+            }
+        };
+        let svg = match serde_json::from_str::<serde_json::Value>(&self.svg) {
+            Ok(svg) => Some(svg),
+            Err(e) => {
+                errors.push(FieldError::new("svg", &format!("is not JSON: {}", e)));
+                None
+            }
+        };
+        for (field, cid) in [
+            ("ipfs_image", &self.ipfs_image),
+            ("ipfs_meta", &self.ipfs_meta),
+        ] {
+            if !cid.is_empty() && !is_cid(cid) {
+                errors.push(FieldError::new(field, "is not an IPFS CIDv0 (Qm...)"));
+            }
+        }
+        if let Some(color) = &self.background_color {
+            if color.len() != 6 || !color.chars().all(|c| c.is_ascii_hexdigit()) {
+                errors.push(FieldError::new(
+                    "background_color",
+                    "is not a 6 digit hex color, without the #",
+                ));
+            }
+        }
+        for (field, url) in [
+            ("external_url", &self.external_url),
+            ("animation_url", &self.animation_url),
+            ("youtube_url", &self.youtube_url),
+        ] {
+            if let Some(url) = url {
+                if url.len() > 2000 {
+                    errors.push(FieldError::new(field, "is longer than 2000 characters"));
+                } else if !is_url(url) {
+                    errors.push(FieldError::new(
+                        field,
+                        "is not an http(s):// or ipfs:// URL",
+                    ));
+                }
+            }
+        }
+        match (meta, svg) {
+            (Some(meta), Some(svg)) if errors.is_empty() => Ok((meta, svg)),
+            _ => Err(errors),
+        }
+    }
+}
 #[derive(Serialize)]
 pub struct NewNFTResponse {
     pub nft_id: Uuid,
 }
+/// change an NFT which hasn't been reserved. fields left out are kept as they are
+#[derive(Serialize, Deserialize)]
+pub struct UpdateNFTRequest {
+    pub name: Option<String>,
+    pub meta: Option<String>,
+    pub svg: Option<String>,
+    pub ipfs_image: Option<String>,
+    pub ipfs_meta: Option<String>,
+    pub image_data: Option<String>,
+    pub external_url: Option<String>,
+    pub description: Option<String>,
+    pub background_color: Option<String>,
+    pub animation_url: Option<String>,
+    pub youtube_url: Option<String>,
+}
+impl UpdateNFTRequest {
+    /// `nft` with this request's fields in place of its own
+    pub fn apply(self, nft: NewNFTRequest) -> NewNFTRequest {
+        NewNFTRequest {
+            name: self.name.unwrap_or(nft.name),
+            meta: self.meta.unwrap_or(nft.meta),
+            svg: self.svg.unwrap_or(nft.svg),
+            ipfs_image: self.ipfs_image.unwrap_or(nft.ipfs_image),
+            ipfs_meta: self.ipfs_meta.unwrap_or(nft.ipfs_meta),
+            image_data: self.image_data.or(nft.image_data),
+            external_url: self.external_url.or(nft.external_url),
+            description: self.description.or(nft.description),
+            background_color: self.background_color.or(nft.background_color),
+            animation_url: self.animation_url.or(nft.animation_url),
+            youtube_url: self.youtube_url.or(nft.youtube_url),
+        }
+    }
+}
+/// an admin change to an NFT. `before` and `after` hold the fields it changed
+#[derive(Serialize, Deserialize)]
+pub struct NftAuditEntry {
+    pub id: Uuid,
+    pub nft_id: Uuid,
+    pub action: String,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    /// the signature the change was made with, which shows the admin key that made it
+    pub signature: String,
+    pub nonce: Option<String>,
+    pub created_on: DateTime<Utc>,
+}
+/// the NFTs listed on a page of `/nft/list`, unless `limit` is given
+pub const NFT_LIST_LIMIT: i64 = 50;
+/// the most NFTs listed on a page of `/nft/list`
+pub const NFT_LIST_MAX: i64 = 200;
+
+/// filters for `/nft/list`. every filter given has to match. `cursor` is the `next_cursor` of the previous page
+#[derive(rocket::FromForm)]
+pub struct NftListQuery {
+    pub state: Option<String>,
+    pub trait_type: Option<String>,
+    pub trait_value: Option<String>,
+    pub name_prefix: Option<String>,
+    pub wallet: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+impl NftListQuery {
+    /// the cursor for a page ending with the NFT `name`
+    pub fn cursor_for(name: &str) -> String {
+        base64::encode_config(name, base64::URL_SAFE_NO_PAD)
+    }
+
+    /// the name of the NFT the previous page ended with
+    pub fn after(&self) -> Option<String> {
+        self.cursor.as_ref().and_then(|cursor| {
+            base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+                .ok()
+                .and_then(|name| String::from_utf8(name).ok())
+        })
+    }
+
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(NFT_LIST_LIMIT)
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = vec![];
+        if let Some(Err(e)) = self.state.as_deref().map(NftState::from_str) {
+            errors.push(FieldError::new("state", &e.to_string()));
+        }
+        if self.trait_type.is_some() != self.trait_value.is_some() {
+            errors.push(FieldError::new(
+                "trait_type",
+                "trait_type and trait_value go together",
+            ));
+        }
+        if self.cursor.is_some() && self.after().is_none() {
+            errors.push(FieldError::new("cursor", "is not a cursor from /nft/list"));
+        }
+        if !(1..=NFT_LIST_MAX).contains(&self.limit()) {
+            errors.push(FieldError::new(
+                "limit",
+                &format!("is not between 1 and {}", NFT_LIST_MAX),
+            ));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+/// NFTs to add in one go. with `dry_run` they are checked, and nothing is added
+#[derive(Serialize, Deserialize)]
+pub struct NftImportRequest {
+    pub nfts: Vec<NewNFTRequest>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+/// how one NFT of an import went. `row` counts from 1
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NftImportRow {
+    pub row: usize,
+    pub name: Option<String>,
+    pub nft_id: Option<Uuid>,
+    pub errors: Vec<String>,
+}
+/// an import only adds NFTs when every row is fine, and it isn't a dry run
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NftImportResponse {
+    pub dry_run: bool,
+    pub imported: usize,
+    pub rows: Vec<NftImportRow>,
+}
 #[derive(Serialize)]
 pub struct NameNFTResponse {
     pub allowed: bool,
@@ -31,26 +275,257 @@ pub struct NFTTallyResponse {
     pub reserved: i64,
     pub in_process: i64,
     pub available: i64,
+    pub withdrawn: i64,
 }
 #[derive(Serialize)]
 pub struct NFTTallyStat {
     pub assigned: i64,
     pub reserved: i64,
     pub count: i64,
+    pub available: i64,
+}
+/// a stage's whitelist, summed over its wallets
+#[derive(Serialize, Default)]
+pub struct WhitelistTallyStat {
+    pub wallet_count: i64,
+    pub allocation_count: i64,
+    pub reserved_count: i64,
+    pub assigned_count: i64,
+}
+/// where a stage is in its lifecycle
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StageState {
+    /// stage_open is in the future
+    Pending,
+    Open,
+    /// stage_close has passed
+    Closed,
+}
+impl StageState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StageState::Pending => "pending",
+            StageState::Open => "open",
+            StageState::Closed => "closed",
+        }
+    }
+}
+impl fmt::Display for StageState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+impl FromStr for StageState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(StageState::Pending),
+            "open" => Ok(StageState::Open),
+            "closed" => Ok(StageState::Closed),
+            _ => Err(anyhow::anyhow!("Unknown stage state '{}'", s)),
+        }
+    }
+}
+
+/// where an NFT is in its lifecycle. stored in `NFT.status`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum NftState {
+    /// can be reserved
+    Available,
+    /// held for a wallet until reserved_until
+    Reserved,
+    /// a tx hash was sent, and is waiting to be found on chain before it counts as submitted
+    #[serde(rename = "pending_verification")]
+    PendingVerification,
+    /// a mint transaction has been sent, and is waiting on the chain
+    Submitted,
+    /// the mint transaction failed. the wallet can resubmit
+    Errored,
+    /// failed past what the retry policy allows. held for an admin to requeue or release
+    #[serde(rename = "dead_letter")]
+    DeadLetter,
+    /// minted to a wallet
+    Minted,
+}
+impl NftState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NftState::Available => "available",
+            NftState::Reserved => "reserved",
+            NftState::PendingVerification => "pending_verification",
+            NftState::Submitted => "submitted",
+            NftState::Errored => "errored",
+            NftState::DeadLetter => "dead_letter",
+            NftState::Minted => "minted",
+        }
+    }
+    /// the states in which an NFT is held for the wallet it's reserved to
+    pub const HELD: &'static [NftState] = &[
+        NftState::Reserved,
+        NftState::PendingVerification,
+        NftState::Submitted,
+        NftState::Errored,
+        NftState::DeadLetter,
+    ];
+    /// the states which may move to `to`.
+    /// the chain has the final say, so anything not yet minted can become minted
+    pub fn sources(to: NftState) -> &'static [NftState] {
+        match to {
+            NftState::Available => &[NftState::Reserved, NftState::Errored, NftState::DeadLetter],
+            NftState::Reserved => &[NftState::Available, NftState::PendingVerification],
+            NftState::PendingVerification => &[NftState::Reserved, NftState::Errored],
+            NftState::Submitted => &[
+                NftState::Reserved,
+                NftState::PendingVerification,
+                NftState::Errored,
+            ],
+            NftState::Errored => &[NftState::Submitted, NftState::DeadLetter],
+            NftState::DeadLetter => &[NftState::Errored],
+            NftState::Minted => &[
+                NftState::Available,
+                NftState::Reserved,
+                NftState::PendingVerification,
+                NftState::Submitted,
+                NftState::Errored,
+                NftState::DeadLetter,
+            ],
+        }
+    }
+    pub fn can_become(&self, to: NftState) -> bool {
+        NftState::sources(to).contains(self)
+    }
+    /// the state after moving to `to`, or an error if the move isn't allowed
+    pub fn transition(&self, to: NftState) -> anyhow::Result<NftState> {
+        if self.can_become(to) {
+            Ok(to)
+        } else {
+            Err(anyhow::anyhow!("NFT can not go from {} to {}", self, to))
+        }
+    }
+    /// the state as it stands at `now`. a lapsed reservation is available again
+    pub fn at(&self, reserved_until: Option<DateTime<Utc>>, now: DateTime<Utc>) -> NftState {
+        match (self, reserved_until) {
+            (NftState::Reserved, Some(until)) if until < now => NftState::Available,
+            _ => *self,
+        }
+    }
+}
+impl fmt::Display for NftState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+impl FromStr for NftState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "available" => Ok(NftState::Available),
+            "reserved" => Ok(NftState::Reserved),
+            "pending_verification" => Ok(NftState::PendingVerification),
+            "submitted" => Ok(NftState::Submitted),
+            "errored" => Ok(NftState::Errored),
+            "dead_letter" => Ok(NftState::DeadLetter),
+            "minted" => Ok(NftState::Minted),
+            _ => Err(anyhow::anyhow!("Unknown NFT state '{}'", s)),
+        }
+    }
+}
+
+/// which NFTs a stage draws from. stored as JSON on `stage_whitelist.selection_rule`, e.g.
+/// `{"all":[{"trait":{"trait_type":"hat","value":"bagel"}},{"not":{"names":["Evan Bagelmeister"]}}]}`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SelectionRule {
+    /// NFTs with one of these names
+    Names(Vec<String>),
+    /// NFTs with an attribute of this trait_type and value
+    Trait { trait_type: String, value: String },
+    /// every rule matches
+    All(Vec<SelectionRule>),
+    /// at least one rule matches
+    Any(Vec<SelectionRule>),
+    /// the rule doesn't match. for exclusions
+    Not(Box<SelectionRule>),
+}
+impl SelectionRule {
+    /// rejects rules which can't match anything by mistake
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            SelectionRule::Names(names) => {
+                if names.is_empty() || names.iter().any(|n| n.is_empty()) {
+                    Err(String::from("names needs at least one non-empty name"))
+                } else {
+                    Ok(())
+                }
+            }
+            SelectionRule::Trait { trait_type, value } => {
+                if trait_type.is_empty() || value.is_empty() {
+                    Err(String::from("trait needs a trait_type and a value"))
+                } else {
+                    Ok(())
+                }
+            }
+            SelectionRule::All(rules) | SelectionRule::Any(rules) => {
+                if rules.is_empty() {
+                    Err(String::from("all/any need at least one rule"))
+                } else {
+                    rules.iter().try_for_each(|r| r.validate())
+                }
+            }
+            SelectionRule::Not(rule) => rule.validate(),
+        }
+    }
 }
+
 #[derive(Serialize)]
 pub struct NFTStageTallyStat {
     pub stage_id: Uuid,
     pub stage_code: String,
     pub stage_name: String,
-    pub wallet_count: i64,
+    pub stage_state: StageState,
+    #[serde(flatten)]
+    pub whitelist: WhitelistTallyStat,
     pub stats: NFTTallyStat,
 }
 
+/// how many NFTs have a trait value, and what has become of them
+#[derive(Serialize, Deserialize)]
+pub struct TraitValueStat {
+    pub value: String,
+    pub total: i64,
+    pub assigned: i64,
+    pub reserved: i64,
+    pub available: i64,
+    /// percentage of the collection with this value
+    pub rarity: f64,
+    /// percentage of the NFTs still available which have this value
+    pub available_rarity: f64,
+}
+/// a trait type's values, rarest first
+#[derive(Serialize, Deserialize)]
+pub struct TraitTypeStat {
+    pub trait_type: String,
+    pub total: i64,
+    pub values: Vec<TraitValueStat>,
+}
+/// every trait in the collection. withdrawn NFTs aren't counted
+#[derive(Serialize, Deserialize)]
+pub struct TraitStatsResponse {
+    pub nft_count: i64,
+    pub available: i64,
+    pub traits: Vec<TraitTypeStat>,
+}
+
 #[derive(Serialize)]
 pub struct Reservation {
     pub wallet_address: String,
     pub nft_id: Uuid,
+    pub status: NftState,
+    // the flags follow from status, and are kept for older clients
     pub reserved: bool,
     pub reserved_until: Option<DateTime<Utc>>,
     pub assigned: bool,
@@ -63,6 +538,65 @@ pub struct Reservation {
     pub token_id: Option<String>,
 }
 
+/// where a reservation got to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReservationStatus {
+    /// the wallet holds the NFT
+    Reserved,
+    /// a mint transaction has been sent
+    Submitted,
+    /// the mint transaction failed
+    Errored,
+    /// the reservation lapsed (or was revoked) and the NFT went back to the pool
+    Expired,
+    /// the NFT was minted
+    Completed,
+    /// no NFT was reserved. `tx_error` has the reason
+    Rejected,
+}
+/// a reservation (or attempt) from the wallet's history
+#[derive(Serialize, Deserialize)]
+pub struct ReservationHistory {
+    pub id: Uuid,
+    pub nft_id: Option<Uuid>,
+    pub nft_name: Option<String>,
+    pub stage_code: Option<String>,
+    pub status: ReservationStatus,
+    pub created_on: DateTime<Utc>,
+    pub reserved_until: Option<DateTime<Utc>>,
+    pub submitted_on: Option<DateTime<Utc>>,
+    pub tx_hash: Option<String>,
+    pub errored_on: Option<DateTime<Utc>>,
+    pub tx_error: Option<String>,
+    pub expired_on: Option<DateTime<Utc>>,
+    pub completed_on: Option<DateTime<Utc>>,
+    /// how often the wallet was turned down for this reason, for a rejected attempt. `errored_on` is the latest
+    pub rejected_count: Option<i32>,
+}
+
+/// a failed mint of an NFT, and what the retry policy made of it
+#[derive(Serialize, Deserialize)]
+pub struct TxErrorRecord {
+    pub wallet_address: Option<String>,
+    pub tx_hash: Option<String>,
+    pub tx_error: Option<String>,
+    /// retry, dead_letter or release
+    pub outcome: String,
+    pub created_on: DateTime<Utc>,
+}
+/// an NFT whose mint failed, with every failed mint it has had
+#[derive(Serialize, Deserialize)]
+pub struct ErroredNft {
+    pub nft_id: Uuid,
+    pub name: String,
+    pub status: NftState,
+    pub wallet_address: Option<String>,
+    pub mint_failures: i32,
+    pub tx_error: Option<String>,
+    pub errors: Vec<TxErrorRecord>,
+}
+
 /// request a NFT to be reserved
 #[derive(Serialize, Deserialize, Clone)]
 pub struct NewReservationRequest {
@@ -80,10 +614,68 @@ pub struct NewReservationResponse {
     pub metadata_response: MetadataResponse,
 }
 
+/// a stage a wallet can mint from, now or once it opens
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StageEligibility {
+    pub stage_id: Uuid,
+    pub stage_code: String,
+    pub stage_name: String,
+    pub stage_state: StageState,
+    pub stage_free: bool,
+    pub stage_open: DateTime<Utc>,
+    pub stage_close: Option<DateTime<Utc>>,
+    /// open to every wallet, whitelisted or not
+    pub is_default: bool,
+    /// the wallet's whitelist allocation, if it is on the stage's whitelist
+    pub allocation_count: Option<i64>,
+    pub reserved_count: Option<i64>,
+    pub assigned_count: Option<i64>,
+    /// mints left in the wallet's allocation. none for a default stage the wallet isn't whitelisted on
+    pub remaining: Option<i64>,
+    /// whether a reservation now could draw from this stage
+    pub eligible_now: bool,
+}
+/// the stages a wallet can mint from, and how many more NFTs it can hold
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WalletEligibility {
+    pub wallet_address: String,
+    /// `MAX_RESERVATIONS`, the most NFTs a wallet can hold or have minted
+    pub max_reservations: usize,
+    /// the NFTs the wallet holds or has minted
+    pub reservation_count: usize,
+    pub reservations_left: usize,
+    /// open stages first, then those still to open
+    pub stages: Vec<StageEligibility>,
+}
+
+/// shows how an NFT reserved to a wallet was picked, as recorded at the time. see `selection`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SelectionProof {
+    pub wallet_address: String,
+    /// the drop salt, revealed once every stage has closed
+    pub salt: Option<String>,
+    /// hex encoded SHA-256 of the salt, published before the drop
+    pub salt_hash: String,
+    /// hex encoded seed the pick was made with. the first is SHA-256 of "<wallet_address>/<salt>"
+    pub seed: String,
+    pub stage_code: String,
+    pub candidate_count: usize,
+    /// hex encoded SHA-256 of the comma separated candidate ids, in id order
+    pub candidates_hash: String,
+    /// position of the NFT in the id ordered candidate list
+    pub index: usize,
+    pub nft_id: Uuid,
+    pub nft_name: String,
+    pub reserved_on: DateTime<Utc>,
+}
+
 #[derive(Serialize)]
 pub struct ErrorResponse {
     pub code: u16,
     pub message: String,
+    /// what's wrong with each field, for a request which didn't validate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Vec<FieldError>>,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
@@ -157,12 +749,64 @@ pub struct MintReservation {
     pub wallet_address: String,
     /// the 'id' of the NFT in our system
     pub nft_id: Uuid,
+    /// the NFT's name, which is its token_id in the contract
+    pub name: String,
     /// the meta data to mint it
     pub meta_data: Metadata,
 }
 
+/// a mint transaction the minter sent for a free stage (or, in a dry run, would have sent)
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MintBatch {
+    /// the NFTs minted by it
+    pub nft_ids: Vec<Uuid>,
+    pub tx_hash: Option<String>,
+    /// the unsigned `MsgExecuteContract`s, when it is a dry run
+    pub messages: Option<Vec<serde_json::Value>>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FreeMintResponse {
+    pub reservations: Vec<MintReservation>,
+    pub batches: Vec<MintBatch>,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct AssignOwner {
     pub token_id: String,
     pub wallet_address: String,
 }
+
+/// create a stage
+#[derive(Serialize, Deserialize, Clone)]
+pub struct NewStageRequest {
+    pub code: String,
+    pub name: String,
+    pub selection_rule: Option<SelectionRule>,
+    pub is_default: bool,
+    pub stage_free: bool,
+    pub stage_open: DateTime<Utc>,
+    pub stage_close: Option<DateTime<Utc>>,
+}
+/// change a stage. fields which are not present are left as they are
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UpdateStageRequest {
+    pub name: Option<String>,
+    pub selection_rule: Option<SelectionRule>,
+    pub is_default: Option<bool>,
+    pub stage_free: Option<bool>,
+    pub stage_open: Option<DateTime<Utc>>,
+    pub stage_close: Option<DateTime<Utc>>,
+}
+/// the number of NFTs a wallet is allowed in a stage
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WalletAllocationRequest {
+    pub wallet_address: String,
+    pub allocation_count: i32,
+}
+/// add (or replace) a batch of wallets on a stage's whitelist
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WhitelistUploadRequest {
+    pub wallets: Vec<WalletAllocationRequest>,
+}
@@ -0,0 +1,108 @@
+//! Deterministic, auditable selection of NFTs for a wallet.
+//!
+//! The seed is `SHA-256("<wallet address>/<drop salt>")`. Candidates are sorted by their id, and
+//! the first 8 bytes of the seed (big endian) modulo the number of candidates gives the pick.
+//! Each further pick removes the chosen candidate and re-hashes the seed.
+//!
+//! The salt stays secret while the drop is live, otherwise wallet addresses could be ground offline
+//! to land a chosen NFT. Its SHA-256 is published beforehand, and the salt itself once every stage has closed:
+//! the one salt seeds every stage, so revealing it earlier would give away the picks of those still open.
+//! Each reservation records its seed, the candidates' hash and the index picked, so given the salt and
+//! the list of candidates available at the time, anyone can re-run this and arrive at the same NFT.
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// the seed for a wallet in this drop
+pub fn wallet_seed(wallet_address: &str, salt: &str) -> [u8; 32] {
+    let mut seed = [0u8; 32];
+    seed.copy_from_slice(&Sha256::digest(
+        format!("{}/{}", wallet_address, salt).as_bytes(),
+    ));
+    seed
+}
+
+/// the commitment published before the drop, so the salt revealed afterwards can be checked
+pub fn salt_hash(salt: &str) -> String {
+    to_hex(&Sha256::digest(salt.as_bytes()))
+}
+
+/// the seed used for the pick after this one
+pub fn next_seed(seed: &[u8; 32]) -> [u8; 32] {
+    let mut next = [0u8; 32];
+    next.copy_from_slice(&Sha256::digest(seed));
+    next
+}
+
+/// index into a list of `candidate_count` sorted candidates
+pub fn seed_index(seed: &[u8; 32], candidate_count: usize) -> Option<usize> {
+    if candidate_count == 0 {
+        None
+    } else {
+        let mut head = [0u8; 8];
+        head.copy_from_slice(&seed[0..8]);
+        Some((u64::from_be_bytes(head) % candidate_count as u64) as usize)
+    }
+}
+
+/// a fingerprint of the candidate list, so the pool a pick was made from can be verified later
+pub fn candidates_hash(candidates: &[Uuid]) -> String {
+    let joined = candidates
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<String>>()
+        .join(",");
+    to_hex(&Sha256::digest(joined.as_bytes()))
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// a candidate picked for a wallet, with what is needed to show it was the one the seed chose
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pick {
+    pub nft_id: Uuid,
+    /// the seed this pick was made with
+    pub seed: [u8; 32],
+    /// the candidates left when it was made
+    pub candidate_count: usize,
+    pub candidates_hash: String,
+    /// position of the NFT in the id ordered candidates
+    pub index: usize,
+}
+
+/// Iterates the candidates in the order a wallet would be allocated them.
+pub struct SelectionOrder {
+    candidates: Vec<Uuid>,
+    seed: [u8; 32],
+}
+
+impl SelectionOrder {
+    pub fn new(wallet_address: &str, salt: &str, candidates: &[Uuid]) -> Self {
+        let mut sorted = candidates.to_vec();
+        sorted.sort();
+        sorted.dedup();
+        Self {
+            candidates: sorted,
+            seed: wallet_seed(wallet_address, salt),
+        }
+    }
+}
+
+impl Iterator for SelectionOrder {
+    type Item = Pick;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = seed_index(&self.seed, self.candidates.len())?;
+        let pick = Pick {
+            nft_id: self.candidates[index],
+            seed: self.seed,
+            candidate_count: self.candidates.len(),
+            candidates_hash: candidates_hash(&self.candidates),
+            index,
+        };
+        self.seed = next_seed(&self.seed);
+        self.candidates.remove(index);
+        Some(pick)
+    }
+}
@@ -0,0 +1,85 @@
+use postgres::{Client, NoTls};
+use rocket::{Orbit, Rocket};
+use rocket_sync_db_pools::r2d2;
+use rocket_sync_db_pools::r2d2_postgres::PostgresConnectionManager;
+use std::time::Duration;
+
+pub mod nonce_pruner;
+pub mod reaper;
+pub mod stage_lifecycle;
+pub mod tx_broadcaster;
+pub mod tx_verifier;
+pub mod tx_watcher;
+
+/// connections for the background tasks, apart from the request pool.
+/// a task takes one for each piece of work and hands it straight back, so they share a few between them
+#[derive(Clone)]
+pub struct TaskPool(r2d2::Pool<PostgresConnectionManager<NoTls>>);
+
+impl TaskPool {
+    /// connections are only opened when a task needs one, and closed again once idle
+    pub fn new(db_url: &str, size: u32) -> anyhow::Result<Self> {
+        let manager = PostgresConnectionManager::new(db_url.parse()?, NoTls);
+        Ok(TaskPool(
+            r2d2::Pool::builder()
+                .max_size(size)
+                .min_idle(Some(0))
+                .idle_timeout(Some(Duration::from_secs(60)))
+                .build_unchecked(manager),
+        ))
+    }
+
+    /// the pool rocket is managing, logging why `name` can't start if there isn't one
+    pub(crate) fn of(rocket: &Rocket<Orbit>, name: &str) -> Option<TaskPool> {
+        let pool = rocket.state::<TaskPool>().cloned();
+        if pool.is_none() {
+            log::error!("{}: no task DB pool. Not started", name);
+        }
+        pool
+    }
+
+    /// run `job` on a connection of its own, which goes back to the pool when it is done
+    pub async fn run<F, T>(&self, job: F) -> anyhow::Result<T>
+    where
+        F: FnOnce(&mut Client) -> Result<T, postgres::Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.0.clone();
+        rocket::tokio::task::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            Ok(job(&mut conn)?)
+        })
+        .await?
+    }
+}
+
+/// run `job` every `interval` on a pooled connection, until rocket shuts down.
+/// the connection is only held while the job runs.
+pub(crate) async fn run_periodically<F>(
+    rocket: &Rocket<Orbit>,
+    name: &'static str,
+    interval: Duration,
+    job: F,
+) where
+    F: Fn(&mut Client) + Clone + Send + 'static,
+{
+    let pool = match TaskPool::of(rocket, name) {
+        Some(pool) => pool,
+        None => return,
+    };
+    let mut shutdown = rocket.shutdown();
+    rocket::tokio::spawn(async move {
+        let mut ticker = rocket::tokio::time::interval(interval);
+        loop {
+            rocket::tokio::select! {
+                _ = ticker.tick() => {
+                    let job = job.clone();
+                    if let Err(e) = pool.run(move |c| { job(c); Ok(()) }).await {
+                        log::error!("{}: {}", name, e);
+                    }
+                }
+                _ = &mut shutdown => break,
+            }
+        }
+    });
+}
@@ -0,0 +1,37 @@
+use crate::db::prune_nonces;
+use crate::tasks::run_periodically;
+use chrono::{Duration, Utc};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Orbit, Rocket};
+
+/// Periodically removes used nonces whose timestamps are older than the allowed skew.
+/// Requests carrying them are rejected on their timestamp, so they no longer need to be kept.
+pub struct NoncePruner {
+    pub interval: std::time::Duration,
+    pub max_skew: Duration,
+}
+
+#[rocket::async_trait]
+impl Fairing for NoncePruner {
+    fn info(&self) -> Info {
+        Info {
+            name: "Nonce Pruner",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let max_skew = self.max_skew;
+        run_periodically(
+            rocket,
+            "Nonce Pruner",
+            self.interval,
+            move |c| match prune_nonces(c, Utc::now() - max_skew) {
+                Ok(0) => {}
+                Ok(n) => log::info!("Nonce Pruner: removed {} nonces", n),
+                Err(e) => log::error!("Nonce Pruner: {}", e),
+            },
+        )
+        .await;
+    }
+}
@@ -0,0 +1,37 @@
+use crate::db::{clear_reservations, release_expired_errored};
+use crate::tasks::run_periodically;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Orbit, Rocket};
+use std::time::Duration;
+
+/// Periodically releases reservations which have passed their `reserved_until` time, and errored NFTs
+/// whose hold has run out, and gives the whitelist allocation back to the wallet that held them.
+pub struct ReservationReaper {
+    pub interval: Duration,
+}
+
+#[rocket::async_trait]
+impl Fairing for ReservationReaper {
+    fn info(&self) -> Info {
+        Info {
+            name: "Reservation Reaper",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        run_periodically(rocket, "Reservation Reaper", self.interval, |c| {
+            match clear_reservations(c) {
+                Ok(0) => {}
+                Ok(n) => log::info!("Reservation Reaper: released {} expired reservations", n),
+                Err(e) => log::error!("Reservation Reaper: {}", e),
+            }
+            match release_expired_errored(c) {
+                Ok(0) => {}
+                Ok(n) => log::info!("Reservation Reaper: released {} errored NFTs", n),
+                Err(e) => log::error!("Reservation Reaper: {}", e),
+            }
+        })
+        .await;
+    }
+}
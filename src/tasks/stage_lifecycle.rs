@@ -0,0 +1,68 @@
+use crate::db::advance_stage_lifecycle;
+use crate::tasks::run_periodically;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Orbit, Rocket};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// what happens to reservations still held in a stage when it closes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StageClosePolicy {
+    /// reservations run until their `reserved_until`
+    Honor,
+    /// reservations not yet submitted are released back to the pool
+    Revoke,
+}
+
+impl FromStr for StageClosePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "honor" => Ok(StageClosePolicy::Honor),
+            "revoke" => Ok(StageClosePolicy::Revoke),
+            _ => Err(anyhow::anyhow!(
+                "Unknown stage close policy '{}'. expecting honor or revoke",
+                s
+            )),
+        }
+    }
+}
+
+/// Moves stages from pending to open to closed as their `stage_open`/`stage_close` times pass,
+/// recording each transition in `stage_transition`.
+pub struct StageLifecycle {
+    pub interval: Duration,
+    pub close_policy: StageClosePolicy,
+}
+
+#[rocket::async_trait]
+impl Fairing for StageLifecycle {
+    fn info(&self) -> Info {
+        Info {
+            name: "Stage Lifecycle",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let revoke = self.close_policy == StageClosePolicy::Revoke;
+        run_periodically(rocket, "Stage Lifecycle", self.interval, move |c| {
+            match advance_stage_lifecycle(c, revoke) {
+                Ok(transitions) => {
+                    for transition in transitions {
+                        log::info!(
+                            "Stage {}: {} -> {} ({} reservations revoked)",
+                            transition.stage_code,
+                            transition.from_state,
+                            transition.to_state,
+                            transition.revoked
+                        )
+                    }
+                }
+                Err(e) => log::error!("Stage Lifecycle: {}", e),
+            }
+        })
+        .await;
+    }
+}
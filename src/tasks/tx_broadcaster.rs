@@ -0,0 +1,203 @@
+use crate::chain::{broadcast_tx, Backoff, RetryPolicy};
+use crate::db::{note_broadcast_failure, set_broadcast_hash, signed_txs_to_broadcast};
+use crate::handlers::mint::check_signed_tx;
+use crate::models::SignedTxToBroadcast;
+use crate::tasks::TaskPool;
+use crate::ReservationState;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Orbit, Rocket};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use terra_rust_api::errors::TerraRustAPIError;
+use terra_rust_api::PrivateKey;
+
+/// the most signed transactions sent each round
+const BROADCAST_LIMIT: i64 = 50;
+
+/// Broadcasts the signed transactions wallets hand to `/mint/tx`, once they are checked to mint
+/// just the NFT reserved to them. the hash is then left for the tx watcher.
+///
+/// a transaction the LCD turns away is tried again, up to `max_retries` times, before the NFT errors.
+/// when the LCD can't be reached, it is tried again with an increasing delay, up to `max_backoff`.
+#[derive(Clone)]
+pub struct TxBroadcaster {
+    pub interval: Duration,
+    pub max_backoff: Duration,
+    pub max_retries: i32,
+    /// the key `/mint/<wallet>/<nft>` signs metadata with, to rebuild the expected mint message
+    pub signing_key: Arc<PrivateKey>,
+}
+
+/// what came of sending a signed transaction
+enum Sent {
+    Hash(String),
+    /// turned away. one which doesn't mint the reservation is never worth sending again
+    Rejected {
+        error: String,
+        retryable: bool,
+    },
+    Unreachable(String),
+}
+
+#[rocket::async_trait]
+impl Fairing for TxBroadcaster {
+    fn info(&self) -> Info {
+        Info {
+            name: "Tx Broadcaster",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let state = match rocket.state::<ReservationState>() {
+            Some(state) => state,
+            None => {
+                log::error!("Tx Broadcaster: no reservation state. Not started");
+                return;
+            }
+        };
+        let lcd = state.lcd.clone();
+        let chain = state.chain.clone();
+        let nft_contract = state.nft_contract.clone();
+        let policy = state.retry_policy.clone();
+        let pool = match TaskPool::of(rocket, "Tx Broadcaster") {
+            Some(pool) => pool,
+            None => return,
+        };
+        let mut backoff = Backoff::new(self.interval, self.max_backoff);
+        let interval = self.interval;
+        let settings = self.clone();
+        let mut shutdown = rocket.shutdown();
+        rocket::tokio::spawn(async move {
+            let mut ticker = rocket::tokio::time::interval(interval);
+            loop {
+                rocket::tokio::select! {
+                    _ = ticker.tick() => {
+                        broadcast(&pool, &lcd, &chain, &nft_contract, &settings, &policy, &mut backoff).await;
+                    }
+                    _ = &mut shutdown => break,
+                }
+            }
+        });
+    }
+}
+
+async fn broadcast(
+    pool: &TaskPool,
+    lcd: &str,
+    chain: &str,
+    nft_contract: &str,
+    settings: &TxBroadcaster,
+    policy: &RetryPolicy,
+    backoff: &mut Backoff,
+) {
+    let max_retries = settings.max_retries;
+    let pending = match pool
+        .run(|c| signed_txs_to_broadcast(c, BROADCAST_LIMIT))
+        .await
+    {
+        Ok(pending) => pending,
+        Err(e) => {
+            log::error!("Tx Broadcaster: {}", e);
+            return;
+        }
+    };
+    backoff.retain(
+        &pending
+            .iter()
+            .map(|p| p.nft_id.to_string())
+            .collect::<Vec<_>>(),
+    );
+    for signed in pending {
+        let key = signed.nft_id.to_string();
+        if !backoff.is_due(&key, Instant::now()) {
+            continue;
+        }
+        let nft_id = signed.nft_id;
+        let sent = send(lcd, chain, nft_contract, &settings.signing_key, &signed).await;
+        let recorded = match sent {
+            Sent::Hash(hash) => {
+                log::info!("Tx Broadcaster: {} sent as {}", nft_id, hash);
+                backoff.succeeded(&key);
+                pool.run(move |c| set_broadcast_hash(c, &nft_id, &hash).map(|_| ()))
+                    .await
+            }
+            Sent::Unreachable(e) => {
+                let delay = backoff.failed(&key, Instant::now());
+                log::warn!(
+                    "Tx Broadcaster: {} not sent ({}). next try in {}s",
+                    nft_id,
+                    e,
+                    delay.as_secs()
+                );
+                Ok(())
+            }
+            Sent::Rejected {
+                error: e,
+                retryable,
+            } => {
+                backoff.failed(&key, Instant::now());
+                log::warn!(
+                    "Tx Broadcaster: {} rejected (attempt {}) {}",
+                    nft_id,
+                    signed.tx_retry_count + 1,
+                    e
+                );
+                let policy = policy.clone();
+                pool.run(move |c| {
+                    note_broadcast_failure(c, &nft_id, &e, max_retries, retryable, &policy).map(
+                        |errored| {
+                            if errored {
+                                log::info!("Tx Broadcaster: {} errored", nft_id)
+                            }
+                        },
+                    )
+                })
+                .await
+            }
+        };
+        if let Err(e) = recorded {
+            log::error!("Tx Broadcaster: {} {}", nft_id, e);
+        }
+    }
+}
+
+async fn send(
+    lcd: &str,
+    chain: &str,
+    nft_contract: &str,
+    signing_key: &PrivateKey,
+    signed: &SignedTxToBroadcast,
+) -> Sent {
+    let tx = match check_signed_tx(
+        &signed.signed_packet,
+        &signed.wallet_address,
+        &signed.meta_data,
+        signing_key,
+        nft_contract,
+    ) {
+        Ok(tx) => tx,
+        Err(error) => {
+            return Sent::Rejected {
+                error,
+                retryable: false,
+            }
+        }
+    };
+    match broadcast_tx(lcd, chain, &tx).await {
+        Ok(result) => match result.error() {
+            Some(error) => Sent::Rejected {
+                error,
+                retryable: true,
+            },
+            None => Sent::Hash(result.txhash),
+        },
+        Err(e) => match e.downcast_ref::<TerraRustAPIError>() {
+            Some(TerraRustAPIError::TerraLCDResponse(status, text)) => Sent::Rejected {
+                error: format!("{}-{}", status, text),
+                retryable: true,
+            },
+            _ => Sent::Unreachable(e.to_string()),
+        },
+    }
+}
@@ -0,0 +1,168 @@
+use crate::chain::{check_tx_sender, get_tx};
+use crate::db::{fail_verification, txs_pending_verification, verified_tx_hash};
+use crate::tasks::TaskPool;
+use crate::ReservationState;
+use chrono::Utc;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Orbit, Rocket};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// the most pending hashes looked up each round
+const VERIFY_LIMIT: i64 = 50;
+
+/// how `/mint/hash` treats a tx hash before the NFT counts as submitted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TxHashVerification {
+    /// any hash is taken at its word
+    Off,
+    /// the transaction must already be on chain, sent by the reserved wallet to the NFT contract
+    Reject,
+    /// a transaction not yet on chain is held in `pending_verification` until it shows up, or times out
+    Park,
+}
+
+impl FromStr for TxHashVerification {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(TxHashVerification::Off),
+            "reject" => Ok(TxHashVerification::Reject),
+            "park" => Ok(TxHashVerification::Park),
+            _ => Err(anyhow::anyhow!(
+                "Unknown tx hash verification '{}'. expecting off, reject or park",
+                s
+            )),
+        }
+    }
+}
+
+/// what the chain says about a tx hash
+pub enum Verified {
+    /// sent by the wallet, to the NFT contract
+    Vouched,
+    Refused(String),
+    NotFound(String),
+}
+
+/// look `hash` up on `lcd`, checking it was sent by `wallet` to the NFT contract
+pub async fn verify_tx_hash(
+    lcd: &str,
+    chain: &str,
+    nft_contract: &str,
+    wallet: &str,
+    hash: &str,
+) -> Verified {
+    match get_tx(lcd, chain, hash).await {
+        Ok(tx) => match check_tx_sender(nft_contract, wallet, &tx) {
+            Ok(()) => Verified::Vouched,
+            Err(e) => Verified::Refused(e),
+        },
+        Err(e) => Verified::NotFound(e.to_string()),
+    }
+}
+
+/// Looks for the transactions behind hashes parked by `/mint/hash`. one sent by the reserved wallet to the
+/// NFT contract moves its NFT on to submitted, for the tx watcher. one sent by anyone else, or not found
+/// before its `verify_by`, is dropped and the NFT is reserved to the wallet again.
+pub struct TxVerifier {
+    pub interval: Duration,
+}
+
+#[rocket::async_trait]
+impl Fairing for TxVerifier {
+    fn info(&self) -> Info {
+        Info {
+            name: "Tx Verifier",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let state = match rocket.state::<ReservationState>() {
+            Some(state) => state,
+            None => {
+                log::error!("Tx Verifier: no reservation state. Not started");
+                return;
+            }
+        };
+        let lcd = state.tx_verification_lcd.clone();
+        let chain = state.chain.clone();
+        let nft_contract = state.nft_contract.clone();
+        let pool = match TaskPool::of(rocket, "Tx Verifier") {
+            Some(pool) => pool,
+            None => return,
+        };
+        let interval = self.interval;
+        let mut shutdown = rocket.shutdown();
+        rocket::tokio::spawn(async move {
+            let mut ticker = rocket::tokio::time::interval(interval);
+            loop {
+                rocket::tokio::select! {
+                    _ = ticker.tick() => {
+                        verify(&pool, &lcd, &chain, &nft_contract).await;
+                    }
+                    _ = &mut shutdown => break,
+                }
+            }
+        });
+    }
+}
+
+async fn verify(pool: &TaskPool, lcd: &str, chain: &str, nft_contract: &str) {
+    let pending = match pool
+        .run(|c| txs_pending_verification(c, VERIFY_LIMIT))
+        .await
+    {
+        Ok(pending) => pending,
+        Err(e) => {
+            log::error!("Tx Verifier: {}", e);
+            return;
+        }
+    };
+    for parked in pending {
+        let nft_id = parked.nft_id;
+        let hash = parked.txhash.clone();
+        let recorded = match verify_tx_hash(
+            lcd,
+            chain,
+            nft_contract,
+            &parked.wallet_address,
+            &parked.txhash,
+        )
+        .await
+        {
+            Verified::Vouched => {
+                log::info!("Tx Verifier: {} verified {}", nft_id, hash);
+                pool.run(move |c| verified_tx_hash(c, &nft_id, &hash).map(|_| ()))
+                    .await
+            }
+            Verified::Refused(e) => {
+                log::warn!("Tx Verifier: {} refused {} {}", nft_id, hash, e);
+                pool.run(move |c| fail_verification(c, &nft_id, &hash, &e).map(|_| ()))
+                    .await
+            }
+            Verified::NotFound(e) => {
+                if parked.verify_by > Utc::now() {
+                    log::debug!("Tx Verifier: {} {} not found yet {}", nft_id, hash, e);
+                    continue;
+                }
+                log::warn!("Tx Verifier: {} {} timed out", nft_id, hash);
+                pool.run(move |c| {
+                    fail_verification(
+                        c,
+                        &nft_id,
+                        &hash,
+                        "tx not found before verification timed out",
+                    )
+                    .map(|_| ())
+                })
+                .await
+            }
+        };
+        if let Err(e) = recorded {
+            log::error!("Tx Verifier: {} {}", nft_id, e);
+        }
+    }
+}
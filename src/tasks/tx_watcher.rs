@@ -0,0 +1,176 @@
+use crate::chain::{get_tx, parse_mint_result, parse_result, Backoff, RetryPolicy};
+use crate::db::{
+    nft_assign_owner, nft_assign_tx_result, reservations_in_mint_process, reservations_in_process,
+};
+use crate::requests::ReservationTxResultRequest;
+use crate::tasks::TaskPool;
+use crate::ReservationState;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Orbit, Rocket};
+use std::time::{Duration, Instant};
+
+/// the most submitted transactions looked at each round
+const WATCH_LIMIT: i64 = 100;
+
+/// Watches submitted mint transactions on the LCD and records their outcome, in place of running
+/// `examples/check_in_process.rs` and `examples/check_free.rs`.
+///
+/// transactions not found yet are looked for again with an increasing delay, up to `max_backoff`.
+pub struct TxWatcher {
+    pub interval: Duration,
+    pub max_backoff: Duration,
+}
+
+/// a submitted transaction, and the NFT name for mint run transactions
+struct Watched {
+    hash: String,
+    mint_run_name: Option<String>,
+}
+
+#[rocket::async_trait]
+impl Fairing for TxWatcher {
+    fn info(&self) -> Info {
+        Info {
+            name: "Tx Watcher",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let state = match rocket.state::<ReservationState>() {
+            Some(state) => state,
+            None => {
+                log::error!("Tx Watcher: no reservation state. Not started");
+                return;
+            }
+        };
+        let lcd = state.lcd.clone();
+        let chain = state.chain.clone();
+        let nft_contract = state.nft_contract.clone();
+        let policy = state.retry_policy.clone();
+        let pool = match TaskPool::of(rocket, "Tx Watcher") {
+            Some(pool) => pool,
+            None => return,
+        };
+        let mut backoff = Backoff::new(self.interval, self.max_backoff);
+        let interval = self.interval;
+        let mut shutdown = rocket.shutdown();
+        rocket::tokio::spawn(async move {
+            let mut ticker = rocket::tokio::time::interval(interval);
+            loop {
+                rocket::tokio::select! {
+                    _ = ticker.tick() => {
+                        watch(&pool, &lcd, &chain, &nft_contract, &policy, &mut backoff).await;
+                    }
+                    _ = &mut shutdown => break,
+                }
+            }
+        });
+    }
+}
+
+async fn watch(
+    pool: &TaskPool,
+    lcd: &str,
+    chain: &str,
+    nft_contract: &str,
+    policy: &RetryPolicy,
+    backoff: &mut Backoff,
+) {
+    let watched = match pool
+        .run(|c| {
+            let mut watched = reservations_in_process(c, WATCH_LIMIT)?
+                .into_iter()
+                .map(|hash| Watched {
+                    hash,
+                    mint_run_name: None,
+                })
+                .collect::<Vec<Watched>>();
+            watched.extend(
+                reservations_in_mint_process(c, WATCH_LIMIT)?
+                    .into_iter()
+                    .map(|(hash, name)| Watched {
+                        hash,
+                        mint_run_name: Some(name),
+                    }),
+            );
+            Ok::<_, postgres::Error>(watched)
+        })
+        .await
+    {
+        Ok(watched) => watched,
+        Err(e) => {
+            log::error!("Tx Watcher: {}", e);
+            return;
+        }
+    };
+    backoff.retain(&watched.iter().map(|w| w.hash.clone()).collect::<Vec<_>>());
+    for watching in watched {
+        if !backoff.is_due(&watching.hash, Instant::now()) {
+            continue;
+        }
+        match get_tx(lcd, chain, &watching.hash).await {
+            Ok(tx) => {
+                let result = match &watching.mint_run_name {
+                    Some(name) => parse_mint_result(nft_contract, &tx, name),
+                    None => parse_result(nft_contract, &tx),
+                };
+                let mint_run = watching.mint_run_name.is_some();
+                // the rest of a mint run transaction may have worked, so only a failed
+                // transaction fails all of its NFTs
+                if mint_run && !result.success && tx.code.is_none() {
+                    backoff.failed(&watching.hash, Instant::now());
+                    log::warn!(
+                        "Tx Watcher: {} {} {}",
+                        watching.hash,
+                        watching.mint_run_name.unwrap_or_default(),
+                        result.error.unwrap_or_default()
+                    );
+                    continue;
+                }
+                let policy = policy.clone();
+                match pool
+                    .run(move |c| apply_result(c, result, mint_run, &policy))
+                    .await
+                {
+                    Ok(_) => backoff.succeeded(&watching.hash),
+                    Err(e) => log::error!("Tx Watcher: {} {}", watching.hash, e),
+                }
+            }
+            Err(e) => {
+                let delay = backoff.failed(&watching.hash, Instant::now());
+                log::debug!(
+                    "Tx Watcher: {} not found ({}). next look in {}s",
+                    watching.hash,
+                    e,
+                    delay.as_secs()
+                );
+            }
+        }
+    }
+}
+
+/// record the outcome the same way `/mint/tx_result` and `/mint/assign-owner` do
+fn apply_result(
+    conn: &mut postgres::Client,
+    result: ReservationTxResultRequest,
+    mint_run: bool,
+    policy: &RetryPolicy,
+) -> Result<u64, postgres::Error> {
+    log::info!(
+        "Tx Watcher: {} {}",
+        result.tx,
+        if result.success { "minted" } else { "failed" }
+    );
+    match (
+        mint_run,
+        result.success,
+        &result.wallet_address,
+        &result.token_id,
+    ) {
+        (true, true, Some(wallet), Some(token_id)) => {
+            nft_assign_owner(conn, wallet.clone(), token_id.clone())
+        }
+        _ => nft_assign_tx_result(conn, result, policy),
+    }
+}
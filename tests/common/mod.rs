@@ -0,0 +1,187 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+use chrono::Utc;
+use pfc_reservation::migrations;
+use postgres::config::Host;
+use postgres::{Client, Config, NoTls};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::env;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+static DATABASES: AtomicU64 = AtomicU64::new(0);
+
+/// a freshly migrated database, created on the server `DATABASE_URL` points at and dropped afterwards
+pub struct TestDb {
+    config: Config,
+    name: String,
+}
+
+impl TestDb {
+    /// None (and the test should return) when `DATABASE_URL` isn't set
+    pub fn create() -> Option<TestDb> {
+        let db = TestDb::empty()?;
+        migrations::migrate_up(&mut db.connect()).expect("Unable to migrate test database");
+        Some(db)
+    }
+
+    /// as `create`, without running the migrations
+    pub fn empty() -> Option<TestDb> {
+        let db_url = match env::var("DATABASE_URL") {
+            Ok(url) => url,
+            Err(_) => {
+                eprintln!("DATABASE_URL is not set, skipping");
+                return None;
+            }
+        };
+        let config = Config::from_str(&db_url).expect("Unable to parse DATABASE_URL");
+        let name = format!(
+            "pfc_test_{}_{}_{}",
+            std::process::id(),
+            Utc::now().timestamp_nanos(),
+            DATABASES.fetch_add(1, Ordering::SeqCst)
+        );
+        let mut server = config
+            .connect(NoTls)
+            .expect("Unable to connect to DATABASE_URL");
+        server
+            .batch_execute(&format!("create database {}", name))
+            .expect("Unable to create test database");
+        Some(TestDb { config, name })
+    }
+
+    pub fn connect(&self) -> Client {
+        self.config
+            .clone()
+            .dbname(&self.name)
+            .connect(NoTls)
+            .expect("Unable to connect to test database")
+    }
+
+    /// a connection string for the test database, for code which connects by itself
+    pub fn url(&self) -> String {
+        let quote = |value: &str| format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"));
+        let mut params = vec![format!("dbname={}", quote(&self.name))];
+        match self.config.get_hosts().first() {
+            Some(Host::Tcp(host)) => params.push(format!("host={}", quote(host))),
+            Some(Host::Unix(path)) => {
+                params.push(format!("host={}", quote(&path.to_string_lossy())))
+            }
+            None => {}
+        }
+        if let Some(port) = self.config.get_ports().first() {
+            params.push(format!("port={}", port));
+        }
+        if let Some(user) = self.config.get_user() {
+            params.push(format!("user={}", quote(user)));
+        }
+        if let Some(password) = self.config.get_password() {
+            params.push(format!(
+                "password={}",
+                quote(&String::from_utf8_lossy(password))
+            ));
+        }
+        params.join(" ")
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        if let Ok(mut server) = self.config.connect(NoTls) {
+            if let Err(e) = server.batch_execute(&format!(
+                "drop database if exists {} with (force)",
+                self.name
+            )) {
+                eprintln!("Unable to drop {}: {}", self.name, e);
+            }
+        }
+    }
+}
+
+/// a canned LCD. `GET /txs/<hash>` answers with the transaction added for the hash, and `POST /txs` with the
+/// broadcast response, keeping the bodies posted. anything else is a 404
+#[derive(Default)]
+pub struct MockLcd {
+    txs: HashMap<String, Value>,
+    broadcast: Option<Value>,
+}
+
+impl MockLcd {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tx(mut self, hash: &str, tx: Value) -> Self {
+        self.txs.insert(String::from(hash), tx);
+        self
+    }
+
+    pub fn broadcast(mut self, response: Value) -> Self {
+        self.broadcast = Some(response);
+        self
+    }
+
+    /// start answering on a port of its own. returns the LCD url, and the bodies posted to `/txs`
+    pub fn serve(self) -> (String, Arc<Mutex<Vec<Value>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Unable to bind mock LCD");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let posted = Arc::new(Mutex::new(vec![]));
+        let received = posted.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    if reader.read_line(&mut header).unwrap() == 0 || header == "\r\n" {
+                        break;
+                    }
+                    if let Some(value) = header.to_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let mut parts = request_line.split(' ');
+                let method = parts.next().unwrap_or_default();
+                let path = parts.next().unwrap_or_default();
+                let answer = match (method, path) {
+                    ("GET", _) => path
+                        .strip_prefix("/txs/")
+                        .and_then(|hash| self.txs.get(hash))
+                        .cloned(),
+                    ("POST", "/txs") => self.broadcast.clone().inspect(|_| {
+                        received
+                            .lock()
+                            .unwrap()
+                            .push(serde_json::from_slice(&body).unwrap());
+                    }),
+                    _ => None,
+                };
+                let (status, body) = match answer {
+                    Some(answer) => ("200 OK", answer.to_string()),
+                    None => ("404 Not Found", json!({"error": "not found"}).to_string()),
+                };
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+            }
+        });
+        (url, posted)
+    }
+}
@@ -0,0 +1,133 @@
+//! Reserves from many connections at once and checks nothing is over-allocated.
+//!
+//! Creates (and drops) its own database on the server `DATABASE_URL` points at, and is skipped when it isn't set.
+//!
+//! DATABASE_URL=postgres://postgres@localhost/postgres cargo test --test concurrent_reservation
+mod common;
+
+use chrono::{Duration, Utc};
+use common::TestDb;
+use pfc_reservation::db;
+use postgres::Client;
+use std::collections::HashSet;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use uuid::Uuid;
+
+const WALLETS: usize = 4;
+/// connections reserving for each wallet at the same time
+const THREADS: usize = 6;
+/// attempts made on each connection
+const ATTEMPTS: usize = 3;
+const MAX_RESERVATIONS: usize = 3;
+const NFTS: usize = 40;
+const SALT: &str = "concurrency";
+
+/// enough NFTs, and an open default stage to draw from
+fn seed_database(conn: &mut Client) {
+    for i in 0..NFTS {
+        conn.execute(
+            "insert into NFT(name, meta_data, svg) values($1, $2, '{}')",
+            &[
+                &format!("concurrency-{}", i),
+                &serde_json::json!({ "token_uri": format!("concurrency-{}", i) }),
+            ],
+        )
+        .expect("Unable to insert NFT");
+    }
+    conn.execute(
+        "insert into stage_whitelist(code, name, is_default, stage_open) values('concurrency','Concurrency Test',true, now() - interval '1 minute')",
+        &[],
+    )
+    .expect("Unable to insert stage");
+}
+
+#[test]
+fn concurrent_reservations_are_not_over_allocated() {
+    let db = match TestDb::create() {
+        Some(db) => Arc::new(db),
+        None => return,
+    };
+    seed_database(&mut db.connect());
+
+    let wallets = (0..WALLETS)
+        .map(|w| format!("terra1{:038}", w))
+        .collect::<Vec<String>>();
+    let start = Arc::new(Barrier::new(WALLETS * THREADS));
+    let mut handles = Vec::new();
+    for wallet in &wallets {
+        for _ in 0..THREADS {
+            let db = db.clone();
+            let start = start.clone();
+            let wallet = wallet.clone();
+            handles.push(thread::spawn(move || {
+                let mut conn = db.connect();
+                start.wait();
+                (0..ATTEMPTS)
+                    .filter_map(|_| {
+                        let (_, result) = db::do_reservation(
+                            &mut conn,
+                            &wallet,
+                            &(Utc::now() + Duration::minutes(10)),
+                            MAX_RESERVATIONS,
+                            SALT,
+                        );
+                        result.ok().map(|(nft_id, _)| (wallet.clone(), nft_id))
+                    })
+                    .collect::<Vec<(String, Uuid)>>()
+            }));
+        }
+    }
+    let granted = handles
+        .into_iter()
+        .flat_map(|handle| handle.join().unwrap())
+        .collect::<Vec<(String, Uuid)>>();
+
+    let distinct = granted.iter().map(|g| g.1).collect::<HashSet<Uuid>>();
+    assert_eq!(
+        distinct.len(),
+        granted.len(),
+        "the same NFT was handed out more than once"
+    );
+    let mut conn = db.connect();
+    for wallet in &wallets {
+        let count = granted.iter().filter(|g| &g.0 == wallet).count();
+        assert_eq!(
+            count, MAX_RESERVATIONS,
+            "{} was granted {} reservations (max {})",
+            wallet, count, MAX_RESERVATIONS
+        );
+        let held: i64 = conn
+            .query_one(
+                "select count(*) from NFT where reserved_to_wallet_address=$1 and status='reserved'",
+                &[wallet],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!(held as usize, MAX_RESERVATIONS, "{} holds {}", wallet, held);
+    }
+    let reserved_twice: i64 = conn
+        .query_one(
+            "select count(*) from (select nft_reserved from NFT_Reservation where nft_reserved is not null group by nft_reserved having count(*) > 1) r",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert_eq!(reserved_twice, 0, "an NFT was reserved more than once");
+
+    let row = conn
+        .query_one(
+            "select count(*), sum(rejected_count)::bigint from NFT_Reservation where nft_reserved is null",
+            &[],
+        )
+        .unwrap();
+    let (rejections, turned_down): (i64, i64) = (row.get(0), row.get(1));
+    assert_eq!(
+        rejections, WALLETS as i64,
+        "rejected attempts are kept once for each wallet and reason"
+    );
+    assert_eq!(
+        turned_down as usize,
+        WALLETS * (THREADS * ATTEMPTS - MAX_RESERVATIONS)
+    );
+}
@@ -0,0 +1,201 @@
+//! Checks what happens to an NFT whose mint fails: retried, held for the wallet, dead lettered or released.
+//!
+//! Each test creates (and drops) its own database on the server `DATABASE_URL` points at,
+//! and is skipped when it isn't set.
+//!
+//! DATABASE_URL=postgres://postgres@localhost/postgres cargo test --test failed_mints
+mod common;
+
+use chrono::{Duration, Utc};
+use common::TestDb;
+use pfc_reservation::chain::RetryPolicy;
+use pfc_reservation::db;
+use pfc_reservation::requests::ReservationTxResultRequest;
+use postgres::Client;
+use uuid::Uuid;
+
+const WALLET: &str = "terra1failedmintswallet000000000000000000000";
+
+fn policy(max_retries: i32, errored_hold: i32) -> RetryPolicy {
+    RetryPolicy {
+        max_retries,
+        retryable_codespaces: vec![],
+        release_after: 0,
+        errored_hold,
+    }
+}
+
+/// an NFT reserved to `WALLET`
+fn reserved_nft(conn: &mut Client) -> Uuid {
+    let name = format!("failed-mint-{}", Utc::now().timestamp_nanos());
+    conn.query_one(
+        r#"insert into NFT(name, meta_data, svg, status, reserved_to_wallet_address, reserved_until)
+            values($1, $2, '{}', 'reserved', $3, $4) returning id"#,
+        &[
+            &name,
+            &serde_json::json!({ "token_uri": name }),
+            &WALLET,
+            &(Utc::now() + Duration::minutes(10)),
+        ],
+    )
+    .unwrap()
+    .get(0)
+}
+
+/// (status, txhash)
+fn status(conn: &mut Client, nft: &Uuid) -> (String, Option<String>) {
+    let row = conn
+        .query_one("select status, txhash from NFT where id=$1", &[nft])
+        .unwrap();
+    (row.get(0), row.get(1))
+}
+
+fn failed(txhash: &str) -> ReservationTxResultRequest {
+    ReservationTxResultRequest {
+        tx: txhash.into(),
+        wallet_address: Some(WALLET.into()),
+        assigned_on: None,
+        token_id: None,
+        success: false,
+        error: Some("11/sdk-out of gas".into()),
+    }
+}
+
+#[test]
+fn only_transactions_the_lcd_turned_away_are_broadcast_again() {
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    let policy = policy(3, 60);
+    let nft = reserved_nft(&mut conn);
+    let packet = serde_json::json!({"tx": "signed"});
+
+    // turned away by the LCD, so the sequence wasn't used: send it again
+    assert_eq!(db::set_tx_for_nft(&mut conn, &nft, &packet).unwrap(), 1);
+    assert!(db::note_broadcast_failure(&mut conn, &nft, "timeout", 1, true, &policy).unwrap());
+    assert_eq!(status(&mut conn, &nft), ("submitted".into(), None));
+    let queued = db::signed_txs_to_broadcast(&mut conn, 10).unwrap();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].tx_retry_count, 0);
+
+    // failed in a block: the same bytes would be refused, so the wallet has to sign again
+    assert_eq!(db::set_broadcast_hash(&mut conn, &nft, "HASH1").unwrap(), 1);
+    assert_eq!(
+        db::nft_assign_tx_result(&mut conn, failed("HASH1"), &policy).unwrap(),
+        1
+    );
+    let (state, txhash) = status(&mut conn, &nft);
+    assert_eq!(state, "errored");
+    assert!(txhash.is_some(), "the failed transaction's hash is kept");
+    assert!(db::signed_txs_to_broadcast(&mut conn, 10)
+        .unwrap()
+        .is_empty());
+    assert_eq!(db::requeue_failed_nft(&mut conn, &nft, &policy).unwrap(), 1);
+    assert_eq!(status(&mut conn, &nft).0, "errored");
+    assert!(db::signed_txs_to_broadcast(&mut conn, 10)
+        .unwrap()
+        .is_empty());
+
+    let resigned = serde_json::json!({"tx": "signed again"});
+    assert_eq!(db::set_tx_for_nft(&mut conn, &nft, &resigned).unwrap(), 1);
+    let queued = db::signed_txs_to_broadcast(&mut conn, 10).unwrap();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].signed_packet, resigned);
+}
+
+#[test]
+fn dead_lettered_broadcasts_are_sent_again_when_requeued() {
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    let policy = policy(0, 60);
+    let nft = reserved_nft(&mut conn);
+
+    db::set_tx_for_nft(&mut conn, &nft, &serde_json::json!({"tx": "signed"})).unwrap();
+    assert!(db::note_broadcast_failure(&mut conn, &nft, "timeout", 1, true, &policy).unwrap());
+    assert_eq!(status(&mut conn, &nft), ("dead_letter".into(), None));
+
+    assert_eq!(db::requeue_failed_nft(&mut conn, &nft, &policy).unwrap(), 1);
+    assert_eq!(status(&mut conn, &nft), ("submitted".into(), None));
+    assert_eq!(db::signed_txs_to_broadcast(&mut conn, 10).unwrap().len(), 1);
+}
+
+#[test]
+fn errored_nfts_are_held_until_their_deadline() {
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    let nft = reserved_nft(&mut conn);
+    db::set_tx_hash_for_nft(&mut conn, &nft, "HASH1").unwrap();
+    db::nft_assign_tx_result(&mut conn, failed("HASH1"), &policy(3, 60)).unwrap();
+    assert_eq!(status(&mut conn, &nft).0, "errored");
+    assert_eq!(db::release_expired_errored(&mut conn).unwrap(), 0);
+
+    db::set_tx_hash_for_nft(&mut conn, &nft, "HASH2").unwrap();
+    db::nft_assign_tx_result(&mut conn, failed("HASH2"), &policy(3, 0)).unwrap();
+    assert_eq!(status(&mut conn, &nft).0, "errored");
+    assert_eq!(db::release_expired_errored(&mut conn).unwrap(), 1);
+    assert_eq!(status(&mut conn, &nft), ("available".into(), None));
+}
+
+#[test]
+fn errors_outside_the_retryable_codespaces_dead_letter_straight_away() {
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    let policy = RetryPolicy {
+        retryable_codespaces: vec!["sdk".into()],
+        ..policy(3, 60)
+    };
+    let nft = reserved_nft(&mut conn);
+    db::set_tx_hash_for_nft(&mut conn, &nft, "HASH1").unwrap();
+    let mut result = failed("HASH1");
+    result.error = Some("4/wasm-execute wasm contract failed".into());
+    assert_eq!(
+        db::nft_assign_tx_result(&mut conn, result, &policy).unwrap(),
+        1
+    );
+    assert_eq!(status(&mut conn, &nft).0, "dead_letter");
+
+    let nft = reserved_nft(&mut conn);
+    db::set_tx_hash_for_nft(&mut conn, &nft, "HASH2").unwrap();
+    db::nft_assign_tx_result(&mut conn, failed("HASH2"), &policy).unwrap();
+    assert_eq!(status(&mut conn, &nft).0, "errored");
+}
+
+#[test]
+fn nfts_are_released_after_release_after_failures() {
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    let policy = RetryPolicy {
+        release_after: 2,
+        ..policy(3, 60)
+    };
+    let nft = reserved_nft(&mut conn);
+    db::set_tx_hash_for_nft(&mut conn, &nft, "HASH1").unwrap();
+    db::nft_assign_tx_result(&mut conn, failed("HASH1"), &policy).unwrap();
+    assert_eq!(status(&mut conn, &nft).0, "errored");
+
+    db::set_tx_hash_for_nft(&mut conn, &nft, "HASH2").unwrap();
+    db::nft_assign_tx_result(&mut conn, failed("HASH2"), &policy).unwrap();
+    assert_eq!(status(&mut conn, &nft), ("available".into(), None));
+    let wallet: Option<String> = conn
+        .query_one(
+            "select reserved_to_wallet_address from NFT where id=$1",
+            &[&nft],
+        )
+        .unwrap()
+        .get(0);
+    assert_eq!(wallet, None);
+}
@@ -0,0 +1,82 @@
+//! Checks collection manifests are read as `pfc-reservation import` expects, and each NFT is checked
+//! against CW721 `Metadata`.
+//!
+//! cargo test --test manifest
+use pfc_reservation::manifest::read_manifest;
+use serde_json::json;
+use std::fs;
+use std::path::PathBuf;
+
+/// a fresh directory for the test's manifest
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pfc-manifest-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn record(name: &str) -> serde_json::Value {
+    json!({
+        "name": name,
+        "meta": {"token_uri": format!("ipfs://meta/{}", name), "attributes": [{"trait_type": "hat", "value": "bagel"}]},
+        "svg": {"svg": "<svg/>"},
+        "ipfs_image": "",
+        "ipfs_meta": ""
+    })
+}
+
+#[test]
+fn reads_jsonl_line_by_line() {
+    let dir = scratch("jsonl");
+    let path = dir.join("peeps.jsonl");
+    fs::write(
+        &path,
+        format!("{}\n\n{}\nnot json\n", record("Peep 1"), record("Peep 2")),
+    )
+    .unwrap();
+    let records = read_manifest(&path).unwrap();
+
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].0, format!("{}:1", path.display()));
+    assert_eq!(records[1].0, format!("{}:3", path.display()));
+    let nft = records[1].1.as_ref().unwrap();
+    assert_eq!(nft.name, "Peep 2");
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&nft.meta).unwrap()["token_uri"],
+        json!("ipfs://meta/Peep 2")
+    );
+    assert!(records[2].1.is_err());
+}
+
+#[test]
+fn reads_json_files_in_name_order() {
+    let dir = scratch("dir");
+    fs::write(dir.join("2.json"), record("Peep 2").to_string()).unwrap();
+    fs::write(dir.join("1.json"), record("Peep 1").to_string()).unwrap();
+    fs::write(dir.join("README.md"), "not an NFT").unwrap();
+    let names = read_manifest(&dir)
+        .unwrap()
+        .into_iter()
+        .map(|(_, nft)| nft.unwrap().name)
+        .collect::<Vec<_>>();
+
+    assert_eq!(names, vec!["Peep 1", "Peep 2"]);
+}
+
+#[test]
+fn metadata_is_checked() {
+    let dir = scratch("meta");
+    let path = dir.join("peeps.jsonl");
+    let mut bad_meta = record("Peep 1");
+    bad_meta["meta"] = json!({"image": "ipfs://image/1"});
+    let mut meta_as_string = record("Peep 2");
+    meta_as_string["meta"] = json!(record("Peep 2")["meta"].to_string());
+    fs::write(&path, format!("{}\n{}\n", bad_meta, meta_as_string)).unwrap();
+    let records = read_manifest(&path).unwrap();
+
+    let errors = records[0].1.as_ref().unwrap().validate().unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].field, "meta");
+    assert!(errors[0].message.starts_with("is not CW721 metadata"));
+    assert!(records[1].1.as_ref().unwrap().validate().is_ok());
+}
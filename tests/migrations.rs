@@ -0,0 +1,131 @@
+//! Checks migrations roll back and forward again, and refuse a database set up by hand until it is baselined,
+//! or one migrated by a newer binary.
+//!
+//! Each test creates (and drops) its own database on the server `DATABASE_URL` points at,
+//! and is skipped when it isn't set.
+//!
+//! DATABASE_URL=postgres://postgres@localhost/postgres cargo test --test migrations
+mod common;
+
+use common::TestDb;
+use pfc_reservation::migrations::{baseline, migrate_down, migrate_up, on_startup, MIGRATIONS};
+use postgres::Client;
+
+fn recorded(conn: &mut Client) -> Vec<String> {
+    conn.query(
+        "select version from schema_migrations order by version",
+        &[],
+    )
+    .unwrap()
+    .iter()
+    .map(|r| r.get(0))
+    .collect()
+}
+
+fn versions(count: usize) -> Vec<String> {
+    MIGRATIONS[..count]
+        .iter()
+        .map(|m| m.version.to_string())
+        .collect()
+}
+
+#[test]
+fn migrates_an_empty_database() {
+    let db = match TestDb::empty() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    assert_eq!(migrate_up(&mut conn).unwrap().len(), MIGRATIONS.len());
+    assert!(migrate_up(&mut conn).unwrap().is_empty());
+}
+
+#[test]
+fn refuses_a_hand_made_database_until_baselined() {
+    let db = match TestDb::empty() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    conn.batch_execute("create table NFT (id uuid primary key)")
+        .unwrap();
+
+    let refused = migrate_up(&mut conn).unwrap_err().to_string();
+    assert!(
+        refused.contains("migrate baseline 2021-10-17-initial"),
+        "{}",
+        refused
+    );
+
+    let latest = MIGRATIONS.last().unwrap().version;
+    assert_eq!(baseline(&mut conn, latest).unwrap().len(), MIGRATIONS.len());
+    assert!(migrate_up(&mut conn).unwrap().is_empty());
+}
+
+#[test]
+fn rolls_back_and_forward_again() {
+    let db = match TestDb::empty() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    migrate_up(&mut conn).unwrap();
+    assert_eq!(recorded(&mut conn), versions(MIGRATIONS.len()));
+
+    let rolled_back = migrate_down(&mut conn, 3).unwrap();
+    let newest = MIGRATIONS
+        .iter()
+        .rev()
+        .take(3)
+        .map(|m| m.version)
+        .collect::<Vec<&str>>();
+    assert_eq!(rolled_back, newest);
+    assert_eq!(recorded(&mut conn), versions(MIGRATIONS.len() - 3));
+    assert!(
+        on_startup(&db.url(), false).is_err(),
+        "started with pending migrations"
+    );
+
+    assert_eq!(
+        migrate_down(&mut conn, MIGRATIONS.len()).unwrap().len(),
+        MIGRATIONS.len() - 3
+    );
+    assert!(recorded(&mut conn).is_empty());
+    let nft_table: bool = conn
+        .query_one("select to_regclass('nft') is not null", &[])
+        .unwrap()
+        .get(0);
+    assert!(
+        !nft_table,
+        "the initial migration left the NFT table behind"
+    );
+
+    assert_eq!(migrate_up(&mut conn).unwrap().len(), MIGRATIONS.len());
+    assert_eq!(recorded(&mut conn), versions(MIGRATIONS.len()));
+    on_startup(&db.url(), false).unwrap();
+}
+
+#[test]
+fn refuses_a_schema_newer_than_the_binary() {
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    db.connect()
+        .execute(
+            "insert into schema_migrations (version) values ('2999-01-01-from-the-future')",
+            &[],
+        )
+        .unwrap();
+
+    for apply in [false, true] {
+        let refused = on_startup(&db.url(), apply).unwrap_err().to_string();
+        assert!(refused.contains("newer than this binary"), "{}", refused);
+        assert!(
+            refused.contains("2999-01-01-from-the-future"),
+            "{}",
+            refused
+        );
+    }
+    assert!(migrate_down(&mut db.connect(), 1).is_err());
+}
@@ -0,0 +1,143 @@
+//! Checks how the minter batches free stage mints, with a stand-in LCD client recording what it's asked to send.
+//!
+//! cargo test --test minter
+use pfc_reservation::minter::{mint_msg, LcdClient, Minter};
+use pfc_reservation::requests::{Metadata, MintReservation};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+const NFT_CONTRACT: &str = "terra1nftcontract";
+const OWNER: &str = "terra1owner";
+const WALLET: &str = "terra1uag78r7hn6we00ddudd9juv2f5nvapf0qtuskg";
+
+/// hands out `HASH<n>` for each broadcast, failing any with more than `fail_over` messages
+struct MockLcd {
+    sent: Arc<Mutex<Vec<Vec<Value>>>>,
+    fail_over: usize,
+}
+
+#[rocket::async_trait]
+impl LcdClient for MockLcd {
+    async fn broadcast(&self, contract: &str, execute_msgs: Vec<Value>) -> anyhow::Result<String> {
+        assert_eq!(contract, NFT_CONTRACT);
+        if execute_msgs.len() > self.fail_over {
+            return Err(anyhow::anyhow!("out of gas"));
+        }
+        let mut sent = self.sent.lock().unwrap();
+        sent.push(execute_msgs);
+        Ok(format!("HASH{}", sent.len()))
+    }
+}
+
+fn minter(
+    batch_size: usize,
+    dry_run: bool,
+    fail_over: usize,
+) -> (Minter, Arc<Mutex<Vec<Vec<Value>>>>) {
+    let sent = Arc::new(Mutex::new(vec![]));
+    (
+        Minter {
+            sender: String::from(OWNER),
+            nft_contract: String::from(NFT_CONTRACT),
+            batch_size,
+            dry_run,
+            lcd: Box::new(MockLcd {
+                sent: sent.clone(),
+                fail_over,
+            }),
+        },
+        sent,
+    )
+}
+
+fn reservation(n: usize) -> MintReservation {
+    MintReservation {
+        wallet_address: String::from(WALLET),
+        nft_id: Uuid::from_u128(n as u128),
+        name: format!("Peep {}", n),
+        meta_data: Metadata {
+            token_uri: format!("ipfs://meta/{}", n),
+            image: Some(format!("ipfs://image/{}", n)),
+            image_data: None,
+            external_url: None,
+            description: None,
+            name: Some(format!("Peep {}", n)),
+            attributes: None,
+            background_color: None,
+            animation_url: None,
+            youtube_url: None,
+        },
+    }
+}
+
+#[test]
+fn mint_msg_carries_the_metadata() {
+    let msg = mint_msg(&reservation(1));
+    assert_eq!(msg["mint"]["token_id"], json!("Peep 1"));
+    assert_eq!(msg["mint"]["owner"], json!(WALLET));
+    assert_eq!(msg["mint"]["token_uri"], json!("ipfs://meta/1"));
+    assert_eq!(msg["mint"]["extension"]["image"], json!("ipfs://image/1"));
+    assert!(msg["mint"]["extension"].get("token_uri").is_none());
+}
+
+#[rocket::async_test]
+async fn mints_in_batches() {
+    let (minter, sent) = minter(2, false, usize::MAX);
+    let reservations = (1..=5).map(reservation).collect::<Vec<_>>();
+    let batches = minter.mint(&reservations).await;
+
+    assert_eq!(
+        batches.iter().map(|b| b.nft_ids.len()).collect::<Vec<_>>(),
+        vec![2, 2, 1]
+    );
+    assert_eq!(
+        batches
+            .iter()
+            .map(|b| b.tx_hash.clone())
+            .collect::<Vec<_>>(),
+        vec![
+            Some(String::from("HASH1")),
+            Some(String::from("HASH2")),
+            Some(String::from("HASH3"))
+        ]
+    );
+    assert_eq!(batches[2].nft_ids, vec![reservations[4].nft_id]);
+    assert_eq!(sent.lock().unwrap()[1][0], mint_msg(&reservations[2]));
+}
+
+#[rocket::async_test]
+async fn failed_broadcasts_are_reported() {
+    let (minter, sent) = minter(2, false, 1);
+    let batches = minter
+        .mint(&(1..=3).map(reservation).collect::<Vec<_>>())
+        .await;
+
+    assert!(batches[0].tx_hash.is_none());
+    assert_eq!(batches[0].error.as_deref(), Some("out of gas"));
+    assert_eq!(batches[1].tx_hash.as_deref(), Some("HASH1"));
+    assert_eq!(sent.lock().unwrap().len(), 1);
+}
+
+#[rocket::async_test]
+async fn dry_run_hands_back_unsigned_messages() {
+    let (minter, sent) = minter(10, true, usize::MAX);
+    let reservations = (1..=2).map(reservation).collect::<Vec<_>>();
+    let batches = minter.mint(&reservations).await;
+
+    assert!(sent.lock().unwrap().is_empty());
+    assert_eq!(batches.len(), 1);
+    assert!(batches[0].tx_hash.is_none());
+    let messages = batches[0]
+        .messages
+        .clone()
+        .expect("dry run should have messages");
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0]["type"], json!("wasm/MsgExecuteContract"));
+    assert_eq!(messages[0]["value"]["sender"], json!(OWNER));
+    assert_eq!(messages[0]["value"]["contract"], json!(NFT_CONTRACT));
+    assert_eq!(
+        messages[1]["value"]["execute_msg"],
+        mint_msg(&reservations[1])
+    );
+}
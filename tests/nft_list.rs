@@ -0,0 +1,52 @@
+//! Checks the `/nft/list` filters are checked, and its cursors lead on from the NFT a page ended with.
+//!
+//! cargo test --test nft_list
+use pfc_reservation::requests::{NftListQuery, NFT_LIST_LIMIT, NFT_LIST_MAX};
+
+fn query() -> NftListQuery {
+    NftListQuery {
+        state: None,
+        trait_type: None,
+        trait_value: None,
+        name_prefix: None,
+        wallet: None,
+        cursor: None,
+        limit: None,
+    }
+}
+
+#[test]
+fn cursors_lead_on_from_the_last_name() {
+    for name in ["Peep 1", "Evan Bagelmeister/🥯?&=", ""] {
+        let mut next = query();
+        next.cursor = Some(NftListQuery::cursor_for(name));
+        assert_eq!(next.after().as_deref(), Some(name));
+        assert!(next.validate().is_ok());
+    }
+}
+
+#[test]
+fn checks_the_filters() {
+    assert!(query().validate().is_ok());
+    assert_eq!(query().limit(), NFT_LIST_LIMIT);
+
+    let mut good = query();
+    good.state = Some(String::from("pending_verification"));
+    good.trait_type = Some(String::from("hat"));
+    good.trait_value = Some(String::from("bagel"));
+    good.limit = Some(NFT_LIST_MAX);
+    assert!(good.validate().is_ok());
+
+    let mut bad = query();
+    bad.state = Some(String::from("Available"));
+    bad.trait_value = Some(String::from("bagel"));
+    bad.cursor = Some(String::from("not a cursor!"));
+    bad.limit = Some(NFT_LIST_MAX + 1);
+    let fields = bad
+        .validate()
+        .unwrap_err()
+        .into_iter()
+        .map(|e| e.field)
+        .collect::<Vec<_>>();
+    assert_eq!(fields, vec!["state", "trait_type", "cursor", "limit"]);
+}
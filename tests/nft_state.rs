@@ -0,0 +1,136 @@
+//! Checks NFTs only move between states the state machine allows, both in `NftState` and in the database.
+//!
+//! The database tests create (and drop) a database of their own on the server `DATABASE_URL` points at,
+//! and are skipped when it isn't set.
+//!
+//! DATABASE_URL=postgres://postgres@localhost/postgres cargo test --test nft_state
+mod common;
+
+use chrono::{Duration, Utc};
+use common::TestDb;
+use pfc_reservation::chain::RetryPolicy;
+use pfc_reservation::db;
+use pfc_reservation::requests::NftState;
+use postgres::Client;
+use uuid::Uuid;
+
+const WALLET: &str = "terra1nftstatewallet00000000000000000000000";
+
+/// an NFT in `status`, reserved to `WALLET` until `reserved_until`
+fn nft_in(conn: &mut Client, status: &str, reserved_until: Duration) -> Uuid {
+    conn.query_one(
+        r#"insert into NFT(name, meta_data, svg, status, reserved_to_wallet_address, reserved_until)
+            values($1, '{}', '{}', $2, $3, $4) returning id"#,
+        &[
+            &format!(
+                "state-{}",
+                Uuid::from_u128(Utc::now().timestamp_nanos() as u128)
+            ),
+            &status,
+            &WALLET,
+            &(Utc::now() + reserved_until),
+        ],
+    )
+    .unwrap()
+    .get(0)
+}
+
+fn status(conn: &mut Client, nft: &Uuid) -> String {
+    conn.query_one("select status from NFT where id=$1", &[nft])
+        .unwrap()
+        .get(0)
+}
+
+#[test]
+fn lapsed_reservations_are_available() {
+    let now = Utc::now();
+    assert_eq!(
+        NftState::Reserved.at(Some(now - Duration::seconds(1)), now),
+        NftState::Available
+    );
+    assert_eq!(
+        NftState::Reserved.at(Some(now + Duration::seconds(1)), now),
+        NftState::Reserved
+    );
+    assert_eq!(
+        NftState::Errored.at(Some(now - Duration::seconds(1)), now),
+        NftState::Errored
+    );
+}
+
+#[test]
+fn only_allowed_moves() {
+    assert!(NftState::Errored.transition(NftState::Submitted).is_ok());
+    assert!(NftState::DeadLetter.transition(NftState::Available).is_ok());
+    assert!(NftState::Submitted.transition(NftState::Minted).is_ok());
+    assert!(NftState::Minted.transition(NftState::Reserved).is_err());
+    assert!(NftState::Minted.transition(NftState::Available).is_err());
+    assert!(NftState::Submitted.transition(NftState::Available).is_err());
+    assert!(NftState::DeadLetter
+        .transition(NftState::Submitted)
+        .is_err());
+    for state in NftState::HELD {
+        assert!(
+            state.can_become(NftState::Minted),
+            "{} can't be minted",
+            state
+        );
+    }
+}
+
+#[test]
+fn reaper_only_releases_lapsed_reservations() {
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    let lapsed = nft_in(&mut conn, "reserved", Duration::minutes(-1));
+    let held = nft_in(&mut conn, "reserved", Duration::minutes(10));
+    let submitted = nft_in(&mut conn, "submitted", Duration::minutes(-1));
+
+    assert_eq!(db::clear_reservations(&mut conn).unwrap(), 1);
+    assert_eq!(status(&mut conn, &lapsed), "available");
+    assert_eq!(status(&mut conn, &held), "reserved");
+    assert_eq!(status(&mut conn, &submitted), "submitted");
+}
+
+#[test]
+fn minted_nfts_stay_minted() {
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    let policy = RetryPolicy {
+        max_retries: 3,
+        retryable_codespaces: vec![],
+        release_after: 0,
+        errored_hold: 60,
+    };
+    let minted = nft_in(&mut conn, "minted", Duration::minutes(10));
+
+    assert_eq!(
+        db::set_tx_hash_for_nft(&mut conn, &minted, "HASH").unwrap(),
+        0
+    );
+    assert_eq!(db::release_failed_nft(&mut conn, &minted).unwrap(), 0);
+    assert_eq!(
+        db::requeue_failed_nft(&mut conn, &minted, &policy).unwrap(),
+        0
+    );
+    assert_eq!(status(&mut conn, &minted), "minted");
+}
+
+#[test]
+fn submitted_nfts_are_not_released() {
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    let submitted = nft_in(&mut conn, "submitted", Duration::minutes(10));
+
+    assert_eq!(db::release_failed_nft(&mut conn, &submitted).unwrap(), 0);
+    assert_eq!(status(&mut conn, &submitted), "submitted");
+}
@@ -0,0 +1,97 @@
+//! Checks `NewNFTRequest` is checked field by field, so `/nft/new` and `/nft/import` can say what is wrong
+//! with an NFT rather than falling over on it.
+//!
+//! cargo test --test nft_validation
+use pfc_reservation::requests::{NewNFTRequest, UpdateNFTRequest};
+use serde_json::json;
+
+fn nft() -> NewNFTRequest {
+    NewNFTRequest {
+        name: String::from("Peep 1"),
+        meta: json!({"token_uri": "ipfs://meta/1", "attributes": [{"trait_type": "hat", "value": "bagel"}]})
+            .to_string(),
+        svg: json!({"svg": "<svg/>"}).to_string(),
+        ipfs_image: String::from("QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG"),
+        ipfs_meta: String::new(),
+        image_data: None,
+        external_url: Some(String::from("https://peeps.example/1")),
+        description: None,
+        background_color: Some(String::from("00ffAA")),
+        animation_url: Some(String::from("ipfs://QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG")),
+        youtube_url: None,
+    }
+}
+
+/// the fields `validate` found fault with
+fn faults(nft: NewNFTRequest) -> Vec<String> {
+    nft.validate()
+        .unwrap_err()
+        .into_iter()
+        .map(|e| e.field)
+        .collect()
+}
+
+#[test]
+fn accepts_a_good_nft() {
+    let (meta, svg) = nft().validate().unwrap();
+    assert_eq!(meta["token_uri"], json!("ipfs://meta/1"));
+    assert_eq!(svg["svg"], json!("<svg/>"));
+}
+
+#[test]
+fn checks_the_name() {
+    for name in ["", "   ", " Peep 1", &"x".repeat(256)] {
+        let mut bad = nft();
+        bad.name = name.to_string();
+        assert_eq!(faults(bad), vec!["name"], "{:?}", name);
+    }
+}
+
+#[test]
+fn checks_meta_and_svg_are_json() {
+    let mut bad = nft();
+    bad.meta = String::from("{not json");
+    bad.svg = String::from("");
+    assert_eq!(faults(bad), vec!["meta", "svg"]);
+
+    let mut no_token_uri = nft();
+    no_token_uri.meta = json!({"token_uri": ""}).to_string();
+    let errors = no_token_uri.validate().unwrap_err();
+    assert_eq!(errors[0].to_string(), "meta: token_uri is empty");
+}
+
+#[test]
+fn checks_cids_colors_and_urls() {
+    let mut bad = nft();
+    bad.ipfs_image = String::from("QmImage");
+    bad.ipfs_meta = String::from("bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi");
+    bad.background_color = Some(String::from("#00ffaa"));
+    bad.external_url = Some(String::from("javascript:alert(1)"));
+    bad.animation_url = Some(format!("https://{}", "x".repeat(2000)));
+    bad.youtube_url = Some(String::from("https://youtube.com/watch?v=a b"));
+    assert_eq!(
+        faults(bad),
+        vec![
+            "ipfs_image",
+            "ipfs_meta",
+            "background_color",
+            "external_url",
+            "animation_url",
+            "youtube_url"
+        ]
+    );
+}
+
+#[test]
+fn updates_keep_the_fields_left_out() {
+    let update: UpdateNFTRequest =
+        serde_json::from_value(json!({"description": "a peep", "background_color": "zz"})).unwrap();
+    let updated = update.apply(nft());
+    assert_eq!(updated.name, "Peep 1");
+    assert_eq!(updated.description.as_deref(), Some("a peep"));
+    assert_eq!(
+        updated.external_url.as_deref(),
+        Some("https://peeps.example/1")
+    );
+    assert_eq!(faults(updated), vec!["background_color"]);
+}
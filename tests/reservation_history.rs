@@ -0,0 +1,79 @@
+//! Checks a wallet's reservation history keeps its reservations, and counts repeated rejections on one entry.
+//!
+//! Creates (and drops) its own database on the server `DATABASE_URL` points at, and is skipped when it isn't set.
+//!
+//! DATABASE_URL=postgres://postgres@localhost/postgres cargo test --test reservation_history
+mod common;
+
+use chrono::{Duration, Utc};
+use common::TestDb;
+use pfc_reservation::db;
+use pfc_reservation::requests::{NewStageRequest, ReservationStatus};
+use postgres::Client;
+
+const WALLET: &str = "terra1reservationhistorywallet00000000000000";
+const SALT: &str = "reservation-history";
+const MAX_RESERVATIONS: usize = 2;
+
+fn reserve(conn: &mut Client) -> bool {
+    let (_, result) = db::do_reservation(
+        conn,
+        WALLET,
+        &(Utc::now() + Duration::minutes(10)),
+        MAX_RESERVATIONS,
+        SALT,
+    );
+    result.is_ok()
+}
+
+#[test]
+fn repeated_rejections_are_counted_on_one_entry() {
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    assert!(!reserve(&mut conn), "reserved with no stage open");
+    for i in 0..3 {
+        conn.execute(
+            "insert into NFT(name, meta_data, svg) values($1, $2, '{}')",
+            &[
+                &format!("history-{}", i),
+                &serde_json::json!({ "token_uri": format!("history-{}", i) }),
+            ],
+        )
+        .unwrap();
+    }
+    db::create_stage(
+        &mut conn,
+        &NewStageRequest {
+            code: "history".into(),
+            name: "History".into(),
+            selection_rule: None,
+            is_default: true,
+            stage_free: false,
+            stage_open: Utc::now() - Duration::minutes(1),
+            stage_close: None,
+        },
+    )
+    .unwrap();
+    assert!(reserve(&mut conn));
+    assert!(reserve(&mut conn));
+    for _ in 0..250 {
+        assert!(!reserve(&mut conn));
+    }
+
+    let history = db::get_reservation_history(&mut conn, WALLET).unwrap();
+    assert_eq!(history.len(), 4);
+    assert_eq!(history[0].status, ReservationStatus::Rejected);
+    assert_eq!(
+        history[0].tx_error.as_deref(),
+        Some("Reservation limit exceeded")
+    );
+    assert_eq!(history[0].rejected_count, Some(250));
+    assert_eq!(history[1].status, ReservationStatus::Reserved);
+    assert_eq!(history[1].rejected_count, None);
+    assert_eq!(history[2].status, ReservationStatus::Reserved);
+    assert_eq!(history[3].status, ReservationStatus::Rejected);
+    assert_eq!(history[3].rejected_count, Some(1));
+}
@@ -0,0 +1,131 @@
+//! Checks what the retry policy makes of failed mint transactions.
+//!
+//! The broadcast rejection test also runs against a database of its own on the server `DATABASE_URL` points at,
+//! when it is set.
+//!
+//! cargo test --test retry_policy
+mod common;
+
+use chrono::{Duration, Utc};
+use common::{MockLcd, TestDb};
+use pfc_reservation::chain::{broadcast_tx, error_codespace, FailureOutcome, RetryPolicy};
+use pfc_reservation::db;
+use serde_json::json;
+use uuid::Uuid;
+
+fn policy(retryable_codespaces: &[&str], release_after: i32) -> RetryPolicy {
+    RetryPolicy {
+        max_retries: 2,
+        retryable_codespaces: retryable_codespaces.iter().map(|c| c.to_string()).collect(),
+        release_after,
+        errored_hold: 60,
+    }
+}
+
+#[test]
+fn reads_the_codespace() {
+    assert_eq!(error_codespace("13/sdk-insufficient fee"), Some("sdk"));
+    assert_eq!(
+        error_codespace("4/wasm-execute wasm contract failed: token_id already claimed"),
+        Some("wasm")
+    );
+    assert_eq!(error_codespace("5/-no codespace"), None);
+    assert_eq!(error_codespace("contract mismatch"), None);
+    assert_eq!(error_codespace("32-account sequence mismatch"), None);
+}
+
+#[test]
+fn retries_until_max_retries() {
+    let policy = policy(&[], 0);
+    assert_eq!(
+        policy.outcome(1, "11/sdk-out of gas"),
+        FailureOutcome::Retry
+    );
+    assert_eq!(
+        policy.outcome(2, "11/sdk-out of gas"),
+        FailureOutcome::Retry
+    );
+    assert_eq!(
+        policy.outcome(3, "11/sdk-out of gas"),
+        FailureOutcome::DeadLetter
+    );
+}
+
+#[test]
+fn only_retries_listed_codespaces() {
+    let policy = policy(&["sdk"], 0);
+    assert_eq!(
+        policy.outcome(1, "11/sdk-out of gas"),
+        FailureOutcome::Retry
+    );
+    assert_eq!(
+        policy.outcome(1, "4/wasm-token_id already claimed"),
+        FailureOutcome::DeadLetter
+    );
+    assert_eq!(
+        policy.outcome(1, "Unable to find event"),
+        FailureOutcome::DeadLetter
+    );
+}
+
+#[test]
+fn releases_after_enough_failures() {
+    let policy = policy(&["sdk"], 2);
+    assert_eq!(
+        policy.outcome(1, "11/sdk-out of gas"),
+        FailureOutcome::Retry
+    );
+    assert_eq!(
+        policy.outcome(2, "11/sdk-out of gas"),
+        FailureOutcome::Release
+    );
+    // errors which can't be retried are left for an admin
+    assert_eq!(
+        policy.outcome(2, "4/wasm-token_id already claimed"),
+        FailureOutcome::DeadLetter
+    );
+}
+
+#[test]
+fn broadcast_rejections_are_retried_by_codespace() {
+    let (lcd, _) = MockLcd::new()
+        .broadcast(
+            json!({"txhash": "CCCC", "code": 11, "codespace": "sdk", "raw_log": "out of gas"}),
+        )
+        .serve();
+    let result = rocket::tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(broadcast_tx(&lcd, "bombay-12", &json!({})))
+        .expect("broadcast should be answered");
+    let error = result.error().expect("the broadcast was turned away");
+    assert_eq!(error, "11/sdk-out of gas");
+    let policy = policy(&["sdk"], 0);
+    assert_eq!(policy.outcome(1, &error), FailureOutcome::Retry);
+
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    let wallet = "terra1retrypolicywallet000000000000000000000";
+    let nft: Uuid = conn
+        .query_one(
+            r#"insert into NFT(name, meta_data, svg, status, reserved_to_wallet_address, reserved_until)
+                values('retry-policy', '{"token_uri":"retry-policy"}', '{}', 'reserved', $1, $2) returning id"#,
+            &[&wallet, &(Utc::now() + Duration::minutes(10))],
+        )
+        .unwrap()
+        .get(0);
+    db::set_tx_for_nft(&mut conn, &nft, &json!({"tx": "signed"})).unwrap();
+    assert!(db::note_broadcast_failure(&mut conn, &nft, &error, 1, true, &policy).unwrap());
+    let status: String = conn
+        .query_one("select status from NFT where id=$1", &[&nft])
+        .unwrap()
+        .get(0);
+    assert_eq!(
+        status, "submitted",
+        "a retryable rejection is sent again rather than dead lettered"
+    );
+}
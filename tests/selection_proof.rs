@@ -0,0 +1,165 @@
+//! Checks the selection proof is the one recorded when the NFT was reserved, and the salt stays hidden
+//! until the stage closes.
+//!
+//! Creates (and drops) its own database on the server `DATABASE_URL` points at, and is skipped when it isn't set.
+//!
+//! DATABASE_URL=postgres://postgres@localhost/postgres cargo test --test selection_proof
+mod common;
+
+use chrono::{Duration, Utc};
+use common::TestDb;
+use pfc_reservation::db;
+use pfc_reservation::requests::NewStageRequest;
+use pfc_reservation::selection::{
+    candidates_hash, next_seed, salt_hash, seed_index, to_hex, wallet_seed, SelectionOrder,
+};
+use uuid::Uuid;
+
+const WALLET: &str = "terra1selectionproofwallet00000000000000000";
+const SALT: &str = "selection-proof";
+
+#[test]
+fn picks_carry_the_seed_they_were_made_with() {
+    let candidates = (0..5u128)
+        .map(|i| Uuid::from_u128(i * 0x9e37_79b9_7f4a_7c15))
+        .collect::<Vec<Uuid>>();
+    let mut sorted = candidates.clone();
+    sorted.sort();
+    let mut picks = SelectionOrder::new(WALLET, SALT, &candidates);
+
+    let first = picks.next().unwrap();
+    assert_eq!(first.seed, wallet_seed(WALLET, SALT));
+    assert_eq!(first.candidate_count, 5);
+    assert_eq!(first.candidates_hash, candidates_hash(&sorted));
+    assert_eq!(Some(first.index), seed_index(&first.seed, 5));
+    assert_eq!(first.nft_id, sorted[first.index]);
+
+    sorted.remove(first.index);
+    let second = picks.next().unwrap();
+    assert_eq!(second.seed, next_seed(&first.seed));
+    assert_eq!(second.candidate_count, 4);
+    assert_eq!(second.candidates_hash, candidates_hash(&sorted));
+    assert_eq!(second.nft_id, sorted[second.index]);
+    assert_eq!(picks.count(), 3);
+}
+
+#[test]
+fn proof_is_recorded_with_the_reservation() {
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    for i in 0..3 {
+        conn.execute(
+            "insert into NFT(name, meta_data, svg) values($1, $2, '{}')",
+            &[
+                &format!("proof-{}", i),
+                &serde_json::json!({ "token_uri": format!("proof-{}", i) }),
+            ],
+        )
+        .unwrap();
+    }
+    let stage = db::create_stage(
+        &mut conn,
+        &NewStageRequest {
+            code: "proof".into(),
+            name: "Proof".into(),
+            selection_rule: None,
+            is_default: true,
+            stage_free: false,
+            stage_open: Utc::now() - Duration::minutes(1),
+            stage_close: Some(Utc::now() + Duration::hours(1)),
+        },
+    )
+    .unwrap();
+    let (_, reserved) = db::do_reservation(
+        &mut conn,
+        WALLET,
+        &(Utc::now() + Duration::minutes(10)),
+        5,
+        SALT,
+    );
+    let (nft_id, _) = reserved.ok().expect("reservation");
+
+    // the pool changing afterwards doesn't change the proof
+    conn.execute(
+        "insert into NFT(name, meta_data, svg) values('proof-late', '{\"token_uri\":\"proof-late\"}', '{}')",
+        &[],
+    )
+    .unwrap();
+
+    let proofs = db::selection_proofs(&mut conn, WALLET, SALT).unwrap();
+    assert_eq!(proofs.len(), 1);
+    let proof = &proofs[0];
+    assert_eq!(proof.nft_id, nft_id);
+    assert_eq!(proof.stage_code, "proof");
+    assert_eq!(proof.seed, to_hex(&wallet_seed(WALLET, SALT)));
+    assert_eq!(proof.candidate_count, 3);
+    assert_eq!(Some(proof.index), seed_index(&wallet_seed(WALLET, SALT), 3));
+    assert_eq!(proof.salt_hash, salt_hash(SALT));
+    assert_eq!(
+        proof.salt, None,
+        "the salt is revealed while the stage is open"
+    );
+
+    conn.execute(
+        "update stage_whitelist set stage_close = now() - interval '1 minute' where id = $1",
+        &[&stage.id],
+    )
+    .unwrap();
+    let proofs = db::selection_proofs(&mut conn, WALLET, SALT).unwrap();
+    assert_eq!(proofs[0].salt.as_deref(), Some(SALT));
+}
+
+#[test]
+fn salt_is_kept_while_any_stage_is_open() {
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    conn.execute(
+        "insert into NFT(name, meta_data, svg) values('proof-0', '{\"token_uri\":\"proof-0\"}', '{}')",
+        &[],
+    )
+    .unwrap();
+    let stage = |code: &str, is_default: bool| NewStageRequest {
+        code: code.into(),
+        name: code.into(),
+        selection_rule: None,
+        is_default,
+        stage_free: false,
+        stage_open: Utc::now() - Duration::minutes(1),
+        stage_close: Some(Utc::now() + Duration::hours(1)),
+    };
+    let first = db::create_stage(&mut conn, &stage("first", true)).unwrap();
+    let second = db::create_stage(&mut conn, &stage("second", false)).unwrap();
+    let (_, reserved) = db::do_reservation(
+        &mut conn,
+        WALLET,
+        &(Utc::now() + Duration::minutes(10)),
+        5,
+        SALT,
+    );
+    reserved.ok().expect("reservation");
+
+    let close = |conn: &mut postgres::Client, id: &Uuid| {
+        conn.execute(
+            "update stage_whitelist set stage_close = now() - interval '1 minute' where id = $1",
+            &[id],
+        )
+        .unwrap();
+    };
+    close(&mut conn, &first.id);
+    let proofs = db::selection_proofs(&mut conn, WALLET, SALT).unwrap();
+    assert_eq!(proofs.len(), 1);
+    assert_eq!(
+        proofs[0].salt, None,
+        "the salt is revealed while another stage is still open"
+    );
+
+    close(&mut conn, &second.id);
+    let proofs = db::selection_proofs(&mut conn, WALLET, SALT).unwrap();
+    assert_eq!(proofs[0].salt.as_deref(), Some(SALT));
+}
@@ -0,0 +1,133 @@
+//! Checks the signed transactions wallets send to `/mint/tx` are only broadcast when they mint their reservation,
+//! against a mock LCD answering `POST /txs`.
+//!
+//! cargo test --test signed_tx
+mod common;
+
+use common::MockLcd;
+use pfc_reservation::chain::{broadcast_tx, check_signed_mint, decode_signed_tx, signed_mint_msg};
+use serde_json::{json, Value};
+
+const CHAIN: &str = "bombay-12";
+const NFT_CONTRACT: &str = "terra1nftcontract";
+const WALLET: &str = "terra1uag78r7hn6we00ddudd9juv2f5nvapf0qtuskg";
+
+fn expected() -> Value {
+    signed_mint_msg("{\"token_uri\":\"ipfs://meta/1\"}", "c2lnbmF0dXJl")
+}
+
+fn execute(sender: &str, contract: &str, execute_msg: Value) -> Value {
+    json!({
+        "type": "wasm/MsgExecuteContract",
+        "value": {"sender": sender, "contract": contract, "execute_msg": execute_msg, "coins": []}
+    })
+}
+
+fn signed_tx(msgs: Vec<Value>) -> Value {
+    json!({
+        "msg": msgs,
+        "fee": {"amount": [{"denom": "uusd", "amount": "30000"}], "gas": "200000"},
+        "signatures": [{"pub_key": {"type": "tendermint/PubKeySecp256k1", "value": "AAAA"}, "signature": "BBBB"}],
+        "memo": ""
+    })
+}
+
+fn mint_tx() -> Value {
+    signed_tx(vec![execute(WALLET, NFT_CONTRACT, expected())])
+}
+
+#[test]
+fn decodes_std_tx_or_its_value() {
+    let tx = mint_tx();
+    assert_eq!(decode_signed_tx(&tx), Ok(tx.clone()));
+    assert_eq!(
+        decode_signed_tx(&json!({"type": "core/StdTx", "value": tx.clone()})),
+        Ok(tx)
+    );
+    assert!(decode_signed_tx(&json!({"type": "core/Other", "value": {}})).is_err());
+}
+
+#[test]
+fn refuses_unsigned_tx() {
+    let mut tx = mint_tx();
+    tx["signatures"] = json!([]);
+    assert_eq!(
+        decode_signed_tx(&tx),
+        Err(String::from("transaction is not signed"))
+    );
+}
+
+#[test]
+fn accepts_the_expected_mint() {
+    assert_eq!(
+        check_signed_mint(&mint_tx(), NFT_CONTRACT, WALLET, &expected()),
+        Ok(())
+    );
+}
+
+#[test]
+fn refuses_anything_else() {
+    let cases = vec![
+        (
+            signed_tx(vec![
+                execute(WALLET, NFT_CONTRACT, expected()),
+                execute(WALLET, NFT_CONTRACT, expected()),
+            ]),
+            "expected 1 message, found 2",
+        ),
+        (
+            signed_tx(vec![json!({"type": "bank/MsgSend", "value": {}})]),
+            "message is not a contract execution",
+        ),
+        (
+            signed_tx(vec![execute(WALLET, "terra1someoneelse", expected())]),
+            "contract mismatch",
+        ),
+        (
+            signed_tx(vec![execute("terra1someoneelse", NFT_CONTRACT, expected())]),
+            "sender is not the reserved wallet",
+        ),
+        (
+            signed_tx(vec![execute(
+                WALLET,
+                NFT_CONTRACT,
+                signed_mint_msg("{\"token_uri\":\"ipfs://meta/2\"}", "c2lnbmF0dXJl"),
+            )]),
+            "mint message does not match the reservation",
+        ),
+    ];
+    for (tx, error) in cases {
+        assert_eq!(
+            check_signed_mint(&tx, NFT_CONTRACT, WALLET, &expected()),
+            Err(String::from(error))
+        );
+    }
+}
+
+#[rocket::async_test]
+async fn broadcasts_sync() {
+    let (lcd, posted) = MockLcd::new()
+        .broadcast(json!({"txhash": "AAAA", "raw_log": "[]"}))
+        .serve();
+    let result = broadcast_tx(&lcd, CHAIN, &mint_tx())
+        .await
+        .expect("broadcast should be answered");
+    assert_eq!(result.txhash, "AAAA");
+    assert!(result.code.is_none());
+    assert_eq!(
+        posted.lock().unwrap()[0],
+        json!({"tx": mint_tx(), "mode": "sync"})
+    );
+}
+
+#[rocket::async_test]
+async fn broadcast_turned_away_has_a_code() {
+    let (lcd, _) = MockLcd::new()
+        .broadcast(json!({"txhash": "BBBB", "code": 4, "raw_log": "signature verification failed"}))
+        .serve();
+    let result = broadcast_tx(&lcd, CHAIN, &mint_tx())
+        .await
+        .expect("broadcast should be answered");
+    assert_eq!(result.code, Some(4));
+    assert_eq!(result.raw_log, "signature verification failed");
+}
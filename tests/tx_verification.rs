@@ -0,0 +1,81 @@
+//! Checks the hashes wallets hand to `/mint/hash` are only vouched for when the transaction on chain is theirs,
+//! against a mock LCD answering `GET /txs/<hash>`.
+//!
+//! cargo test --test tx_verification
+mod common;
+
+use common::MockLcd;
+use pfc_reservation::chain::{check_tx_sender, get_tx, is_tx_hash};
+use serde_json::{json, Value};
+
+const CHAIN: &str = "bombay-12";
+const NFT_CONTRACT: &str = "terra1nftcontract";
+const WALLET: &str = "terra1uag78r7hn6we00ddudd9juv2f5nvapf0qtuskg";
+const HASH: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+
+/// an LCD with a transaction holding `msgs` at `HASH`. returns the LCD url
+fn mock_lcd(msgs: Vec<Value>) -> String {
+    let tx = json!({
+        "height": "1",
+        "txhash": HASH,
+        "raw_log": "[]",
+        "timestamp": "2021-10-17T10:00:00Z",
+        "tx": {"type": "core/StdTx", "value": {"msg": msgs}}
+    });
+    MockLcd::new().tx(HASH, tx).serve().0
+}
+
+fn execute(sender: &str, contract: &str) -> Value {
+    json!({
+        "type": "wasm/MsgExecuteContract",
+        "value": {"sender": sender, "contract": contract, "execute_msg": {"mint": {}}, "coins": []}
+    })
+}
+
+async fn check(msgs: Vec<Value>) -> Result<(), String> {
+    let lcd = mock_lcd(msgs);
+    let tx = get_tx(&lcd, CHAIN, HASH).await.expect("tx should be found");
+    check_tx_sender(NFT_CONTRACT, WALLET, &tx)
+}
+
+#[rocket::async_test]
+async fn vouches_for_the_wallets_mint() {
+    assert_eq!(check(vec![execute(WALLET, NFT_CONTRACT)]).await, Ok(()));
+}
+
+#[rocket::async_test]
+async fn refuses_other_contracts() {
+    assert_eq!(
+        check(vec![
+            execute(WALLET, NFT_CONTRACT),
+            execute(WALLET, "terra1someoneelse")
+        ])
+        .await,
+        Err(String::from("contract mismatch"))
+    );
+}
+
+#[rocket::async_test]
+async fn refuses_other_senders() {
+    assert_eq!(
+        check(vec![execute("terra1someoneelse", NFT_CONTRACT)]).await,
+        Err(String::from("sender is not the reserved wallet"))
+    );
+}
+
+#[rocket::async_test]
+async fn unknown_hashes_are_not_found() {
+    let lcd = mock_lcd(vec![execute(WALLET, NFT_CONTRACT)]);
+    assert!(get_tx(&lcd, CHAIN, "garbage").await.is_err());
+}
+
+#[test]
+fn only_hex_hashes_are_taken() {
+    assert!(is_tx_hash(HASH));
+    assert!(is_tx_hash(&HASH.to_lowercase()));
+    assert!(!is_tx_hash(""));
+    assert!(!is_tx_hash("not a hash"));
+    assert!(!is_tx_hash(&HASH[1..]));
+    assert!(!is_tx_hash(&format!("{}A", HASH)));
+    assert!(!is_tx_hash(&HASH.replacen('A', "G", 1)));
+}
@@ -0,0 +1,160 @@
+//! Checks how the tx watcher reads mint results, against a mock LCD serving canned `/txs/<hash>` responses.
+//!
+//! cargo test --test tx_watcher
+mod common;
+
+use common::MockLcd;
+use pfc_reservation::chain::{get_tx, parse_mint_result, parse_result, Backoff};
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+
+const CHAIN: &str = "bombay-12";
+const NFT_CONTRACT: &str = "terra1nftcontract";
+const MINTER: &str = "terra1uag78r7hn6we00ddudd9juv2f5nvapf0qtuskg";
+
+fn tx(hash: &str) -> Value {
+    json!({
+        "height": "123",
+        "txhash": hash,
+        "raw_log": "[]",
+        "timestamp": "2021-10-17T10:00:00Z",
+    })
+}
+
+fn minted_tx(hash: &str, contract: &str) -> Value {
+    let mut tx = tx(hash);
+    tx["logs"] = json!([{
+        "msg_index": 0,
+        "events": [{
+            "type": "wasm",
+            "attributes": [
+                {"key": "contract_address", "value": contract},
+                {"key": "minter", "value": MINTER},
+                {"key": "token_id", "value": "Peep 1"},
+            ]
+        }]
+    }]);
+    tx
+}
+
+fn failed_tx(hash: &str) -> Value {
+    let mut tx = tx(hash);
+    tx["code"] = json!(11);
+    tx["codespace"] = json!("sdk");
+    tx["raw_log"] = json!("out of gas");
+    tx
+}
+
+fn mint_run_tx(hash: &str, names: &[&str]) -> Value {
+    let mut tx = tx(hash);
+    tx["tx"] = json!({
+        "value": {
+            "msg": names.iter().map(|name| json!({
+                "value": {
+                    "sender": "terra1owner",
+                    "contract": NFT_CONTRACT,
+                    "execute_msg": {"mint": {"token_id": name, "owner": MINTER}}
+                }
+            })).collect::<Vec<Value>>()
+        }
+    });
+    tx
+}
+
+fn lcd_with(txs: &[(&str, Value)]) -> String {
+    txs.iter()
+        .fold(MockLcd::new(), |lcd, (hash, tx)| lcd.tx(hash, tx.clone()))
+        .serve()
+        .0
+}
+
+#[rocket::async_test]
+async fn reads_successful_mint() {
+    let lcd = lcd_with(&[("AAAA", minted_tx("AAAA", NFT_CONTRACT))]);
+    let tx = get_tx(&lcd, CHAIN, "AAAA")
+        .await
+        .expect("tx should be found");
+    let result = parse_result(NFT_CONTRACT, &tx);
+    assert!(result.success);
+    assert_eq!(result.tx, "AAAA");
+    assert_eq!(result.wallet_address.as_deref(), Some(MINTER));
+    assert_eq!(result.token_id.as_deref(), Some("Peep 1"));
+    assert_eq!(
+        result.assigned_on.map(|t| t.to_rfc3339()),
+        Some(String::from("2021-10-17T10:00:00+00:00"))
+    );
+}
+
+#[rocket::async_test]
+async fn reads_failed_tx() {
+    let lcd = lcd_with(&[("BBBB", failed_tx("BBBB"))]);
+    let tx = get_tx(&lcd, CHAIN, "BBBB")
+        .await
+        .expect("tx should be found");
+    let result = parse_result(NFT_CONTRACT, &tx);
+    assert!(!result.success);
+    assert_eq!(result.error.as_deref(), Some("11/sdk-out of gas"));
+    assert!(result.wallet_address.is_none());
+}
+
+#[rocket::async_test]
+async fn rejects_other_contracts() {
+    let lcd = lcd_with(&[("CCCC", minted_tx("CCCC", "terra1someoneelse"))]);
+    let tx = get_tx(&lcd, CHAIN, "CCCC")
+        .await
+        .expect("tx should be found");
+    let result = parse_result(NFT_CONTRACT, &tx);
+    assert!(!result.success);
+    assert_eq!(result.error.as_deref(), Some("contract mismatch"));
+}
+
+#[rocket::async_test]
+async fn missing_tx_is_an_error() {
+    let lcd = lcd_with(&[]);
+    assert!(get_tx(&lcd, CHAIN, "DDDD").await.is_err());
+}
+
+#[rocket::async_test]
+async fn reads_mint_run_by_name() {
+    let lcd = lcd_with(&[("EEEE", mint_run_tx("EEEE", &["Peep 1", "Peep 2"]))]);
+    let tx = get_tx(&lcd, CHAIN, "EEEE")
+        .await
+        .expect("tx should be found");
+
+    let second = parse_mint_result(NFT_CONTRACT, &tx, "Peep 2");
+    assert!(second.success);
+    assert_eq!(second.token_id.as_deref(), Some("Peep 2"));
+    assert_eq!(second.wallet_address.as_deref(), Some(MINTER));
+
+    let missing = parse_mint_result(NFT_CONTRACT, &tx, "Peep 3");
+    assert!(!missing.success);
+}
+
+#[test]
+fn backoff_doubles_up_to_max() {
+    let mut backoff = Backoff::new(Duration::from_secs(10), Duration::from_secs(60));
+    let now = Instant::now();
+    assert!(backoff.is_due("FFFF", now));
+    let delays = (0..5)
+        .map(|_| backoff.failed("FFFF", now).as_secs())
+        .collect::<Vec<u64>>();
+    assert_eq!(delays, vec![10, 20, 40, 60, 60]);
+    assert_eq!(backoff.attempts("FFFF"), 5);
+    assert!(!backoff.is_due("FFFF", now + Duration::from_secs(59)));
+    assert!(backoff.is_due("FFFF", now + Duration::from_secs(60)));
+
+    backoff.succeeded("FFFF");
+    assert!(backoff.is_due("FFFF", now));
+    assert_eq!(backoff.attempts("FFFF"), 0);
+}
+
+#[test]
+fn backoff_forgets_unwatched_hashes() {
+    let mut backoff = Backoff::new(Duration::from_secs(10), Duration::from_secs(60));
+    let now = Instant::now();
+    backoff.failed("GGGG", now);
+    backoff.failed("HHHH", now);
+    backoff.retain(&[String::from("HHHH")]);
+    assert_eq!(backoff.attempts("GGGG"), 0);
+    assert_eq!(backoff.attempts("HHHH"), 1);
+}
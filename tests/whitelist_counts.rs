@@ -0,0 +1,196 @@
+//! Checks a wallet's whitelist counters as its NFT is reserved, fails to mint, and is resubmitted.
+//!
+//! Each test creates (and drops) its own database on the server `DATABASE_URL` points at,
+//! and is skipped when it isn't set.
+//!
+//! DATABASE_URL=postgres://postgres@localhost/postgres cargo test --test whitelist_counts
+mod common;
+
+use chrono::{Duration, Utc};
+use common::TestDb;
+use pfc_reservation::chain::RetryPolicy;
+use pfc_reservation::db;
+use pfc_reservation::requests::{
+    NewStageRequest, ReservationTxResultRequest, WalletAllocationRequest,
+};
+use postgres::Client;
+use uuid::Uuid;
+
+const WALLET: &str = "terra1whitelistcountswallet0000000000000000";
+const SALT: &str = "whitelist-counts";
+
+fn policy() -> RetryPolicy {
+    RetryPolicy {
+        max_retries: 3,
+        retryable_codespaces: vec![],
+        release_after: 0,
+        errored_hold: 60,
+    }
+}
+
+/// a few NFTs, and an open stage with `WALLET` allowed `allocation` of them
+fn whitelist_stage(conn: &mut Client, allocation: i32) -> Uuid {
+    for i in 0..4 {
+        conn.execute(
+            "insert into NFT(name, meta_data, svg) values($1, $2, '{}')",
+            &[
+                &format!("whitelist-{}", i),
+                &serde_json::json!({ "token_uri": format!("whitelist-{}", i) }),
+            ],
+        )
+        .unwrap();
+    }
+    let stage = db::create_stage(
+        conn,
+        &NewStageRequest {
+            code: "whitelist".into(),
+            name: "Whitelist".into(),
+            selection_rule: None,
+            is_default: false,
+            stage_free: false,
+            stage_open: Utc::now() - Duration::minutes(1),
+            stage_close: None,
+        },
+    )
+    .unwrap();
+    db::upsert_wallet_allocations(
+        conn,
+        stage.id,
+        &[WalletAllocationRequest {
+            wallet_address: WALLET.into(),
+            allocation_count: allocation,
+        }],
+    )
+    .unwrap();
+    stage.id
+}
+
+fn reserve(conn: &mut Client) -> Option<Uuid> {
+    let (_, result) =
+        db::do_reservation(conn, WALLET, &(Utc::now() + Duration::minutes(10)), 5, SALT);
+    result.ok().map(|(nft_id, _)| nft_id)
+}
+
+/// (reserved_count, assigned_count)
+fn counts(conn: &mut Client, stage_id: Uuid) -> (i64, i64) {
+    let row = conn
+        .query_one(
+            "select reserved_count::bigint, assigned_count::bigint from wallet_whitelist where wallet_address=$1 and stage=$2",
+            &[&WALLET, &stage_id],
+        )
+        .unwrap();
+    (row.get(0), row.get(1))
+}
+
+fn tx_result(txhash: &str, success: bool) -> ReservationTxResultRequest {
+    ReservationTxResultRequest {
+        tx: txhash.into(),
+        wallet_address: Some(WALLET.into()),
+        assigned_on: Some(Utc::now()),
+        token_id: success.then(|| "whitelist-token".to_string()),
+        success,
+        error: if success {
+            None
+        } else {
+            Some("11/sdk-out of gas".into())
+        },
+    }
+}
+
+#[test]
+fn errored_nft_keeps_its_allocation_until_resubmitted() {
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    let stage_id = whitelist_stage(&mut conn, 1);
+
+    let nft = reserve(&mut conn).expect("first reservation");
+    assert_eq!(counts(&mut conn, stage_id), (1, 0));
+
+    assert_eq!(
+        db::set_tx_hash_for_nft(&mut conn, &nft, "HASH1").unwrap(),
+        1
+    );
+    assert_eq!(
+        db::nft_assign_tx_result(&mut conn, tx_result("HASH1", false), &policy()).unwrap(),
+        1
+    );
+    assert_eq!(
+        counts(&mut conn, stage_id),
+        (1, 0),
+        "the errored NFT is still the wallet's"
+    );
+    assert!(
+        reserve(&mut conn).is_none(),
+        "the wallet reserved past its allocation while its NFT was errored"
+    );
+    assert!(db::reconcile_whitelist(&mut conn, stage_id)
+        .unwrap()
+        .is_empty());
+
+    assert_eq!(
+        db::set_tx_hash_for_nft(&mut conn, &nft, "HASH2").unwrap(),
+        1
+    );
+    assert_eq!(counts(&mut conn, stage_id), (1, 0));
+    assert_eq!(
+        db::nft_assign_tx_result(&mut conn, tx_result("HASH2", true), &policy()).unwrap(),
+        1
+    );
+    assert_eq!(counts(&mut conn, stage_id), (0, 1));
+    assert!(db::reconcile_whitelist(&mut conn, stage_id)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn releasing_an_errored_nft_hands_the_allocation_back() {
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    let stage_id = whitelist_stage(&mut conn, 1);
+
+    let nft = reserve(&mut conn).expect("first reservation");
+    db::set_tx_hash_for_nft(&mut conn, &nft, "HASH1").unwrap();
+    db::nft_assign_tx_result(&mut conn, tx_result("HASH1", false), &policy()).unwrap();
+    assert_eq!(db::release_failed_nft(&mut conn, &nft).unwrap(), 1);
+    assert_eq!(counts(&mut conn, stage_id), (0, 0));
+
+    assert!(reserve(&mut conn).is_some());
+    assert_eq!(counts(&mut conn, stage_id), (1, 0));
+    assert!(db::reconcile_whitelist(&mut conn, stage_id)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn reconcile_corrects_drifted_counts() {
+    let db = match TestDb::create() {
+        Some(db) => db,
+        None => return,
+    };
+    let mut conn = db.connect();
+    let stage_id = whitelist_stage(&mut conn, 2);
+    let minted = reserve(&mut conn).expect("first reservation");
+    reserve(&mut conn).expect("second reservation");
+    db::set_tx_hash_for_nft(&mut conn, &minted, "HASH1").unwrap();
+    db::nft_assign_tx_result(&mut conn, tx_result("HASH1", true), &policy()).unwrap();
+    assert_eq!(counts(&mut conn, stage_id), (1, 1));
+
+    conn.execute(
+        "update wallet_whitelist set reserved_count = 0, assigned_count = 2 where stage = $1",
+        &[&stage_id],
+    )
+    .unwrap();
+    let corrections = db::reconcile_whitelist(&mut conn, stage_id).unwrap();
+    assert_eq!(corrections.len(), 1);
+    assert_eq!(corrections[0].reserved_count_was, 0);
+    assert_eq!(corrections[0].reserved_count, 1);
+    assert_eq!(corrections[0].assigned_count_was, 2);
+    assert_eq!(corrections[0].assigned_count, 1);
+    assert_eq!(counts(&mut conn, stage_id), (1, 1));
+}